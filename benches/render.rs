@@ -0,0 +1,75 @@
+use std::{collections::HashMap, fs, hint::black_box};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use prometheus_apcupsd_exporter::{parse_metric, render_metrics, MetricParseType, RenderOptions};
+
+fn load_example(path: &str) -> HashMap<String, String> {
+	fs::read_to_string(path)
+		.expect("example status file should be readable")
+		.lines()
+		.filter_map(|line| line.split_once(':'))
+		.map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+		.collect()
+}
+
+fn bench_parse_metric(c: &mut Criterion) {
+	let cases = [
+		("timestamp", "2024-09-18 13:31:52 -0500", MetricParseType::Timestamp),
+		("historic_timestamp", "Mon Sep 18 09:34:14 +0200 2000", MetricParseType::Timestamp),
+		("date", "2024-03-21", MetricParseType::Date),
+		("duration", "41.1 Minutes", MetricParseType::Duration),
+		("percentage", "16.0 Percent", MetricParseType::Percentage),
+		("voltage", "122.0 Volts", MetricParseType::Voltage),
+	];
+	let mut group = c.benchmark_group("parse_metric");
+	for (name, value, parse_type) in cases {
+		group.bench_function(name, |b| {
+			b.iter_batched(
+				|| value.to_string(),
+				|value| parse_metric(black_box(value), parse_type.into()),
+				BatchSize::SmallInput,
+			)
+		});
+	}
+	group.finish();
+}
+
+fn bench_render_metrics(c: &mut Criterion) {
+	let render_options = RenderOptions::default();
+	let mut group = c.benchmark_group("render_metrics");
+	for example in [
+		"tests/apcupsd_examples/PowerStack450.status",
+		"tests/user_examples/Back-UPS-XS-1500M_1.status",
+	] {
+		let data = load_example(example);
+		group.bench_function(example, |b| {
+			b.iter_batched(
+				|| data.clone(),
+				|data| render_metrics(black_box(data), "ups0".to_string(), &render_options),
+				BatchSize::SmallInput,
+			)
+		});
+	}
+	group.finish();
+}
+
+fn bench_render_many_hosts(c: &mut Criterion) {
+	let render_options = RenderOptions::default();
+	let data = load_example("tests/apcupsd_examples/PowerStack450.status");
+	c.bench_function("render_50_hosts", |b| {
+		b.iter_batched(
+			|| vec![data.clone(); 50],
+			|hosts| {
+				hosts
+					.into_iter()
+					.enumerate()
+					.map(|(host_index, data)| render_metrics(black_box(data), format!("apcupsd{host_index}"), &render_options))
+					.collect::<Result<String, _>>()
+			},
+			BatchSize::SmallInput,
+		)
+	});
+}
+
+criterion_group!(benches, bench_parse_metric, bench_render_metrics, bench_render_many_hosts);
+criterion_main!(benches);
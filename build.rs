@@ -0,0 +1,23 @@
+use std::{env, fs, path::Path};
+
+/// Packages that would link OpenSSL or the platform's TLS library.
+const NON_RUSTLS_PACKAGES: [&str; 3] = ["native-tls", "openssl", "openssl-sys"];
+
+fn main() {
+	built::write_built_file().expect("Failed to acquire build-time information");
+	if env::var_os("CARGO_FEATURE_RUSTLS_ONLY").is_some() {
+		check_rustls_only();
+	}
+}
+
+/// Fail the build if anything in the lock file uses a TLS implementation other than rustls, which a static musl binary can't link.
+fn check_rustls_only() {
+	let lock_file = Path::new(&env::var("CARGO_MANIFEST_DIR").expect("Cargo sets CARGO_MANIFEST_DIR")).join("Cargo.lock");
+	println!("cargo:rerun-if-changed={}", lock_file.display());
+	let lock = fs::read_to_string(&lock_file).expect("Failed to read Cargo.lock");
+	for package in NON_RUSTLS_PACKAGES {
+		if lock.lines().any(|line| line == format!("name = \"{package}\"")) {
+			panic!("the rustls-only feature is enabled, but {package} is a dependency");
+		}
+	}
+}
@@ -0,0 +1,11 @@
+#![no_main]
+
+use std::collections::HashMap;
+
+use libfuzzer_sys::fuzz_target;
+use prometheus_apcupsd_exporter::{render_metrics, RenderOptions};
+
+fuzz_target!(|hex: String| {
+	let apcupsd_data = ["STATFLAG", "DIPSW", "REG1", "REG2", "REG3"].into_iter().map(|key| (key.to_string(), hex.clone())).collect::<HashMap<_, _>>();
+	let _ = render_metrics(apcupsd_data, "ups0".to_string(), &RenderOptions::default());
+});
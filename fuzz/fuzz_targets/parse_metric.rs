@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use prometheus_apcupsd_exporter::{parse_metric, MetricParseType};
+
+const PARSE_TYPES: [MetricParseType; 11] = [
+	MetricParseType::Timestamp,
+	MetricParseType::Date,
+	MetricParseType::Duration,
+	MetricParseType::Percentage,
+	MetricParseType::Voltage,
+	MetricParseType::Temperature,
+	MetricParseType::Frequency,
+	MetricParseType::Current,
+	MetricParseType::Count,
+	MetricParseType::Power,
+	MetricParseType::ApparentPower,
+];
+
+fuzz_target!(|input: (u8, String)| {
+	let (parse_type, value) = input;
+	let _ = parse_metric(value, PARSE_TYPES[usize::from(parse_type) % PARSE_TYPES.len()].into());
+});
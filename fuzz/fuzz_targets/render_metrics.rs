@@ -0,0 +1,10 @@
+#![no_main]
+
+use std::collections::HashMap;
+
+use libfuzzer_sys::fuzz_target;
+use prometheus_apcupsd_exporter::{render_metrics, RenderOptions};
+
+fuzz_target!(|apcupsd_data: HashMap<String, String>| {
+	let _ = render_metrics(apcupsd_data, "ups0".to_string(), &RenderOptions::default());
+});
@@ -0,0 +1,329 @@
+//! A separate HTTP listener for endpoints other than `/metrics`, so they can be kept off the port exposed to Prometheus.
+
+use std::{
+	collections::HashMap,
+	net::SocketAddr,
+	sync::{Arc, PoisonError},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use chrono::DateTime;
+use http_body_util::Full;
+use hyper::{
+	body::{Bytes, Incoming},
+	header::{HeaderValue, CONTENT_DISPOSITION, CONTENT_TYPE, HOST},
+	HeaderMap, Method, Request, Response, StatusCode,
+};
+use serde_json::json;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+	history::History,
+	history_store, query_parameter,
+	server::{self, text_response, HttpServerOptions},
+	status_page,
+	tls::TlsOptions,
+	ups_commands::{UpsCommandError, UpsCommandOptions},
+	ReloadRequest, SharedExporter,
+};
+
+/// apcupsd keys exported as columns by `/export.csv` unless others are requested.
+const EXPORT_KEYS: [&str; 7] = ["LINEV", "OUTPUTV", "LINEFREQ", "LOADPCT", "BCHARGE", "BATTV", "TIMELEFT"];
+
+/// Everything the admin endpoints serve.
+pub struct AdminState {
+	/// Password required with HTTP basic authentication, `admin_authorization`'s or else the same as for `/metrics`.
+	pub password: Option<String>,
+	/// The hosts and effective configuration, which change on reload.
+	pub exporter: SharedExporter,
+	/// Address `/metrics` is served on.
+	pub metrics_address: SocketAddr,
+	/// Path `/metrics` is served at.
+	pub metrics_path: String,
+	/// Whether both listeners are served over TLS.
+	pub tls: bool,
+	/// Actions that can be triggered with `/command`, if enabled.
+	pub ups_commands: Option<UpsCommandOptions>,
+	/// Where `/-/reload` requests a reload of the configuration, if enabled with `--web.enable-lifecycle`.
+	pub reload_requests: Option<mpsc::Sender<ReloadRequest>>,
+	/// Where `/-/quit` requests shutting down, if enabled with `--web.enable-lifecycle`.
+	pub quit_requests: Option<mpsc::Sender<()>>,
+}
+
+/// Serve the admin endpoints on `address` in the background, with the same TLS options as `/metrics`.
+pub async fn spawn(
+	address: SocketAddr,
+	tls_options: Option<&TlsOptions>,
+	http_options: &HttpServerOptions,
+	state: AdminState,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let state = Arc::new(state);
+	server::spawn("Admin server", address, tls_options, http_options, move |request| {
+		let state = state.clone();
+		async move { handle(request, &state).await }
+	})
+	.await?;
+	Ok(())
+}
+
+async fn handle(request: Request<Incoming>, state: &AdminState) -> Response<Full<Bytes>> {
+	// Liveness probes usually can't authenticate, and there's nothing to hide in the answer.
+	if request.uri().path() == "/healthz" {
+		return text_response(StatusCode::OK, "ok\n".to_string());
+	}
+	// Actions have their own password, checked instead of the one for reading metrics.
+	if request.uri().path() == "/command" {
+		return command(&request, state).await;
+	}
+	if let Some(password) = &state.password {
+		if !server::authorized(request.headers(), password) {
+			return server::unauthorized();
+		}
+	}
+	match request.uri().path() {
+		"/" => {
+			let mut response = text_response(StatusCode::OK, status_page::render(&histories(state)));
+			response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+			response
+		},
+		"/-/reload" if request.method() == Method::POST => reload(state).await,
+		"/-/reload" => text_response(StatusCode::METHOD_NOT_ALLOWED, "use POST\n".to_string()),
+		"/-/quit" if request.method() == Method::POST => quit(state).await,
+		"/-/quit" => text_response(StatusCode::METHOD_NOT_ALLOWED, "use POST\n".to_string()),
+		"/config" => text_response(StatusCode::OK, state.exporter.current().effective_config.clone()),
+		"/debug" => text_response(StatusCode::OK, debug(state).await),
+		"/history" => history(request.uri().query(), state).await,
+		"/export.csv" => export_csv(request.uri().query(), state).await,
+		"/http_sd" => http_sd(request.headers(), state),
+		_ => text_response(StatusCode::NOT_FOUND, "not found\n".to_string()),
+	}
+}
+
+/// Recent polls of each host by slug, for hosts that keep them.
+fn histories(state: &AdminState) -> HashMap<String, Arc<std::sync::Mutex<History>>> {
+	state.exporter.current().hosts.iter().filter_map(|host| Some((host.slug.clone(), host.history.clone()?))).collect()
+}
+
+/// `POST /-/reload`: reload the configuration like SIGHUP does, answering once it's done, if enabled.
+async fn reload(state: &AdminState) -> Response<Full<Bytes>> {
+	let Some(reload_requests) = &state.reload_requests else {
+		return text_response(
+			StatusCode::FORBIDDEN,
+			"lifecycle endpoints aren't enabled, see --web.enable-lifecycle\n".to_string(),
+		);
+	};
+	let (respond, result) = oneshot::channel();
+	if reload_requests.send(respond).await.is_err() {
+		return text_response(StatusCode::SERVICE_UNAVAILABLE, "shutting down\n".to_string());
+	}
+	match result.await {
+		Ok(Ok(())) => text_response(StatusCode::OK, "reloaded\n".to_string()),
+		Ok(Err(e)) => text_response(StatusCode::INTERNAL_SERVER_ERROR, format!("failed to reload configuration: {e}\n")),
+		Err(_) => text_response(StatusCode::SERVICE_UNAVAILABLE, "shutting down\n".to_string()),
+	}
+}
+
+/// `POST /-/quit`: shut down, if enabled.
+async fn quit(state: &AdminState) -> Response<Full<Bytes>> {
+	let Some(quit_requests) = &state.quit_requests else {
+		return text_response(
+			StatusCode::FORBIDDEN,
+			"lifecycle endpoints aren't enabled, see --web.enable-lifecycle\n".to_string(),
+		);
+	};
+	// A full channel means shutting down was already requested.
+	let _ = quit_requests.try_send(());
+	text_response(StatusCode::OK, "shutting down\n".to_string())
+}
+
+/// `/history?target=<slug>&minutes=<minutes>`: the recent polls of a host as JSON, all of them if `minutes` isn't given. With `from`
+/// and/or `to` (Unix timestamps, RFC 3339 times, or dates), or if the host doesn't keep recent polls in memory, they're read from the
+/// history store instead.
+async fn history(query: Option<&str>, state: &AdminState) -> Response<Full<Bytes>> {
+	let Some(target) = query_parameter(query, "target") else {
+		return text_response(StatusCode::BAD_REQUEST, "missing target\n".to_string());
+	};
+	let since = match query_parameter(query, "minutes").map(|minutes| minutes.parse::<f64>()) {
+		Some(Ok(minutes)) => SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64() - minutes * 60.,
+		Some(Err(_)) => return text_response(StatusCode::BAD_REQUEST, "invalid minutes\n".to_string()),
+		None => f64::NEG_INFINITY,
+	};
+	let json = match histories(state).remove(&target) {
+		Some(history) if query_parameter(query, "from").is_none() && query_parameter(query, "to").is_none() => {
+			history.lock().unwrap_or_else(PoisonError::into_inner).to_json(since)
+		},
+		_ => {
+			let samples = async {
+				let from = time_parameter(query, "from", since)?;
+				let to = time_parameter(query, "to", f64::INFINITY)?;
+				stored_samples(state, &target, from, to).await
+			};
+			match samples.await {
+				Ok(samples) => history_store::samples_to_json(&samples),
+				Err((status, message)) => return text_response(status, message),
+			}
+		},
+	};
+	let mut response = text_response(StatusCode::OK, json.to_string());
+	response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+	response
+}
+
+/// `/export.csv?target=<slug>&from=<time>&to=<time>&keys=<keys>`: the polls of a host recorded in the history store as CSV for
+/// spreadsheets, with a row for each poll and a column for each of `keys` (comma separated, [`EXPORT_KEYS`] by default).
+async fn export_csv(query: Option<&str>, state: &AdminState) -> Response<Full<Bytes>> {
+	let Some(target) = query_parameter(query, "target") else {
+		return text_response(StatusCode::BAD_REQUEST, "missing target\n".to_string());
+	};
+	let keys: Vec<String> = match query_parameter(query, "keys") {
+		Some(keys) => keys.split(',').map(str::to_string).collect(),
+		None => EXPORT_KEYS.map(str::to_string).to_vec(),
+	};
+	let samples = async {
+		let from = time_parameter(query, "from", f64::NEG_INFINITY)?;
+		let to = time_parameter(query, "to", f64::INFINITY)?;
+		stored_samples(state, &target, from, to).await
+	};
+	let samples = match samples.await {
+		Ok(samples) => samples,
+		Err((status, message)) => return text_response(status, message),
+	};
+	let mut csv = format!("time (UTC),{}\r\n", keys.join(","));
+	for row in samples.chunk_by(|(a, _, _), (b, _, _)| a == b) {
+		let time = DateTime::from_timestamp_millis((row[0].0 * 1000.) as i64).unwrap_or_default();
+		csv += &time.format("%Y-%m-%d %H:%M:%S").to_string();
+		for key in &keys {
+			csv.push(',');
+			if let Some((_, _, value)) = row.iter().find(|(_, sample_key, _)| sample_key == key) {
+				csv += &value.to_string();
+			}
+		}
+		csv += "\r\n";
+	}
+	let mut response = text_response(StatusCode::OK, csv);
+	response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/csv; charset=utf-8"));
+	if let Ok(disposition) = HeaderValue::from_str(&format!("attachment; filename=\"{target}.csv\"")) {
+		response.headers_mut().insert(CONTENT_DISPOSITION, disposition);
+	}
+	response
+}
+
+/// The time in query parameter `name` as a Unix timestamp, or `default` if it isn't given.
+fn time_parameter(query: Option<&str>, name: &str, default: f64) -> Result<f64, (StatusCode, String)> {
+	match query_parameter(query, name) {
+		Some(time) => history_store::parse_time(&time).ok_or_else(|| (StatusCode::BAD_REQUEST, format!("invalid {name}\n"))),
+		None => Ok(default),
+	}
+}
+
+/// The samples of the host with `slug` from `from` to `to` in the history store, or the status and message to respond with if there are
+/// none to read.
+async fn stored_samples(state: &AdminState, slug: &str, from: f64, to: f64) -> Result<Vec<(f64, String, f64)>, (StatusCode, String)> {
+	let exporter = state.exporter.current();
+	let Some(history_store) = exporter.host_defaults.history_store.clone() else {
+		return Err((StatusCode::NOT_FOUND, format!("no history for target \"{slug}\"\n")));
+	};
+	if !exporter.hosts.iter().any(|host| host.slug == slug) {
+		return Err((StatusCode::NOT_FOUND, format!("no target \"{slug}\"\n")));
+	}
+	history_store
+		.samples(slug.to_string(), from, to)
+		.await
+		.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("failed to read the history store: {e}\n")))
+}
+
+/// `POST /command?target=<slug>&action=<action>`: run a configured action on a host, returning its output.
+async fn command(request: &Request<Incoming>, state: &AdminState) -> Response<Full<Bytes>> {
+	let Some(options) = &state.ups_commands else {
+		return text_response(StatusCode::NOT_FOUND, "not found\n".to_string());
+	};
+	if options.password.is_empty() || !server::authorized(request.headers(), &options.password) {
+		return server::unauthorized();
+	}
+	if request.method() != Method::POST {
+		return text_response(StatusCode::METHOD_NOT_ALLOWED, "use POST\n".to_string());
+	}
+	let query = request.uri().query();
+	let (Some(target), Some(action)) = (query_parameter(query, "target"), query_parameter(query, "action")) else {
+		return text_response(StatusCode::BAD_REQUEST, "missing target or action\n".to_string());
+	};
+	let exporter = state.exporter.current();
+	let Some(host) = exporter.hosts.iter().find(|host| host.slug == target) else {
+		return text_response(StatusCode::NOT_FOUND, format!("no target \"{target}\"\n"));
+	};
+	eprintln!("Running action \"{action}\" on {target}");
+	match options.run(&action, &target, &host.options.address, host.options.port).await {
+		Ok(output) => text_response(StatusCode::OK, output),
+		Err(e) => {
+			eprintln!("Action \"{action}\" on {target} failed: {e}");
+			let status = match &e {
+				UpsCommandError::UnknownAction(_) => StatusCode::NOT_FOUND,
+				UpsCommandError::HostNotAllowed(_) => StatusCode::FORBIDDEN,
+				UpsCommandError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+				UpsCommandError::EmptyCommand(_) | UpsCommandError::Io(_) | UpsCommandError::Failed { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+			};
+			text_response(status, format!("{e}\n"))
+		},
+	}
+}
+
+/// `/debug`: the most recently fetched data of each host as reported by apcupsd, with where and when it was fetched, without fetching.
+async fn debug(state: &AdminState) -> String {
+	let mut debug = String::new();
+	for host in &state.exporter.current().hosts {
+		debug += &format!("[{}]\n", host.slug);
+		match host.access.peek().await {
+			Ok(snapshot) => {
+				debug += &format!("endpoint: {}\n", snapshot.endpoint);
+				if let Some(port) = snapshot.probed_port {
+					debug += &format!("probed port: {port}\n");
+				}
+				if let Some(age) = snapshot.last_success.and_then(|last_success| last_success.elapsed().ok()) {
+					debug += &format!("last success: {:.1}s ago\n", age.as_secs_f64());
+				}
+				debug += &format!("fetch duration: {:.3}s\n", snapshot.fetch_duration.as_secs_f64());
+				let mut data: Vec<_> = snapshot.data.into_iter().collect();
+				data.sort();
+				for (key, value) in data {
+					debug += &format!("{key:<9}: {value}\n");
+				}
+			},
+			Err(e) => debug += &format!("last fetch failed: {e}\n"),
+		}
+		debug.push('\n');
+	}
+	debug
+}
+
+/// `/http_sd`: a target for each host in Prometheus' HTTP service discovery format, scraping `/metrics?target=<slug>` at the configured
+/// metrics path. If `/metrics` is served on all interfaces, the targets use the host name this endpoint was requested with.
+fn http_sd(headers: &HeaderMap, state: &AdminState) -> Response<Full<Bytes>> {
+	let metrics_address = match headers.get(HOST).and_then(|host| host.to_str().ok()) {
+		Some(host) if state.metrics_address.ip().is_unspecified() => {
+			let host_name = host.rsplit_once(':').filter(|(_, port)| port.parse::<u16>().is_ok()).map_or(host, |(host_name, _)| host_name);
+			format!("{host_name}:{}", state.metrics_address.port())
+		},
+		_ => state.metrics_address.to_string(),
+	};
+	let target_groups: Vec<_> = state
+		.exporter
+		.current()
+		.hosts
+		.iter()
+		.map(|host| {
+			json!({
+				"targets": [metrics_address],
+				"labels": {
+					"__metrics_path__": state.metrics_path,
+					"__scheme__": if state.tls { "https" } else { "http" },
+					"__param_target": host.slug,
+					"apcupsd_target": host.slug,
+					"apcupsd_nis_address": format!("{}:{}", host.options.address, host.options.port),
+				},
+			})
+		})
+		.collect();
+	let mut response = text_response(StatusCode::OK, serde_json::Value::from(target_groups).to_string());
+	response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+	response
+}
@@ -1,4 +1,5 @@
-// Adapted from apcupsd defines.h
+// Adapted from apcupsd defines.h. `status` is its full list of STATFLAG bits; any other bit a build sets (such as 0x02000000 for
+// battery charging) is only exported in the raw apcupsd_status_flag_raw metric.
 
 pub(crate) mod status {
 	/* bit values for APC UPS Status Byte (ups->Status) */
@@ -0,0 +1,33 @@
+//! Discovering the NIS server of a local apcupsd from its configuration file, for zero-config single UPS setups.
+
+use std::{fs, io, path::Path};
+
+use crate::HostSpecificOptions;
+
+/// Build host options from the `NISIP`, `NISPORT`, and `UPSNAME` directives of an apcupsd.conf.
+pub fn discover(path: &Path) -> io::Result<HostSpecificOptions> {
+	let mut options = HostSpecificOptions::default();
+	for line in fs::read_to_string(path)?.lines() {
+		let line = line.trim();
+		if line.starts_with('#') {
+			continue;
+		}
+		let Some((directive, value)) = line.split_once(char::is_whitespace) else {
+			continue;
+		};
+		let value = value.trim();
+		match directive {
+			// apcupsd listens on all interfaces for 0.0.0.0, which isn't a connectable address.
+			"NISIP" if value == "0.0.0.0" => options.address = "127.0.0.1".to_string(),
+			"NISIP" => options.address = value.to_string(),
+			"NISPORT" => {
+				options.port = value
+					.parse()
+					.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("invalid NISPORT \"{value}\" in {}", path.display())))?
+			},
+			"UPSNAME" if !value.is_empty() => options.slug = Some(value.to_string()),
+			_ => {},
+		}
+	}
+	Ok(options)
+}
@@ -0,0 +1,46 @@
+//! Periodically uploading gzipped JSON snapshots of the data of every host to S3 compatible object storage, for records of power events
+//! kept independently of how long Prometheus retains metrics.
+
+#[cfg(feature = "archive")]
+mod upload;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "archive")]
+pub use upload::spawn;
+
+#[derive(Clone, Deserialize, JsonSchema, Serialize)]
+#[serde(default)]
+pub struct ArchiveOptions {
+	/// URL of the bucket, path style (`https://s3.example.com/bucket`) or virtual-hosted style (`https://bucket.s3.example.com`).
+	pub bucket_url: String,
+	/// Region requests are signed for.
+	pub region: String,
+	pub access_key_id: String,
+	#[serde(serialize_with = "crate::serialize_redacted")]
+	pub secret_access_key: String,
+	/// Prefix of object keys, which continue with `YYYY/MM/DD/YYYYMMDDTHHMMSSZ.json.gz`.
+	pub prefix: String,
+	/// How often a snapshot is uploaded.
+	pub interval_seconds: f64,
+}
+
+impl Default for ArchiveOptions {
+	fn default() -> Self {
+		Self {
+			bucket_url: String::new(),
+			region: "us-east-1".to_string(),
+			access_key_id: String::new(),
+			secret_access_key: String::new(),
+			prefix: "apcupsd/".to_string(),
+			interval_seconds: 300.,
+		}
+	}
+}
+
+/// Archiving isn't available in builds without the `archive` feature.
+#[cfg(not(feature = "archive"))]
+pub fn spawn(_options: ArchiveOptions, _exporter: crate::SharedExporter) -> Result<(), Box<dyn std::error::Error>> {
+	Err(crate::feature_disabled("archive").into())
+}
@@ -0,0 +1,141 @@
+//! Signing and uploading snapshots with the S3 API.
+
+use std::{
+	io::Write,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Utc};
+use flate2::{write::GzEncoder, Compression};
+use hmac::{Hmac, Mac};
+use http_body_util::Full;
+use hyper::{
+	body::Bytes,
+	header::{AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE},
+	Request, Uri,
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::time::timeout;
+
+use super::ArchiveOptions;
+use crate::{
+	http_client::{self, HttpError},
+	SharedExporter,
+};
+
+/// How long an upload may take before it's given up on, so object storage that stops responding doesn't hold up later uploads.
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Upload a snapshot of every host every interval in the background.
+pub fn spawn(options: ArchiveOptions, exporter: SharedExporter) -> Result<(), Box<dyn std::error::Error>> {
+	let bucket_url: Uri = options.bucket_url.trim_end_matches('/').parse()?;
+	if !matches!(bucket_url.scheme_str(), Some("http" | "https")) {
+		return Err(format!("unsupported archive bucket_url \"{bucket_url}\", only http:// and https:// are supported").into());
+	}
+	let interval = Duration::try_from_secs_f64(options.interval_seconds)?;
+	tokio::spawn(async move {
+		loop {
+			let now = SystemTime::now();
+			let snapshot = snapshot_json(&exporter, now).await;
+			let key = format!(
+				"{}{}.json.gz",
+				options.prefix,
+				DateTime::<Utc>::from(now).format("%Y/%m/%d/%Y%m%dT%H%M%SZ")
+			);
+			if let Err(e) = upload(&options, &bucket_url, &key, &snapshot, now).await {
+				crate::syslog::error(format!("Failed to upload archive snapshot {key}: {e}"));
+			}
+			tokio::time::sleep(interval).await;
+		}
+	});
+	Ok(())
+}
+
+/// The most recent data of every host, fetching it if it's due.
+async fn snapshot_json(exporter: &SharedExporter, now: SystemTime) -> Value {
+	let exporter = exporter.current();
+	let mut hosts = serde_json::Map::new();
+	for host in &exporter.hosts {
+		let host_json = match host.access.refresh().await {
+			Ok(snapshot) => json!({
+				"endpoint": snapshot.endpoint,
+				"fetched_at": snapshot.last_success.and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map(|time| time.as_secs_f64()),
+				"stale": snapshot.stale,
+				"data": snapshot.data,
+			}),
+			Err(e) => json!({ "error": std::io::Error::from(e).to_string() }),
+		};
+		hosts.insert(host.slug.clone(), host_json);
+	}
+	json!({ "timestamp": now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64(), "hosts": hosts })
+}
+
+/// Put `snapshot` gzipped at `key`, signed with AWS Signature Version 4.
+async fn upload(options: &ArchiveOptions, bucket_url: &Uri, key: &str, snapshot: &Value, now: SystemTime) -> Result<(), HttpError> {
+	let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+	encoder.write_all(snapshot.to_string().as_bytes())?;
+	let body = encoder.finish()?;
+
+	let path = format!("{}/{}", bucket_url.path().trim_end_matches('/'), uri_encode(key));
+	let host = bucket_url.authority().ok_or("bucket_url has no host")?.as_str();
+	let now = DateTime::<Utc>::from(now);
+	let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+	let date = now.format("%Y%m%d").to_string();
+	let payload_hash = hex(&Sha256::digest(&body));
+	let signed_headers = "content-encoding;content-type;host;x-amz-content-sha256;x-amz-date";
+	let canonical_request = format!(
+		"PUT\n{path}\n\ncontent-encoding:gzip\ncontent-type:application/json\nhost:{host}\nx-amz-content-sha256:{payload_hash}\n\
+		 x-amz-date:{amz_date}\n\n{signed_headers}\n{payload_hash}"
+	);
+	let scope = format!("{date}/{}/s3/aws4_request", options.region);
+	let string_to_sign = format!(
+		"AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+		hex(&Sha256::digest(canonical_request.as_bytes()))
+	);
+	let signing_key = [date.as_str(), &options.region, "s3", "aws4_request"]
+		.into_iter()
+		.fold(format!("AWS4{}", options.secret_access_key).into_bytes(), |key, part| {
+			hmac_sha256(&key, part)
+		});
+	let signature = hex(&hmac_sha256(&signing_key, &string_to_sign));
+
+	let request = Request::put(format!("{}://{host}{path}", bucket_url.scheme_str().unwrap_or("https")))
+		.header(CONTENT_ENCODING, "gzip")
+		.header(CONTENT_TYPE, "application/json")
+		.header("x-amz-content-sha256", &payload_hash)
+		.header("x-amz-date", &amz_date)
+		.header(
+			AUTHORIZATION,
+			format!(
+				"AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+				options.access_key_id
+			),
+		)
+		.body(Full::new(Bytes::from(body)))?;
+	let (status, response) = timeout(UPLOAD_TIMEOUT, http_client::send(request)).await??;
+	if !status.is_success() {
+		return Err(format!("object storage responded with {status}: {}", String::from_utf8_lossy(&response)).into());
+	}
+	Ok(())
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+	let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+	mac.update(data.as_bytes());
+	mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Percent-encode an object key as required in the canonical request, keeping `/` between its segments.
+fn uri_encode(key: &str) -> String {
+	key.bytes()
+		.map(|byte| match byte {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => char::from(byte).to_string(),
+			_ => format!("%{byte:02X}"),
+		})
+		.collect()
+}
@@ -0,0 +1,64 @@
+//! Capturing raw status output from apcupsd, so users with unsupported UPS models can contribute test examples, and parsing it back
+//! to reproduce rendering issues.
+
+use std::{collections::HashMap, io, time::Duration};
+
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt},
+	net::TcpStream,
+	time::timeout,
+};
+
+/// Fetch status output from an apcupsd NIS server in the same order and format that `apcaccess status` prints it.
+pub async fn fetch_status(host: &str, port: u16) -> io::Result<String> {
+	let mut stream = timeout(Duration::from_secs(5), TcpStream::connect((host, port))).await??;
+	stream.write_u16(6).await?;
+	stream.write_all(b"status").await?;
+	let mut status = String::new();
+	loop {
+		let length = stream.read_u16().await?;
+		if length == 0 {
+			return Ok(status);
+		}
+		let mut message = vec![0; length.into()];
+		stream.read_exact(&mut message).await?;
+		status.push_str(&String::from_utf8_lossy(&message));
+	}
+}
+
+/// Parse saved status output into the key-value pairs apcupsd would report, warning about lines that aren't `KEY : value`.
+pub fn parse_status(status: &str) -> HashMap<String, String> {
+	let mut data = HashMap::new();
+	for (line_number, line) in status.lines().enumerate() {
+		match line.split_once(':') {
+			Some((key, value)) => {
+				data.insert(key.trim().to_string(), value.trim().to_string());
+			},
+			None if line.trim().is_empty() => {},
+			None => eprintln!("Ignoring line {} without a key: {line:?}", line_number + 1),
+		}
+	}
+	data
+}
+
+/// Replace serial numbers and hostnames with placeholders.
+pub fn anonymize(status: &str) -> String {
+	status
+		.lines()
+		.map(
+			|line| match line.split_once(':').and_then(|(key, value)| Some((key, placeholder(key.trim(), value.trim())?))) {
+				Some((key, placeholder)) => format!("{key}: {placeholder}\n"),
+				None => format!("{line}\n"),
+			},
+		)
+		.collect()
+}
+
+/// The placeholder for an identifying value, if `key` has one.
+fn placeholder(key: &str, value: &str) -> Option<String> {
+	match key {
+		"SERIALNO" => Some("X".repeat(value.len())),
+		"HOSTNAME" => Some("Test-Host".to_string()),
+		_ => None,
+	}
+}
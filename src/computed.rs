@@ -0,0 +1,230 @@
+//! Metrics computed from apcupsd keys with arithmetic expressions given in the configuration, for values specific to some hardware.
+
+use std::{collections::HashMap, iter::Peekable, str::Chars};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize};
+use thiserror::Error;
+
+#[derive(Clone, Deserialize, JsonSchema, Serialize)]
+pub struct ComputedMetric {
+	/// Name of the metric, which is prefixed with `apcupsd_`.
+	#[serde(deserialize_with = "deserialize_name")]
+	pub name: String,
+	/// Help text of the metric; default the expression.
+	#[serde(default)]
+	pub help: Option<String>,
+	/// Arithmetic expression (`+`, `-`, `*`, `/`, and parentheses) of numbers and apcupsd keys, e.g. `NOMPOWER * LOADPCT / 100`. Keys
+	/// stand for the number they're reported with, without converting units.
+	#[schemars(with = "String")]
+	pub expr: Expression,
+}
+
+/// Deserialize a metric name, rejecting names that aren't valid in the exposition format (`[a-zA-Z_:][a-zA-Z0-9_:]*`) at load instead
+/// of on every scrape.
+fn deserialize_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+	let name = String::deserialize(deserializer)?;
+	let mut chars = name.chars();
+	let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || matches!(c, '_' | ':'))
+		&& chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | ':'));
+	if !valid {
+		return Err(serde::de::Error::custom(format!(
+			"invalid metric name \"{name}\", which must match [a-zA-Z_:][a-zA-Z0-9_:]*"
+		)));
+	}
+	Ok(name)
+}
+
+/// A parsed arithmetic expression, which keeps its source to be serialized back.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Expression {
+	source: String,
+	node: Node,
+}
+
+#[derive(Clone)]
+enum Node {
+	Number(f64),
+	Key(String),
+	Negate(Box<Node>),
+	Binary(Box<Node>, char, Box<Node>),
+}
+
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct ExpressionError(String);
+
+impl TryFrom<String> for Expression {
+	type Error = ExpressionError;
+
+	fn try_from(source: String) -> Result<Self, Self::Error> {
+		let mut chars = source.chars().peekable();
+		let node = parse_sum(&mut chars)?;
+		skip_whitespace(&mut chars);
+		if let Some(c) = chars.next() {
+			return Err(ExpressionError(format!("unexpected '{c}' in expression \"{source}\"")));
+		}
+		Ok(Self { source, node })
+	}
+}
+
+impl From<Expression> for String {
+	fn from(expression: Expression) -> Self {
+		expression.source
+	}
+}
+
+impl Expression {
+	/// Evaluate the expression, or `None` if a key is missing or doesn't start with a number, or the result isn't finite.
+	pub fn evaluate(&self, apcupsd_data: &HashMap<String, String>) -> Option<f64> {
+		evaluate(&self.node, apcupsd_data).filter(|value| value.is_finite())
+	}
+
+	pub fn source(&self) -> &str {
+		&self.source
+	}
+}
+
+fn evaluate(node: &Node, apcupsd_data: &HashMap<String, String>) -> Option<f64> {
+	Some(match node {
+		Node::Number(number) => *number,
+		Node::Key(key) => apcupsd_data.get(key)?.split_whitespace().next()?.parse().ok()?,
+		Node::Negate(node) => -evaluate(node, apcupsd_data)?,
+		Node::Binary(left, operator, right) => {
+			let (left, right) = (evaluate(left, apcupsd_data)?, evaluate(right, apcupsd_data)?);
+			match operator {
+				'+' => left + right,
+				'-' => left - right,
+				'*' => left * right,
+				_ => left / right,
+			}
+		},
+	})
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+	while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn parse_sum(chars: &mut Peekable<Chars>) -> Result<Node, ExpressionError> {
+	let mut node = parse_product(chars)?;
+	loop {
+		skip_whitespace(chars);
+		match chars.next_if(|c| matches!(*c, '+' | '-')) {
+			Some(operator) => node = Node::Binary(Box::new(node), operator, Box::new(parse_product(chars)?)),
+			None => return Ok(node),
+		}
+	}
+}
+
+fn parse_product(chars: &mut Peekable<Chars>) -> Result<Node, ExpressionError> {
+	let mut node = parse_operand(chars)?;
+	loop {
+		skip_whitespace(chars);
+		match chars.next_if(|c| matches!(*c, '*' | '/')) {
+			Some(operator) => node = Node::Binary(Box::new(node), operator, Box::new(parse_operand(chars)?)),
+			None => return Ok(node),
+		}
+	}
+}
+
+fn parse_operand(chars: &mut Peekable<Chars>) -> Result<Node, ExpressionError> {
+	skip_whitespace(chars);
+	match chars.peek().copied() {
+		Some('-') => {
+			chars.next();
+			Ok(Node::Negate(Box::new(parse_operand(chars)?)))
+		},
+		Some('(') => {
+			chars.next();
+			let node = parse_sum(chars)?;
+			skip_whitespace(chars);
+			match chars.next() {
+				Some(')') => Ok(node),
+				_ => Err(ExpressionError("missing ')' in expression".to_string())),
+			}
+		},
+		Some(c) if c.is_ascii_digit() || c == '.' => {
+			let mut number = String::new();
+			while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+				number.push(c);
+			}
+			number.parse().map(Node::Number).map_err(|_| ExpressionError(format!("invalid number \"{number}\" in expression")))
+		},
+		Some(c) if c.is_ascii_alphabetic() => {
+			let mut key = String::new();
+			while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+				key.push(c);
+			}
+			Ok(Node::Key(key))
+		},
+		Some(c) => Err(ExpressionError(format!("unexpected '{c}' in expression"))),
+		None => Err(ExpressionError("unexpected end of expression".to_string())),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn evaluate(source: &str, apcupsd_data: &[(&str, &str)]) -> Option<f64> {
+		let apcupsd_data = apcupsd_data.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+		Expression::try_from(source.to_string()).unwrap().evaluate(&apcupsd_data)
+	}
+
+	#[test]
+	fn test_name_validation() {
+		let parse = |name: &str| serde_yaml::from_str::<ComputedMetric>(&format!("{{name: '{name}', expr: '1'}}"));
+		assert!(parse("load_watts").is_ok());
+		assert!(parse(":recording:rule_1").is_ok());
+		for invalid in ["", "1st", "load-watts", "load watts", "lōad"] {
+			assert!(parse(invalid).is_err(), "{invalid:?} was accepted");
+		}
+	}
+
+	#[test]
+	fn test_parse_errors() {
+		for invalid in ["", "1 +", "(1 + 2", "1 2", "1 % 2", "1..2"] {
+			assert!(Expression::try_from(invalid.to_string()).is_err(), "{invalid:?} was accepted");
+		}
+	}
+
+	#[test]
+	fn test_precedence() {
+		assert_eq!(evaluate("1 + 2 * 3", &[]), Some(7.));
+		assert_eq!(evaluate("(1 + 2) * 3", &[]), Some(9.));
+		assert_eq!(evaluate("8 / 4 / 2", &[]), Some(1.));
+		assert_eq!(evaluate("10 - 4 - 3", &[]), Some(3.));
+		assert_eq!(
+			evaluate("NOMPOWER * LOADPCT / 100", &[("NOMPOWER", "865 Watts"), ("LOADPCT", "20.0 Percent")]),
+			Some(173.)
+		);
+	}
+
+	#[test]
+	fn test_unary_minus() {
+		assert_eq!(evaluate("-2 * 3", &[]), Some(-6.));
+		assert_eq!(evaluate("1 - -2", &[]), Some(3.));
+		assert_eq!(evaluate("--2", &[]), Some(2.));
+		assert_eq!(evaluate("-(1 + 2)", &[]), Some(-3.));
+		assert_eq!(evaluate("-ITEMP", &[("ITEMP", "29.2 C")]), Some(-29.2));
+	}
+
+	#[test]
+	fn test_missing_and_invalid_values() {
+		assert_eq!(evaluate("LINEV + 1", &[]), None);
+		assert_eq!(evaluate("LINEV + 1", &[("LINEV", "N/A")]), None);
+		assert_eq!(evaluate("LINEV + 1", &[("LINEV", "")]), None);
+	}
+
+	#[test]
+	fn test_division_by_zero() {
+		assert_eq!(evaluate("1 / 0", &[]), None);
+		assert_eq!(evaluate("0 / 0", &[]), None);
+		assert_eq!(
+			evaluate("NOMPOWER / LOADPCT", &[("NOMPOWER", "865 Watts"), ("LOADPCT", "0.0 Percent")]),
+			None
+		);
+	}
+}
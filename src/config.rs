@@ -0,0 +1,461 @@
+//! Loading the configuration file along with the fragments it includes, and resolving secrets kept outside of it.
+
+use std::{
+	env, fmt, fs,
+	path::{Path, PathBuf},
+};
+
+use serde::de::DeserializeOwned;
+use serde_yaml::{
+	value::{Tag, TaggedValue},
+	Value,
+};
+use thiserror::Error;
+
+/// Keys whose value can instead be read from a file given by the key with a `_file` suffix, with how to wrap the file's contents.
+const SECRET_FILE_KEYS: [(&str, Option<&str>); 5] = [
+	("authorization", Some("Basic")),
+	("admin_authorization", Some("Basic")),
+	("hash_salt", None),
+	("basic", None),
+	("password", None),
+];
+
+/// Every problem found in the configuration, reported together so they can all be fixed at once.
+#[derive(Error, Debug)]
+#[error("invalid configuration:{}", .problems.iter().map(|problem| format!("\n  {problem}")).collect::<String>())]
+pub struct ConfigError {
+	pub problems: Vec<ConfigProblem>,
+}
+
+impl ConfigError {
+	fn single(location: Option<String>, message: impl ToString) -> Self {
+		Self {
+			problems: vec![ConfigProblem {
+				location,
+				path: None,
+				message: message.to_string(),
+			}],
+		}
+	}
+}
+
+impl From<String> for ConfigError {
+	fn from(message: String) -> Self {
+		Self::single(None, message)
+	}
+}
+
+#[derive(Debug)]
+pub struct ConfigProblem {
+	/// `file:line` or `file:line:column` the problem is at, if known. Problems with values are reported at the line of their top level key.
+	pub location: Option<String>,
+	/// Path of the key with the problem, e.g. `hosts[1].port`.
+	pub path: Option<String>,
+	pub message: String,
+}
+
+impl fmt::Display for ConfigProblem {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for prefix in [&self.location, &self.path].into_iter().flatten() {
+			write!(f, "{prefix}: ")?;
+		}
+		f.write_str(&self.message)
+	}
+}
+
+/// A file the configuration was loaded from, with its contents, for locating problems.
+pub struct Source {
+	path: PathBuf,
+	contents: String,
+}
+
+/// Load the YAML configuration at `path` and merge in the files listed in its `include` key, in order. Directories include every
+/// `.yaml` or `.yml` file in them, sorted by name. Relative paths are relative to the directory of `path`. Also returns the files it was
+/// loaded from.
+pub fn load(path: &Path) -> Result<(Value, Vec<Source>), ConfigError> {
+	let mut sources = Vec::new();
+	let mut config = read_yaml(path, &mut sources)?;
+	let includes: Vec<PathBuf> = match config.get("include") {
+		Some(include) => serde_yaml::from_value(include.clone()).map_err(|e| locate(&sources, "include", e))?,
+		None => Vec::new(),
+	};
+	let base = path.parent().unwrap_or(Path::new(""));
+	for include in includes {
+		let include = base.join(include);
+		let files = if include.is_dir() {
+			let mut files = Vec::new();
+			for entry in fs::read_dir(&include).map_err(|e| format!("{}: {e}", include.display()))? {
+				let file = entry.map_err(|e| format!("{}: {e}", include.display()))?.path();
+				if file.extension().is_some_and(|extension| extension == "yaml" || extension == "yml") {
+					files.push(file);
+				}
+			}
+			files.sort();
+			files
+		} else {
+			vec![include]
+		};
+		for file in files {
+			let fragment = read_yaml(&file, &mut sources)?;
+			if fragment.get("include").is_some() {
+				return Err(format!("{}: included files can't include other files", file.display()).into());
+			}
+			merge(&mut config, fragment);
+		}
+	}
+	resolve_secrets(&mut config).map_err(|e| e.to_string())?;
+	Ok((config, sources))
+}
+
+fn read_yaml(path: &Path, sources: &mut Vec<Source>) -> Result<Value, ConfigError> {
+	let contents = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+	let value = serde_yaml::from_str(&contents).map_err(|e| {
+		let location = match e.location() {
+			Some(location) => format!("{}:{}:{}", path.display(), location.line(), location.column()),
+			None => path.display().to_string(),
+		};
+		ConfigError::single(Some(location), e)
+	})?;
+	sources.push(Source {
+		path: path.to_path_buf(),
+		contents,
+	});
+	Ok(value)
+}
+
+/// Deserialize a loaded configuration, reporting every invalid value instead of just the first, and unknown keys too if its `strict` key
+/// is set. Also returns the unknown keys.
+pub fn deserialize<T: DeserializeOwned>(config: Value, sources: &[Source]) -> Result<(T, Vec<String>), ConfigError> {
+	let strict = config.get("strict").and_then(Value::as_bool).unwrap_or(false);
+	let mut unknown_keys = Vec::new();
+	let mut problems = Vec::new();
+	let mut first_error = None;
+	let options = match deserialize_tracked(config.clone(), &mut unknown_keys) {
+		Ok(options) => Some(options),
+		Err(e) => {
+			first_error = Some(e);
+			// Deserialization stops at the first problem, so try each top level key (and each item of lists like `hosts`) on its own to
+			// find the rest, and the unknown keys after it.
+			unknown_keys.clear();
+			for (key, value) in config.as_mapping().into_iter().flatten() {
+				let Some(key) = key.as_str() else {
+					continue;
+				};
+				let parts = match value {
+					Value::Sequence(items) => {
+						items.iter().enumerate().map(|(index, item)| (Some(index), Value::Sequence(vec![item.clone()]))).collect()
+					},
+					value => vec![(None, value.clone())],
+				};
+				for (index, value) in parts {
+					let part = Value::Mapping([(Value::String(key.to_string()), value)].into_iter().collect());
+					// Paths within the part start at index 0 of the list.
+					let locate_in_list = |path: String| match index {
+						Some(index) => path.replacen(&format!("{key}[0]"), &format!("{key}[{index}]"), 1),
+						None => path,
+					};
+					let mut part_unknown_keys = Vec::new();
+					let result = deserialize_tracked::<T>(part, &mut part_unknown_keys);
+					unknown_keys.extend(part_unknown_keys.into_iter().map(locate_in_list));
+					if let Err(e) = result {
+						let path = locate_in_list(e.path().to_string());
+						problems.push(ConfigProblem {
+							location: top_level_key_location(sources, key),
+							path: Some(path),
+							message: e.into_inner().to_string(),
+						});
+					}
+				}
+			}
+			None
+		},
+	};
+	if strict {
+		for path in &unknown_keys {
+			let key = path.split(['.', '[']).next().unwrap_or_default();
+			problems.push(ConfigProblem {
+				location: top_level_key_location(sources, key),
+				path: Some(path.clone()),
+				message: "unknown key".to_string(),
+			});
+		}
+	}
+	match (options, first_error) {
+		(Some(options), _) if problems.is_empty() => Ok((options, unknown_keys)),
+		// A problem that only shows up when every key is deserialized together.
+		(_, Some(e)) if problems.is_empty() => Err(ConfigError {
+			problems: vec![ConfigProblem {
+				location: None,
+				path: Some(e.path().to_string()),
+				message: e.into_inner().to_string(),
+			}],
+		}),
+		_ => Err(ConfigError { problems }),
+	}
+}
+
+/// Deserialize `config`, recording the paths of keys that were ignored and the path of the value that failed, if any.
+fn deserialize_tracked<T: DeserializeOwned>(
+	config: Value,
+	unknown_keys: &mut Vec<String>,
+) -> Result<T, serde_path_to_error::Error<serde_yaml::Error>> {
+	let mut record_unknown_key = |path: serde_ignored::Path| unknown_keys.push(ignored_key_path(&path));
+	serde_path_to_error::deserialize(serde_ignored::Deserializer::new(config, &mut record_unknown_key))
+}
+
+/// The path of an ignored key in the format of `serde_path_to_error`, e.g. `hosts[1].port`, so unknown keys and invalid values are reported
+/// alike.
+fn ignored_key_path(path: &serde_ignored::Path) -> String {
+	match path {
+		serde_ignored::Path::Root => String::new(),
+		serde_ignored::Path::Seq { parent, index } => format!("{}[{index}]", ignored_key_path(parent)),
+		serde_ignored::Path::Map { parent, key } => match ignored_key_path(parent) {
+			parent if parent.is_empty() => key.clone(),
+			parent => format!("{parent}.{key}"),
+		},
+		serde_ignored::Path::Some { parent } | serde_ignored::Path::NewtypeStruct { parent } | serde_ignored::Path::NewtypeVariant { parent } => {
+			ignored_key_path(parent)
+		},
+	}
+}
+
+/// Where `key` is set at the top level of the files the configuration was loaded from, as `file:line`, using the last file that sets it.
+fn top_level_key_location(sources: &[Source], key: &str) -> Option<String> {
+	sources.iter().rev().find_map(|source| {
+		let line = source.contents.lines().position(|line| line.strip_prefix(key).is_some_and(|rest| rest.trim_start().starts_with(':')))?;
+		Some(format!("{}:{}", source.path.display(), line + 1))
+	})
+}
+
+/// A problem with the value of top level `key`, which failed to deserialize with `error`.
+fn locate(sources: &[Source], key: &str, error: serde_yaml::Error) -> ConfigError {
+	ConfigError {
+		problems: vec![ConfigProblem {
+			location: top_level_key_location(sources, key),
+			path: Some(key.to_string()),
+			message: error.to_string(),
+		}],
+	}
+}
+
+/// Replace `${NAME}` in strings with the environment variable `NAME` (`$${` is a literal `${`), and read secrets from the files given by
+/// their `_file` keys (e.g. `authorization_file`), so secrets don't have to be stored in the configuration.
+fn resolve_secrets(config: &mut Value) -> Result<(), Box<dyn std::error::Error>> {
+	match config {
+		Value::String(string) => *string = expand_env_vars(string)?,
+		Value::Sequence(sequence) => {
+			for value in sequence {
+				resolve_secrets(value)?;
+			}
+		},
+		Value::Mapping(mapping) => {
+			// Secrets read from files are resolved last, so they aren't mistaken for environment variable references.
+			for (_, value) in mapping.iter_mut() {
+				resolve_secrets(value)?;
+			}
+			for (key, tag) in SECRET_FILE_KEYS {
+				let Some(path) = mapping.remove(format!("{key}_file")) else {
+					continue;
+				};
+				let path: PathBuf = serde_yaml::from_value(path)?;
+				let secret = fs::read_to_string(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+				let secret = Value::String(secret.trim_end_matches(['\r', '\n']).to_string());
+				let secret = match tag {
+					Some(tag) => Value::Tagged(Box::new(TaggedValue {
+						tag: Tag::new(tag),
+						value: secret,
+					})),
+					None => secret,
+				};
+				mapping.insert(key.into(), secret);
+			}
+		},
+		Value::Tagged(tagged) => resolve_secrets(&mut tagged.value)?,
+		Value::Null | Value::Bool(_) | Value::Number(_) => {},
+	}
+	Ok(())
+}
+
+fn expand_env_vars(string: &str) -> Result<String, Box<dyn std::error::Error>> {
+	let mut expanded = String::new();
+	let mut rest = string;
+	while let Some((before, after)) = rest.split_once("${") {
+		if let Some(before) = before.strip_suffix('$') {
+			expanded += before;
+			expanded += "${";
+			rest = after;
+			continue;
+		}
+		let (name, after) = after.split_once('}').ok_or_else(|| format!("unterminated environment variable reference in \"{string}\""))?;
+		expanded += before;
+		expanded += &env::var(name).map_err(|e| format!("environment variable {name}: {e}"))?;
+		rest = after;
+	}
+	expanded += rest;
+	Ok(expanded)
+}
+
+/// Merge `fragment` into `config`: mappings are merged key by key, sequences (such as `hosts`) are appended, and anything else is
+/// replaced.
+fn merge(config: &mut Value, fragment: Value) {
+	match (config, fragment) {
+		(Value::Mapping(config), Value::Mapping(fragment)) => {
+			for (key, value) in fragment {
+				match config.get_mut(&key) {
+					Some(existing) => merge(existing, value),
+					None => {
+						config.insert(key, value);
+					},
+				}
+			}
+		},
+		(Value::Sequence(config), Value::Sequence(fragment)) => config.extend(fragment),
+		(config, fragment) => *config = fragment,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde::Deserialize;
+
+	use super::*;
+
+	#[derive(Debug, Deserialize)]
+	struct TestOptions {
+		#[serde(default)]
+		strict: bool,
+		#[serde(default)]
+		port: u16,
+		#[serde(default)]
+		hosts: Vec<TestHost>,
+	}
+
+	#[derive(Debug, Deserialize)]
+	struct TestHost {
+		address: String,
+		#[serde(default)]
+		port: u16,
+	}
+
+	/// A directory of its own for each test, removed when dropped.
+	struct TestDirectory(PathBuf);
+
+	impl TestDirectory {
+		fn new(name: &str) -> Self {
+			let path = env::temp_dir().join(format!("apcupsd_exporter_config_{name}_{}", std::process::id()));
+			let _ = fs::remove_dir_all(&path);
+			fs::create_dir_all(&path).unwrap();
+			Self(path)
+		}
+
+		fn write(&self, name: &str, contents: &str) -> PathBuf {
+			let path = self.0.join(name);
+			fs::create_dir_all(path.parent().unwrap()).unwrap();
+			fs::write(&path, contents).unwrap();
+			path
+		}
+	}
+
+	impl Drop for TestDirectory {
+		fn drop(&mut self) {
+			let _ = fs::remove_dir_all(&self.0);
+		}
+	}
+
+	#[test]
+	fn test_expand_env_vars() {
+		env::set_var("APCUPSD_EXPORTER_CONFIG_TEST", "value");
+		assert_eq!(expand_env_vars("a ${APCUPSD_EXPORTER_CONFIG_TEST} b").unwrap(), "a value b");
+		assert_eq!(
+			expand_env_vars("$${APCUPSD_EXPORTER_CONFIG_TEST}").unwrap(),
+			"${APCUPSD_EXPORTER_CONFIG_TEST}"
+		);
+		assert_eq!(
+			expand_env_vars("$$${APCUPSD_EXPORTER_CONFIG_TEST}").unwrap(),
+			"$${APCUPSD_EXPORTER_CONFIG_TEST}"
+		);
+		assert_eq!(expand_env_vars("cost: $5").unwrap(), "cost: $5");
+		assert!(expand_env_vars("${APCUPSD_EXPORTER_CONFIG_TEST").is_err());
+		assert!(expand_env_vars("${APCUPSD_EXPORTER_CONFIG_TEST_UNSET}").is_err());
+	}
+
+	#[test]
+	fn test_load_includes_and_secret_files() {
+		let directory = TestDirectory::new("load");
+		// Unlike includes, secret files are relative to the working directory.
+		let authorization_file = directory.write("secrets/authorization", "metrics-password\n");
+		let commands_file = directory.write("secrets/commands", "commands-password\r\n");
+		directory.write("conf.d/b.yaml", "hosts: [{ address: b }]\n");
+		directory.write(
+			"conf.d/a.yml",
+			&format!(
+				"hosts: [{{ address: a }}]\nups_commands: {{ password_file: {} }}\n",
+				commands_file.display()
+			),
+		);
+		directory.write("conf.d/ignored.txt", "hosts: [{ address: ignored }]\n");
+		let config = format!(
+			"include: [conf.d]\nhosts: [{{ address: main }}]\nauthorization_file: {}\nups_commands: {{ hosts: [a] }}\n",
+			authorization_file.display()
+		);
+		let path = directory.write("config.yaml", &config);
+
+		let (config, sources) = load(&path).unwrap();
+		assert_eq!(sources.len(), 3);
+		let addresses: Vec<_> = config["hosts"].as_sequence().unwrap().iter().map(|host| host["address"].as_str().unwrap()).collect();
+		assert_eq!(addresses, ["main", "a", "b"]);
+		let Value::Tagged(authorization) = &config["authorization"] else {
+			panic!("authorization isn't tagged");
+		};
+		assert_eq!(authorization.tag, Tag::new("Basic"));
+		assert_eq!(authorization.value.as_str(), Some("metrics-password"));
+		assert_eq!(config["ups_commands"]["password"].as_str(), Some("commands-password"));
+		assert_eq!(config["ups_commands"]["hosts"][0].as_str(), Some("a"));
+		assert!(config.get("authorization_file").is_none());
+	}
+
+	#[test]
+	fn test_deserialize_reports_every_problem() {
+		let directory = TestDirectory::new("deserialize");
+		let config = "strict: true\nport: not-a-port\nhosts:\n  - address: a\n  - address: b\n    port: -1\n  - address: c\n    colour: red\n";
+		let path = directory.write("config.yaml", config);
+		let (config, sources) = load(&path).unwrap();
+		let problems = deserialize::<TestOptions>(config, &sources).unwrap_err().problems;
+		let found: Vec<_> = problems.iter().map(|problem| (problem.location.clone().unwrap(), problem.path.clone().unwrap())).collect();
+		let location = |line| format!("{}:{line}", path.display());
+		assert_eq!(
+			found,
+			[
+				(location(2), "port".to_string()),
+				(location(3), "hosts[1].port".to_string()),
+				(location(3), "hosts[2].colour".to_string())
+			]
+		);
+		assert_eq!(problems[2].message, "unknown key");
+	}
+
+	#[test]
+	fn test_deserialize_returns_unknown_keys() {
+		let directory = TestDirectory::new("unknown_keys");
+		let path = directory.write("config.yaml", "port: 9162\nhosts: [{ address: a, colour: red }]\n");
+		let (config, sources) = load(&path).unwrap();
+		let (options, unknown_keys) = deserialize::<TestOptions>(config, &sources).unwrap();
+		assert_eq!(
+			(options.strict, options.port, options.hosts[0].address.as_str(), options.hosts[0].port),
+			(false, 9162, "a", 0)
+		);
+		assert_eq!(unknown_keys, ["hosts[0].colour"]);
+	}
+
+	#[test]
+	fn test_load_rejects_nested_includes() {
+		let directory = TestDirectory::new("nested");
+		directory.write("other.yaml", "include: [config.yaml]\n");
+		let path = directory.write("config.yaml", "include: [other.yaml]\n");
+		let Err(e) = load(&path) else {
+			panic!("nested include was loaded");
+		};
+		assert!(e.to_string().contains("included files can't include other files"));
+	}
+}
@@ -0,0 +1,102 @@
+//! The apcupsd version reported in `VERSION`, whose status output has changed over time. Output from versions known to differ from the
+//! current format is parsed accordingly, and warned about so odd metrics can be traced back to it.
+
+/// Newest version the status output has been checked against.
+const NEWEST_KNOWN_VERSION: [u32; 3] = [3, 14, 14];
+/// First version reporting timestamps in [`DateFormat::Iso`].
+const ISO_DATES_SINCE: [u32; 3] = [3, 14, 0];
+
+/// Format of timestamps like `DATE` and `STARTTIME`, which are parsed in the format of the reported version first.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum DateFormat {
+	/// `2024-09-18 13:31:52 -0500`
+	#[default]
+	Iso,
+	/// `Sun Jun 05 21:20:37 -0700 2005`
+	Historic,
+}
+
+pub struct DaemonVersion {
+	/// Version number, e.g. `3.14.14`.
+	pub number: String,
+	/// Release date, e.g. `31 May 2016`.
+	pub release_date: Option<String>,
+	/// Platform apcupsd was built for, e.g. `debian`.
+	pub platform: Option<String>,
+	components: [u32; 3],
+}
+
+impl DaemonVersion {
+	/// Parse a `VERSION` like `3.14.14 (31 May 2016) debian`.
+	pub fn parse(value: &str) -> Option<Self> {
+		let value = value.trim_matches(|c: char| c.is_whitespace() || c.is_control());
+		let (number, rest) = value.split_once(' ').unwrap_or((value, ""));
+		let mut components = [0; 3];
+		for (component, text) in components.iter_mut().zip(number.split('.')) {
+			// Ignore suffixes like `-rc1`.
+			let digits = text.find(|c: char| !c.is_ascii_digit()).map_or(text, |end| &text[..end]);
+			*component = digits.parse().ok()?;
+		}
+		let (release_date, platform) = match rest.strip_prefix('(').and_then(|rest| rest.split_once(')')) {
+			Some((release_date, platform)) => (Some(release_date.to_string()), platform.trim()),
+			None => (None, rest.trim()),
+		};
+		Some(Self {
+			number: number.to_string(),
+			release_date,
+			platform: (!platform.is_empty()).then(|| platform.to_string()),
+			components,
+		})
+	}
+
+	pub fn date_format(&self) -> DateFormat {
+		if self.components < ISO_DATES_SINCE {
+			DateFormat::Historic
+		} else {
+			DateFormat::Iso
+		}
+	}
+
+	/// Why the status output of this version might not be exported as expected, if it's known to differ from the current format.
+	pub fn compatibility_warning(&self) -> Option<String> {
+		if self.components > NEWEST_KNOWN_VERSION {
+			Some(format!(
+				"apcupsd {} is newer than any version this exporter was checked against",
+				self.number
+			))
+		} else if self.date_format() == DateFormat::Historic {
+			Some(format!(
+				"apcupsd {} predates 3.14 and reports timestamps and some keys in older formats",
+				self.number
+			))
+		} else {
+			None
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{DaemonVersion, DateFormat};
+
+	#[test]
+	fn test_parse() {
+		let version = DaemonVersion::parse("3.14.14 (31 May 2016) debian").unwrap();
+		assert_eq!(version.number, "3.14.14");
+		assert_eq!(version.release_date.as_deref(), Some("31 May 2016"));
+		assert_eq!(version.platform.as_deref(), Some("debian"));
+		assert!(version.date_format() == DateFormat::Iso);
+		assert!(version.compatibility_warning().is_none());
+
+		let version = DaemonVersion::parse("3.10.17 (18 March 2005) suse").unwrap();
+		assert!(version.date_format() == DateFormat::Historic);
+		assert!(version.compatibility_warning().is_some());
+
+		let version = DaemonVersion::parse("3.15.0-rc1\0").unwrap();
+		assert_eq!(version.release_date, None);
+		assert_eq!(version.platform, None);
+		assert!(version.compatibility_warning().is_some());
+
+		assert!(DaemonVersion::parse("unknown").is_none());
+	}
+}
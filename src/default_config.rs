@@ -0,0 +1,119 @@
+//! A commented configuration file with every option at its default, generated from the options' JSON Schema (whose descriptions are
+//! their doc comments) and `Default` implementations, so it can't drift from the code.
+
+use serde_json::{Map, Value};
+
+use crate::{ApcupsdExporterOptions, HostSpecificOptions};
+
+/// Width descriptions are wrapped at, not counting indentation.
+const DESCRIPTION_WIDTH: usize = 120;
+
+pub fn render() -> Result<String, serde_json::Error> {
+	let schema = serde_json::to_value(schemars::schema_for!(ApcupsdExporterOptions))?;
+	let defaults = serde_json::to_value(ApcupsdExporterOptions::default())?;
+	let renderer = Renderer {
+		definitions: schema.get("definitions"),
+		host_example: serde_json::to_value(HostSpecificOptions::default())?,
+	};
+	let mut rendered = String::from(
+		"# Every option with its default. Options without a default, and examples of list entries, are commented out.\n# Generated with \
+		 --print-default-config.\n",
+	);
+	renderer.render_properties(&mut rendered, &schema, defaults.as_object(), 0, false);
+	Ok(rendered)
+}
+
+struct Renderer<'a> {
+	definitions: Option<&'a Value>,
+	/// Defaults of an entry of `hosts`, which is empty by default.
+	host_example: Value,
+}
+
+impl<'a> Renderer<'a> {
+	/// Follow `$ref`s, and unwrap the `allOf` and `anyOf` (with `null` for `Option`s) schemas that wrap a single schema.
+	fn resolve(&self, schema: &'a Value) -> &'a Value {
+		if let Some(name) = schema.get("$ref").and_then(Value::as_str).and_then(|reference| reference.strip_prefix("#/definitions/")) {
+			if let Some(definition) = self.definitions.and_then(|definitions| definitions.get(name)) {
+				return self.resolve(definition);
+			}
+		}
+		for combinator in ["allOf", "anyOf"] {
+			if let Some(schemas) = schema.get(combinator).and_then(Value::as_array) {
+				let mut non_null = schemas.iter().filter(|schema| schema.get("type").and_then(Value::as_str) != Some("null"));
+				if let (Some(schema), None) = (non_null.next(), non_null.next()) {
+					return self.resolve(schema);
+				}
+			}
+		}
+		schema
+	}
+
+	/// Render the properties of an object schema with their descriptions, with values from `defaults` or the schema's own defaults.
+	/// Commented out properties stay at their indentation, so removing `# ` uncomments them.
+	fn render_properties(&self, rendered: &mut String, schema: &'a Value, defaults: Option<&Map<String, Value>>, indent: usize, commented: bool) {
+		let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+			return;
+		};
+		let indentation = "  ".repeat(indent);
+		for (key, property) in properties {
+			let resolved = self.resolve(property);
+			if let Some(description) = property.get("description").or_else(|| resolved.get("description")).and_then(Value::as_str) {
+				for line in wrap(description) {
+					*rendered += &format!("{indentation}# {line}\n");
+				}
+			}
+			let prefix = if commented { format!("{indentation}# ") } else { indentation.clone() };
+			let default = defaults.and_then(|defaults| defaults.get(key)).or_else(|| property.get("default"));
+			let item_schema = resolved.get("items").map(|items| self.resolve(items));
+			match default {
+				Some(Value::Object(defaults)) if resolved.get("properties").is_some() => {
+					*rendered += &format!("{prefix}{key}:\n");
+					self.render_properties(rendered, resolved, Some(defaults), indent + 1, commented);
+				},
+				None | Some(Value::Null) if resolved.get("properties").is_some() => {
+					*rendered += &format!("{indentation}# {key}:\n");
+					let defaults = resolved.get("default").and_then(Value::as_object);
+					self.render_properties(rendered, resolved, defaults, indent + 1, true);
+				},
+				Some(Value::Array(items)) if items.is_empty() && item_schema.is_some_and(|items| items.get("properties").is_some()) => {
+					*rendered += &format!("{prefix}{key}: []\n{indentation}# {key}:\n{indentation}  # -\n");
+					let example = if key == "hosts" && indent == 0 {
+						self.host_example.as_object()
+					} else {
+						None
+					};
+					self.render_properties(rendered, item_schema.unwrap_or(resolved), example, indent + 2, true);
+				},
+				Some(value) => *rendered += &format!("{prefix}{key}: {}\n", yaml_value(value)),
+				None => *rendered += &format!("{indentation}# {key}:\n"),
+			}
+		}
+	}
+}
+
+/// A value as YAML, in JSON's flow style which YAML is a superset of. Secrets are serialized redacted, and are empty by default.
+fn yaml_value(value: &Value) -> String {
+	match value {
+		Value::String(string) if string == "<redacted>" => "\"\"".to_string(),
+		value => value.to_string(),
+	}
+}
+
+/// Wrap a description at word boundaries.
+fn wrap(description: &str) -> Vec<String> {
+	let mut lines = Vec::new();
+	for paragraph in description.lines() {
+		let mut line = String::new();
+		for word in paragraph.split_whitespace() {
+			if !line.is_empty() && line.len() + 1 + word.len() > DESCRIPTION_WIDTH {
+				lines.push(std::mem::take(&mut line));
+			}
+			if !line.is_empty() {
+				line.push(' ');
+			}
+			line.push_str(word);
+		}
+		lines.push(line);
+	}
+	lines
+}
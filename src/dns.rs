@@ -0,0 +1,100 @@
+//! Resolving the names of NIS servers with the exporter's own resolver, instead of on every connection, so resolutions are cached for
+//! their TTL and redone when connecting fails, e.g. for UPS management hosts that move behind dynamic DNS.
+
+#[cfg(feature = "dns")]
+mod hickory;
+
+use std::{net::IpAddr, sync::Arc, time::Instant};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "dns")]
+pub use hickory::Resolver;
+
+pub type ResolveError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Clone, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(default)]
+pub struct DnsOptions {
+	/// Address family to connect with when a name resolves to both.
+	pub prefer: AddressFamily,
+	/// Shortest time a resolution is cached, even if its TTL is shorter.
+	pub min_ttl_seconds: f64,
+	/// Longest time a resolution is cached, even if its TTL is longer.
+	pub max_ttl_seconds: f64,
+}
+
+impl Default for DnsOptions {
+	fn default() -> Self {
+		Self {
+			prefer: AddressFamily::Any,
+			min_ttl_seconds: 5.,
+			max_ttl_seconds: 3600.,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressFamily {
+	/// The first address returned.
+	#[default]
+	Any,
+	Ipv4,
+	Ipv6,
+}
+
+/// Stand-in for the resolver in builds without the `dns` feature, which can't be created.
+#[cfg(not(feature = "dns"))]
+pub struct Resolver(std::convert::Infallible);
+
+#[cfg(not(feature = "dns"))]
+impl Resolver {
+	pub fn new(_options: DnsOptions) -> Result<Self, ResolveError> {
+		Err(crate::feature_disabled("dns").into())
+	}
+
+	async fn resolve(&self, _name: &str) -> Result<(IpAddr, std::time::Duration), ResolveError> {
+		match self.0 {}
+	}
+}
+
+/// A name resolved with a [`Resolver`], cached until its TTL runs out or it's invalidated.
+pub struct ResolvedName {
+	resolver: Arc<Resolver>,
+	name: String,
+	valid_until: Option<Instant>,
+}
+
+impl ResolvedName {
+	pub fn new(resolver: Arc<Resolver>, name: String) -> Self {
+		Self {
+			resolver,
+			name,
+			valid_until: None,
+		}
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	/// Resolve the name again if its last resolution expired or was invalidated, returning the new address, or `None` if the last one is
+	/// still valid.
+	pub async fn refresh(&mut self) -> Option<Result<IpAddr, ResolveError>> {
+		if self.valid_until.is_some_and(|valid_until| Instant::now() < valid_until) {
+			return None;
+		}
+		let resolved = self.resolver.resolve(&self.name).await;
+		Some(resolved.map(|(address, ttl)| {
+			self.valid_until = Instant::now().checked_add(ttl);
+			address
+		}))
+	}
+
+	/// Resolve the name again before the next connection, e.g. because connecting to its last address failed.
+	pub fn invalidate(&mut self) {
+		self.valid_until = None;
+	}
+}
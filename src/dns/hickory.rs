@@ -0,0 +1,40 @@
+//! Resolving with hickory-resolver, which keeps the TTL of resolutions unlike the system resolver.
+
+use std::{
+	net::IpAddr,
+	time::{Duration, Instant},
+};
+
+use hickory_resolver::TokioAsyncResolver;
+
+use super::{AddressFamily, DnsOptions, ResolveError};
+
+pub struct Resolver {
+	resolver: TokioAsyncResolver,
+	options: DnsOptions,
+}
+
+impl Resolver {
+	/// Create a resolver using the system's DNS configuration.
+	pub fn new(options: DnsOptions) -> Result<Self, ResolveError> {
+		Ok(Self {
+			resolver: TokioAsyncResolver::tokio_from_system_conf()?,
+			options,
+		})
+	}
+
+	/// Resolve `name` to the address to connect to, with how long it can be cached.
+	pub(super) async fn resolve(&self, name: &str) -> Result<(IpAddr, Duration), ResolveError> {
+		let lookup = self.resolver.lookup_ip(name).await?;
+		let preferred = |address: &IpAddr| match self.options.prefer {
+			AddressFamily::Any => true,
+			AddressFamily::Ipv4 => address.is_ipv4(),
+			AddressFamily::Ipv6 => address.is_ipv6(),
+		};
+		let address = lookup.iter().find(preferred).or_else(|| lookup.iter().next()).ok_or("no addresses")?;
+		let min_ttl = Duration::try_from_secs_f64(self.options.min_ttl_seconds).unwrap_or_default();
+		let max_ttl = Duration::try_from_secs_f64(self.options.max_ttl_seconds).unwrap_or(Duration::MAX);
+		let ttl = lookup.valid_until().saturating_duration_since(Instant::now()).max(min_ttl).min(max_ttl);
+		Ok((address, ttl))
+	}
+}
@@ -0,0 +1,138 @@
+//! Provisioning a dashboard for the exporter's metrics through Grafana's HTTP API, generated for the configured label names and percent
+//! scale so it works without editing.
+
+use std::time::Duration;
+
+use clap::Args;
+use http_body_util::Full;
+use hyper::{
+	body::Bytes,
+	header::{AUTHORIZATION, CONTENT_TYPE},
+	Request,
+};
+use serde_json::{json, Value};
+use tokio::time::timeout;
+
+use crate::{
+	http_client::{self, HttpError},
+	PercentScale, RenderOptions,
+};
+
+/// How long Grafana may take to save the dashboard before provisioning fails.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Args)]
+pub struct GrafanaArgs {
+	/// Base URL of Grafana, e.g. `https://grafana.example.com`. The API token is read from the `GRAFANA_TOKEN` environment variable.
+	#[arg(long)]
+	pub url: String,
+	/// UID of the Prometheus datasource the panels query.
+	#[arg(long, default_value = "prometheus")]
+	pub datasource_uid: String,
+	/// UID of the dashboard, which is replaced if it already exists.
+	#[arg(long, default_value = "apcupsd")]
+	pub dashboard_uid: String,
+	#[arg(long, default_value = "UPS (apcupsd)")]
+	pub title: String,
+	/// UID of the folder to save the dashboard in; defaults to the General folder.
+	#[arg(long)]
+	pub folder_uid: Option<String>,
+	/// Print the dashboard JSON instead of pushing it.
+	#[arg(long)]
+	pub print: bool,
+}
+
+/// Push the dashboard, returning its URL.
+pub async fn provision(args: &GrafanaArgs, dashboard: Value) -> Result<String, HttpError> {
+	let token = std::env::var("GRAFANA_TOKEN").map_err(|_| "GRAFANA_TOKEN isn't set")?;
+	let body = json!({ "dashboard": dashboard, "folderUid": args.folder_uid, "overwrite": true });
+	let request = Request::post(format!("{}/api/dashboards/db", args.url.trim_end_matches('/')))
+		.header(AUTHORIZATION, format!("Bearer {token}"))
+		.header(CONTENT_TYPE, "application/json")
+		.body(Full::new(Bytes::from(body.to_string())))?;
+	let (status, response) = timeout(REQUEST_TIMEOUT, http_client::send(request)).await??;
+	if !status.is_success() {
+		return Err(format!("Grafana responded with {status}: {}", String::from_utf8_lossy(&response)).into());
+	}
+	let response: Value = serde_json::from_slice(&response)?;
+	Ok(format!(
+		"{}{}",
+		args.url.trim_end_matches('/'),
+		response["url"].as_str().unwrap_or_default()
+	))
+}
+
+/// The dashboard, with a variable for the `exported_ups` label and for each label added from request headers, which every query is
+/// filtered by.
+pub fn dashboard(args: &GrafanaArgs, render_options: &RenderOptions) -> Value {
+	let datasource = json!({ "type": "prometheus", "uid": args.datasource_uid });
+	let variable_labels: Vec<&str> = render_options.labels.from_headers.values().map(String::as_str).chain(["exported_ups"]).collect();
+	let selector = variable_labels.iter().map(|label| format!("{label}=~\"${label}\"")).collect::<Vec<_>>().join(",");
+	let variables: Vec<Value> = variable_labels
+		.iter()
+		.map(|label| {
+			let display_name = if *label == "exported_ups" { "UPS" } else { label };
+			json!({
+				"name": label,
+				"label": display_name,
+				"type": "query",
+				"datasource": datasource,
+				"query": { "query": format!("label_values(apcupsd_up, {label})"), "refId": label },
+				"refresh": 2,
+				"includeAll": true,
+				"multi": true,
+				"current": { "text": "All", "value": "$__all" },
+			})
+		})
+		.collect();
+
+	let percent_unit = match render_options.percent_scale {
+		PercentScale::Fraction | PercentScale::Ratio => "percentunit",
+		PercentScale::Percent => "percent",
+	};
+	let percent_scale = render_options.percent_scale;
+	let panels = [
+		("Up", "stat", "apcupsd_up".to_string(), "none"),
+		(
+			"Battery charge",
+			"timeseries",
+			percent_scale.metric_name("apcupsd_battery_charge_percent"),
+			percent_unit,
+		),
+		("Time left on battery", "timeseries", "apcupsd_battery_time_left_seconds".to_string(), "s"),
+		("Load", "timeseries", percent_scale.metric_name("apcupsd_ups_load_percent"), percent_unit),
+		("Line voltage", "timeseries", "apcupsd_line_volts".to_string(), "volt"),
+		("Battery voltage", "timeseries", "apcupsd_battery_volts".to_string(), "volt"),
+	];
+	let panels: Vec<Value> = panels
+		.into_iter()
+		.enumerate()
+		.map(|(index, (title, panel_type, metric, unit))| {
+			json!({
+				"id": index + 1,
+				"title": title,
+				"type": panel_type,
+				"datasource": datasource,
+				"gridPos": { "x": (index % 2) * 12, "y": (index / 2) * 8, "w": 12, "h": 8 },
+				"fieldConfig": { "defaults": { "unit": unit }, "overrides": [] },
+				"targets": [{
+					"refId": "A",
+					"datasource": datasource,
+					"expr": format!("{metric}{{{selector}}}"),
+					"legendFormat": "{{exported_ups}}",
+				}],
+			})
+		})
+		.collect();
+
+	json!({
+		"uid": args.dashboard_uid,
+		"title": args.title,
+		"tags": ["apcupsd", "ups"],
+		"timezone": "browser",
+		"schemaVersion": 39,
+		"time": { "from": "now-24h", "to": "now" },
+		"templating": { "list": variables },
+		"panels": panels,
+	})
+}
@@ -0,0 +1,73 @@
+use std::{collections::HashMap, time::Instant};
+
+use prometheus_exporter_base::{MetricType, PrometheusMetric};
+
+use crate::prometheus_instance_with_labels;
+
+/// Last good values of keys configured to keep their last value, so a sensor that briefly drops out or reports garbage
+/// doesn't leave gaps in its metric, and how many implausible values of each key were dropped.
+#[derive(Default)]
+pub struct HeldValues {
+	values: HashMap<String, (f64, Instant)>,
+	held_counts: HashMap<String, u64>,
+	implausible_counts: HashMap<String, u64>,
+}
+
+impl HeldValues {
+	/// Record `value` if present, otherwise return the last value of `key` if it is at most `hold_seconds` old.
+	pub fn hold(&mut self, key: &str, value: Option<f64>, hold_seconds: f64) -> Option<f64> {
+		if let Some(value) = value {
+			self.values.insert(key.to_string(), (value, Instant::now()));
+			return Some(value);
+		}
+		let (value, updated) = self.values.get(key)?;
+		if updated.elapsed().as_secs_f64() > hold_seconds {
+			return None;
+		}
+		*self.held_counts.entry(key.to_string()).or_default() += 1;
+		Some(*value)
+	}
+
+	/// Render how many times each of `keys` has been replaced by its last value.
+	pub fn render<'a>(&self, keys: impl IntoIterator<Item = &'a String>, labels: &[(String, String)]) -> String {
+		let mut keys: Vec<_> = keys.into_iter().collect();
+		if keys.is_empty() {
+			return String::new();
+		}
+		keys.sort();
+		let mut metric = PrometheusMetric::build()
+			.with_name("apcupsd_held_values_total")
+			.with_help("Number of times a missing or unparsable value was replaced by the last good value.")
+			.with_metric_type(MetricType::Counter)
+			.build();
+		for key in keys {
+			let count = self.held_counts.get(key).copied().unwrap_or_default();
+			metric.render_and_append_instance(&prometheus_instance_with_labels(labels).with_label("key", key.as_str()).with_value(count));
+		}
+		metric.render()
+	}
+
+	/// Count a value of `key` that was dropped for being outside of its plausible range.
+	pub fn count_implausible(&mut self, key: &str) {
+		*self.implausible_counts.entry(key.to_string()).or_default() += 1;
+	}
+
+	/// Render how many values of each of `keys` have been dropped for being implausible.
+	pub fn render_implausible<'a>(&self, keys: impl IntoIterator<Item = &'a String>, labels: &[(String, String)]) -> String {
+		let mut keys: Vec<_> = keys.into_iter().collect();
+		if keys.is_empty() {
+			return String::new();
+		}
+		keys.sort();
+		let mut metric = PrometheusMetric::build()
+			.with_name("apcupsd_exporter_implausible_values_total")
+			.with_help("Number of values dropped for being outside of their plausible range.")
+			.with_metric_type(MetricType::Counter)
+			.build();
+		for key in keys {
+			let count = self.implausible_counts.get(key).copied().unwrap_or_default();
+			metric.render_and_append_instance(&prometheus_instance_with_labels(labels).with_label("key", key.as_str()).with_value(count));
+		}
+		metric.render()
+	}
+}
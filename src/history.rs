@@ -0,0 +1,102 @@
+//! Keeping the most recent polls of each host in memory, since Prometheus often scrapes at a lower resolution than is needed to look back
+//! at an outage.
+
+use std::{
+	collections::{HashMap, VecDeque},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{parse_metric, MetricParseConfig, MetricParseType, PercentScale};
+
+/// Keys kept for every poll, with how to parse them. Percentages are kept as 0-100.
+const HISTORY_KEYS: [(&str, MetricParseType); 8] = [
+	("LINEV", MetricParseType::Voltage),
+	("LINEFREQ", MetricParseType::Frequency),
+	("OUTPUTV", MetricParseType::Voltage),
+	("LOADPCT", MetricParseType::Percentage),
+	("BCHARGE", MetricParseType::Percentage),
+	("BATTV", MetricParseType::Voltage),
+	("TIMELEFT", MetricParseType::Duration),
+	("ITEMP", MetricParseType::Temperature),
+];
+
+#[derive(Clone, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(default)]
+pub struct HistoryOptions {
+	/// Number of polls kept for each host.
+	pub polls: usize,
+}
+
+impl Default for HistoryOptions {
+	fn default() -> Self {
+		Self { polls: 720 }
+	}
+}
+
+pub struct History {
+	capacity: usize,
+	/// Time of each poll and the values of [`HISTORY_KEYS`], oldest first.
+	polls: VecDeque<(f64, [Option<f64>; HISTORY_KEYS.len()])>,
+	/// `STATUS` of each poll.
+	statuses: VecDeque<String>,
+}
+
+impl History {
+	pub fn new(options: &HistoryOptions) -> Self {
+		Self {
+			capacity: options.polls,
+			polls: VecDeque::with_capacity(options.polls),
+			statuses: VecDeque::with_capacity(options.polls),
+		}
+	}
+
+	/// Record data from a successful poll, forgetting the oldest poll if full.
+	pub fn observe(&mut self, apcupsd_data: &HashMap<String, String>) {
+		if self.capacity == 0 {
+			return;
+		}
+		if self.polls.len() == self.capacity {
+			self.polls.pop_front();
+			self.statuses.pop_front();
+		}
+		let values = HISTORY_KEYS.map(|(key, parse_type)| {
+			let parse_config = MetricParseConfig {
+				percent_scale: PercentScale::Percent,
+				..parse_type.into()
+			};
+			parse_metric(apcupsd_data.get(key)?.clone(), parse_config).ok().flatten()
+		});
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+		self.polls.push_back((now, values));
+		self.statuses.push_back(apcupsd_data.get("STATUS").cloned().unwrap_or_default());
+	}
+
+	/// The values of `key` for every poll, oldest first.
+	pub fn series(&self, key: &str) -> Vec<Option<f64>> {
+		match HISTORY_KEYS.iter().position(|(history_key, _)| *history_key == key) {
+			Some(index) => self.polls.iter().map(|(_, values)| values[index]).collect(),
+			None => Vec::new(),
+		}
+	}
+
+	/// `STATUS` of the most recent poll.
+	pub fn latest_status(&self) -> Option<&str> {
+		self.statuses.back().map(String::as_str)
+	}
+
+	/// The polls since `since` (a Unix timestamp) as JSON, with a list of values for each key lined up with the list of timestamps.
+	pub fn to_json(&self, since: f64) -> Value {
+		let start = self.polls.partition_point(|(timestamp, _)| *timestamp < since);
+		let timestamps: Vec<_> = self.polls.range(start..).map(|(timestamp, _)| *timestamp).collect();
+		let mut values = serde_json::Map::new();
+		for (index, (key, _)) in HISTORY_KEYS.iter().enumerate() {
+			values.insert(key.to_string(), self.polls.range(start..).map(|(_, values)| values[index]).collect());
+		}
+		values.insert("STATUS".to_string(), self.statuses.range(start..).cloned().collect());
+		json!({ "timestamps": timestamps, "values": values })
+	}
+}
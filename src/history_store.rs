@@ -0,0 +1,113 @@
+//! Recording every poll of every host in an SQLite database, for history in standalone deployments that don't run Prometheus. Unlike
+//! [`crate::history`], it survives restarts and keeps every numeric key, as the number apcupsd reports it with (`LOADPCT` is 0-100,
+//! `TIMELEFT` is in minutes).
+
+#[cfg(feature = "history-store")]
+mod sqlite;
+
+use std::{
+	collections::{BTreeMap, HashMap},
+	path::PathBuf,
+	sync::Arc,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, NaiveDate};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::task::spawn_blocking;
+
+#[cfg(feature = "history-store")]
+pub use sqlite::HistoryStore;
+
+#[derive(Clone, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(default)]
+pub struct HistoryStoreOptions {
+	/// SQLite database file, created if it doesn't exist.
+	pub path: PathBuf,
+	/// Days samples are kept for.
+	pub retention_days: f64,
+}
+
+impl Default for HistoryStoreOptions {
+	fn default() -> Self {
+		Self {
+			path: PathBuf::from("apcupsd-history.sqlite3"),
+			retention_days: 30.,
+		}
+	}
+}
+
+/// Stand-in for the store in builds without the `history-store` feature, which can't be opened.
+#[cfg(not(feature = "history-store"))]
+#[derive(PartialEq)]
+pub struct HistoryStore(std::convert::Infallible);
+
+#[cfg(not(feature = "history-store"))]
+impl HistoryStore {
+	pub fn open(_options: &HistoryStoreOptions) -> Result<Self, Box<dyn std::error::Error>> {
+		Err(crate::feature_disabled("history-store").into())
+	}
+
+	fn insert(&self, _slug: &str, _timestamp: f64, _data: &HashMap<String, String>) -> Result<(), std::convert::Infallible> {
+		match self.0 {}
+	}
+
+	pub async fn samples(
+		self: Arc<Self>,
+		_slug: String,
+		_from: f64,
+		_to: f64,
+	) -> Result<Vec<(f64, String, f64)>, Box<dyn std::error::Error + Send + Sync>> {
+		match self.0 {}
+	}
+}
+
+/// A [`HistoryStore`] shared by every host, with the slug of the host it records the polls of.
+#[derive(Clone)]
+pub struct HostHistoryStore {
+	pub store: Arc<HistoryStore>,
+	pub slug: String,
+}
+
+impl HostHistoryStore {
+	/// Record the numeric values of a successful poll, deleting samples past the retention at most hourly. Failures are logged.
+	pub async fn record(&self, fetched_at: SystemTime, data: &HashMap<String, String>) {
+		let store = self.clone();
+		let timestamp = fetched_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+		let data = data.clone();
+		let result = spawn_blocking(move || store.store.insert(&store.slug, timestamp, &data)).await;
+		if let Ok(Err(e)) = result {
+			crate::syslog::error(format!("Failed to record poll of {} in the history store: {e}", self.slug));
+		}
+	}
+}
+
+/// Samples as JSON in the format of [`crate::history::History::to_json`], with a list of values for each key lined up with the list of
+/// timestamps.
+pub fn samples_to_json(samples: &[(f64, String, f64)]) -> Value {
+	let mut timestamps: Vec<f64> = samples.iter().map(|(timestamp, _, _)| *timestamp).collect();
+	timestamps.dedup();
+	let mut values: BTreeMap<&str, Vec<Option<f64>>> = BTreeMap::new();
+	let mut index = 0;
+	for (timestamp, key, value) in samples {
+		while timestamps[index] != *timestamp {
+			index += 1;
+		}
+		values.entry(key.as_str()).or_insert_with(|| vec![None; timestamps.len()])[index] = Some(*value);
+	}
+	json!({ "timestamps": timestamps, "values": values })
+}
+
+/// Parse a time given as a Unix timestamp, an RFC 3339 date and time, or a date (midnight UTC).
+pub fn parse_time(value: &str) -> Option<f64> {
+	if let Ok(timestamp) = value.parse::<f64>() {
+		return Some(timestamp);
+	}
+	if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+		return Some(time.timestamp_millis() as f64 / 1000.);
+	}
+	let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+	Some(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp() as f64)
+}
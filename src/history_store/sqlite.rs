@@ -0,0 +1,80 @@
+//! Storing samples in SQLite with rusqlite, which bundles its own copy of SQLite.
+
+use std::{
+	collections::HashMap,
+	error::Error,
+	sync::{Arc, Mutex, PoisonError},
+	time::{Duration, Instant},
+};
+
+use rusqlite::{params, Connection};
+use tokio::task::spawn_blocking;
+
+use super::HistoryStoreOptions;
+
+/// How often samples past the retention are deleted.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+pub struct HistoryStore {
+	connection: Mutex<Connection>,
+	retention: Duration,
+	last_pruned: Mutex<Option<Instant>>,
+}
+
+/// Stores are opened once and shared, so they're only equal to themselves.
+impl PartialEq for HistoryStore {
+	fn eq(&self, other: &Self) -> bool {
+		std::ptr::eq(self, other)
+	}
+}
+
+impl HistoryStore {
+	pub fn open(options: &HistoryStoreOptions) -> Result<Self, Box<dyn Error>> {
+		let connection = Connection::open(&options.path)?;
+		connection.execute_batch(
+			"PRAGMA journal_mode = WAL;
+			CREATE TABLE IF NOT EXISTS samples (host TEXT NOT NULL, timestamp REAL NOT NULL, key TEXT NOT NULL, value REAL NOT NULL);
+			CREATE INDEX IF NOT EXISTS samples_host_timestamp ON samples (host, timestamp);",
+		)?;
+		Ok(Self {
+			connection: Mutex::new(connection),
+			retention: Duration::try_from_secs_f64(options.retention_days * 86400.)?,
+			last_pruned: Mutex::new(None),
+		})
+	}
+
+	pub(super) fn insert(&self, slug: &str, timestamp: f64, data: &HashMap<String, String>) -> rusqlite::Result<()> {
+		let mut connection = self.connection.lock().unwrap_or_else(PoisonError::into_inner);
+		let transaction = connection.transaction()?;
+		{
+			let mut insert = transaction.prepare_cached("INSERT INTO samples (host, timestamp, key, value) VALUES (?1, ?2, ?3, ?4)")?;
+			for (key, value) in data {
+				if let Some(value) = value.split_whitespace().next().and_then(|number| number.parse::<f64>().ok()) {
+					insert.execute(params![slug, timestamp, key, value])?;
+				}
+			}
+		}
+		transaction.commit()?;
+		let mut last_pruned = self.last_pruned.lock().unwrap_or_else(PoisonError::into_inner);
+		if last_pruned.is_none_or(|last_pruned| last_pruned.elapsed() >= PRUNE_INTERVAL) {
+			*last_pruned = Some(Instant::now());
+			let oldest = timestamp - self.retention.as_secs_f64();
+			connection.execute("DELETE FROM samples WHERE timestamp < ?1", params![oldest])?;
+		}
+		Ok(())
+	}
+
+	/// The samples of the host with `slug` from `from` to `to` (Unix timestamps) as `(timestamp, key, value)`, oldest first.
+	pub async fn samples(self: Arc<Self>, slug: String, from: f64, to: f64) -> Result<Vec<(f64, String, f64)>, Box<dyn Error + Send + Sync>> {
+		Ok(spawn_blocking(move || self.select(&slug, from, to)).await??)
+	}
+
+	fn select(&self, slug: &str, from: f64, to: f64) -> rusqlite::Result<Vec<(f64, String, f64)>> {
+		let connection = self.connection.lock().unwrap_or_else(PoisonError::into_inner);
+		let mut select = connection.prepare_cached(
+			"SELECT timestamp, key, value FROM samples WHERE host = ?1 AND timestamp >= ?2 AND timestamp <= ?3 ORDER BY timestamp, key",
+		)?;
+		let samples = select.query_map(params![slug, from, to], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+		samples.collect()
+	}
+}
@@ -0,0 +1,48 @@
+//! A minimal HTTP/1.1 client for pushing to other services, over plain HTTP or HTTPS verified against the Mozilla root certificates.
+
+use http_body_util::{BodyExt, Full};
+use hyper::{
+	body::Bytes,
+	client::conn::http1,
+	header::{HeaderValue, HOST},
+	rt::{Read, Write},
+	Request, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+
+use crate::tls;
+
+pub type HttpError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Send `request` to the server in its absolute `http://` or `https://` URI, returning the status and body of the response.
+pub async fn send(mut request: Request<Full<Bytes>>) -> Result<(StatusCode, Bytes), HttpError> {
+	let uri = request.uri().clone();
+	let https = match uri.scheme_str() {
+		Some("http") => false,
+		Some("https") => true,
+		_ => return Err(format!("unsupported URL \"{uri}\", only http:// and https:// are supported").into()),
+	};
+	let host = uri.host().ok_or("URL has no host")?.trim_start_matches('[').trim_end_matches(']');
+	let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+	*request.uri_mut() = uri.path_and_query().map_or("/", |path| path.as_str()).parse()?;
+	if let Some(authority) = uri.authority() {
+		request.headers_mut().entry(HOST).or_insert(HeaderValue::from_str(authority.as_str())?);
+	}
+	let stream = TcpStream::connect((host, port)).await?;
+	if https {
+		let stream = tls::connector().connect(ServerName::try_from(host.to_string())?, stream).await?;
+		exchange(TokioIo::new(stream), request).await
+	} else {
+		exchange(TokioIo::new(stream), request).await
+	}
+}
+
+async fn exchange<T: Read + Write + Send + Unpin + 'static>(io: T, request: Request<Full<Bytes>>) -> Result<(StatusCode, Bytes), HttpError> {
+	let (mut sender, connection) = http1::handshake(io).await?;
+	tokio::spawn(connection);
+	let response = sender.send_request(request).await?;
+	let status = response.status();
+	Ok((status, response.into_body().collect().await?.to_bytes()))
+}
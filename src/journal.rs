@@ -0,0 +1,192 @@
+//! Counting the power events apcupsd logs to the local systemd journal, read with `journalctl`, which also captures events that happened
+//! while the NIS server couldn't be reached or the exporter wasn't running.
+
+use std::{
+	collections::BTreeMap,
+	path::PathBuf,
+	process::Stdio,
+	sync::{Arc, Mutex, PoisonError},
+	time::Duration,
+};
+
+use prometheus_exporter_base::{MetricType, PrometheusMetric};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+	io::{AsyncBufReadExt, BufReader},
+	process::Command,
+};
+
+use crate::{labels, prometheus_instance_with_labels};
+
+/// How long to wait before restarting `journalctl` when it exits.
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// Beginnings of apcupsd's messages about events, with the name `apccontrol` calls the event by.
+const EVENT_MESSAGES: [(&str, &str); 13] = [
+	("Power failure.", "powerout"),
+	("Running on UPS batteries.", "onbattery"),
+	("Mains returned.", "offbattery"),
+	("Power is back.", "mainsback"),
+	("Battery power exhausted.", "failing"),
+	("Reached run time limit on batteries.", "timeout"),
+	("Battery charge below low limit.", "loadlimit"),
+	("Reached remaining time percentage limit on batteries.", "runlimit"),
+	("Initiating system shutdown!", "doshutdown"),
+	("Remote shutdown requested.", "remotedown"),
+	("Attempting to kill the", "killpower"),
+	("Communications with UPS lost.", "commfailure"),
+	("Communications with UPS restored.", "commok"),
+];
+
+#[derive(Clone, Deserialize, JsonSchema, Serialize)]
+#[serde(default)]
+pub struct JournalOptions {
+	/// `SYSLOG_IDENTIFIER` apcupsd logs with.
+	pub identifier: String,
+	/// Value of the `exported_ups` label, normally the slug of the host the local apcupsd is configured as.
+	pub slug: String,
+	/// File the journal cursor of the last read message is saved to, so reading continues there after a restart. Without it, the
+	/// messages of the current boot are read at startup.
+	pub cursor_file: Option<PathBuf>,
+}
+
+impl Default for JournalOptions {
+	fn default() -> Self {
+		Self {
+			identifier: "apcupsd".to_string(),
+			slug: "apcupsd0".to_string(),
+			cursor_file: None,
+		}
+	}
+}
+
+/// Counts of the events read from the journal so far.
+pub struct JournalEvents {
+	pub slug: String,
+	state: Mutex<JournalState>,
+}
+
+#[derive(Default)]
+struct JournalState {
+	/// Number of messages about each event, and the timestamp of the last one.
+	events: BTreeMap<&'static str, (u64, f64)>,
+	last_message: Option<String>,
+}
+
+/// Read apcupsd's messages from the journal in the background, restarting `journalctl` when it exits.
+pub fn spawn(options: JournalOptions) -> Arc<JournalEvents> {
+	let journal_events = Arc::new(JournalEvents {
+		slug: options.slug.clone(),
+		state: Mutex::new(JournalState::default()),
+	});
+	let events = journal_events.clone();
+	tokio::spawn(async move {
+		let mut cursor = match &options.cursor_file {
+			Some(path) => tokio::fs::read_to_string(path).await.ok().map(|cursor| cursor.trim().to_string()),
+			None => None,
+		};
+		loop {
+			match follow(&options, &events, &mut cursor).await {
+				Ok(()) => eprintln!("journalctl exited, restarting"),
+				Err(e) => crate::syslog::error(format!("Failed to read apcupsd's messages from the journal: {e}")),
+			}
+			tokio::time::sleep(RESTART_DELAY).await;
+		}
+	});
+	journal_events
+}
+
+/// Follow the journal from after `cursor`, or from the start of the current boot, until `journalctl` exits, updating `cursor` to the last
+/// read message.
+async fn follow(options: &JournalOptions, events: &JournalEvents, cursor: &mut Option<String>) -> std::io::Result<()> {
+	let mut command = Command::new("journalctl");
+	command
+		.args(["--follow", "--lines=all", "--output=json"])
+		.arg(format!("--identifier={}", options.identifier))
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.kill_on_drop(true);
+	match cursor {
+		Some(cursor) => command.arg(format!("--after-cursor={cursor}")),
+		None => command.arg("--boot"),
+	};
+	let mut child = command.spawn()?;
+	let mut lines = BufReader::new(child.stdout.take().ok_or(std::io::ErrorKind::BrokenPipe)?).lines();
+	while let Some(line) = lines.next_line().await? {
+		let Ok(entry) = serde_json::from_str::<Value>(&line) else {
+			continue;
+		};
+		// Messages that aren't valid UTF-8 are arrays of bytes, and never about events.
+		if let Some(message) = entry["MESSAGE"].as_str() {
+			let timestamp = entry["__REALTIME_TIMESTAMP"].as_str().and_then(|micros| micros.parse::<f64>().ok()).unwrap_or_default() / 1e6;
+			events.observe(message, timestamp);
+		}
+		if let Some(entry_cursor) = entry["__CURSOR"].as_str() {
+			*cursor = Some(entry_cursor.to_string());
+			if let Some(path) = &options.cursor_file {
+				if let Err(e) = tokio::fs::write(path, entry_cursor).await {
+					crate::syslog::error(format!("Failed to save journal cursor to {}: {e}", path.display()));
+				}
+			}
+		}
+	}
+	child.wait().await?;
+	Ok(())
+}
+
+impl JournalEvents {
+	fn observe(&self, message: &str, timestamp: f64) {
+		let Some((_, event)) = EVENT_MESSAGES.iter().find(|(prefix, _)| message.starts_with(prefix)) else {
+			return;
+		};
+		let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+		let (count, last) = state.events.entry(*event).or_default();
+		*count += 1;
+		*last = timestamp;
+		state.last_message = Some(message.to_string());
+	}
+
+	/// Render the number of messages about each event (0 for events that weren't logged, so `increase()` works from the first one), when
+	/// each was last logged, and the last message.
+	pub fn render(&self) -> String {
+		let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+		let ups_labels = [("exported_ups".to_string(), labels::escape_label_value(&self.slug))];
+
+		let mut events = PrometheusMetric::build()
+			.with_name("apcupsd_journal_events_total")
+			.with_help("Number of messages apcupsd logged to the journal about each event, named like apccontrol's events.")
+			.with_metric_type(MetricType::Counter)
+			.build();
+		for (_, event) in EVENT_MESSAGES {
+			let count = state.events.get(event).map_or(0, |(count, _)| *count);
+			events.render_and_append_instance(&prometheus_instance_with_labels(&ups_labels).with_label("event", event).with_value(count));
+		}
+		let mut rendered = events.render();
+
+		let mut last_event = PrometheusMetric::build()
+			.with_name("apcupsd_journal_last_event_timestamp_seconds")
+			.with_help("When apcupsd last logged a message about each event to the journal.")
+			.with_metric_type(MetricType::Gauge)
+			.build();
+		for (&event, &(_, timestamp)) in &state.events {
+			last_event.render_and_append_instance(&prometheus_instance_with_labels(&ups_labels).with_label("event", event).with_value(timestamp));
+		}
+		if !state.events.is_empty() {
+			rendered += &last_event.render();
+		}
+
+		if let Some(message) = &state.last_message {
+			let message = labels::escape_label_value(message);
+			rendered += &PrometheusMetric::build()
+				.with_name("apcupsd_journal_last_event_message_info")
+				.with_help("The last message about an event apcupsd logged to the journal.")
+				.with_metric_type(MetricType::Gauge)
+				.build()
+				.render_and_append_instance(&prometheus_instance_with_labels(&ups_labels).with_label("message", message.as_str()).with_value(1))
+				.render();
+		}
+		rendered
+	}
+}
@@ -0,0 +1,250 @@
+//! Configurable label names and templates.
+
+use std::collections::{BTreeMap, HashMap};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Deserialize, JsonSchema, Serialize)]
+#[serde(default)]
+pub struct LabelOptions {
+	/// Name of the label for the `UPSNAME` reported by apcupsd.
+	pub ups_name: String,
+	/// Name of the label for the `MODEL` reported by apcupsd.
+	pub model: String,
+	/// Name of the label for the `SERIALNO` reported by apcupsd.
+	pub serial_number: String,
+	/// Template for the value of the `exported_ups` label, overriding each host's `ups_label`. `{{KEY}}` is replaced with the value of `KEY`
+	/// reported by apcupsd (or the host's slug for `{{slug}}`), and `{{KEY[start:end]}}` with a slice of it, e.g. `{{UPSNAME}}-{{SERIALNO[-4:]}}`.
+	/// Hosts whose data doesn't have every key in the template fall back to their `ups_label`.
+	pub ups_template: Option<String>,
+	/// Redaction of identifying values reported by apcupsd.
+	pub redact: RedactionOptions,
+	/// Names of request headers, such as `X-Scrape-Site` set by a federating proxy, mapped to labels their values are added as to every
+	/// sample of the response. Headers missing from a request add no label.
+	pub from_headers: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(default)]
+pub struct RedactionOptions {
+	/// How the `SERIALNO` reported by apcupsd is exported.
+	pub serial_number: Redaction,
+	/// How the `HOSTNAME` reported by apcupsd is exported.
+	pub hostname: Redaction,
+	/// Salt prepended to values before hashing them, so hashes can't be matched against known serial numbers.
+	#[serde(serialize_with = "crate::serialize_redacted")]
+	pub hash_salt: String,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Redaction {
+	/// Export the value as is.
+	#[default]
+	Keep,
+	/// Export a hash of the value, which still distinguishes devices.
+	Hash,
+	/// Don't export the value.
+	Drop,
+}
+
+impl RedactionOptions {
+	/// Redact identifying values before they're used for labels.
+	pub fn redact(&self, apcupsd_data: &mut HashMap<String, String>) {
+		for (key, redaction) in [("SERIALNO", self.serial_number), ("HOSTNAME", self.hostname)] {
+			match redaction {
+				Redaction::Keep => {},
+				Redaction::Hash => {
+					if let Some(value) = apcupsd_data.get_mut(key) {
+						*value = format!("{:x}", Sha256::digest(format!("{}{value}", self.hash_salt)))[..16].to_string();
+					}
+				},
+				Redaction::Drop => {
+					apcupsd_data.remove(key);
+				},
+			}
+		}
+	}
+}
+
+impl Default for LabelOptions {
+	fn default() -> Self {
+		Self {
+			ups_name: "ups_name".to_string(),
+			model: "model".to_string(),
+			serial_number: "serial_number".to_string(),
+			ups_template: None,
+			redact: Default::default(),
+			from_headers: BTreeMap::new(),
+		}
+	}
+}
+
+/// Escape a value for use as a label value in the exposition format, since UPS names and models may contain quotes or backslashes.
+/// Control characters other than newlines (such as stray NULs from firmware) are replaced with U+FFFD.
+pub fn escape_label_value(value: &str) -> String {
+	let mut escaped = String::with_capacity(value.len());
+	for c in value.chars() {
+		match c {
+			'\\' => escaped.push_str("\\\\"),
+			'"' => escaped.push_str("\\\""),
+			'\n' => escaped.push_str("\\n"),
+			c if c.is_control() => escaped.push(char::REPLACEMENT_CHARACTER),
+			c => escaped.push(c),
+		}
+	}
+	escaped
+}
+
+/// Add `labels`, whose values are escaped, before the labels of every sample in `rendered`.
+pub fn inject_labels(rendered: &str, labels: &[(String, String)]) -> String {
+	if labels.is_empty() {
+		return rendered.to_string();
+	}
+	let injected = labels.iter().map(|(name, value)| format!("{name}=\"{}\"", escape_label_value(value))).collect::<Vec<_>>().join(",");
+	let mut output = String::with_capacity(rendered.len());
+	for line in rendered.lines() {
+		if line.is_empty() || line.starts_with('#') {
+			output.push_str(line);
+		} else {
+			// Metric names can't contain `{` or spaces, so whichever comes first ends the name.
+			let name_end = line.find(['{', ' ']).unwrap_or(line.len());
+			let (name, rest) = line.split_at(name_end);
+			match rest.strip_prefix('{') {
+				Some(rest) if rest.starts_with('}') => output.push_str(&format!("{name}{{{injected}{rest}")),
+				Some(rest) => output.push_str(&format!("{name}{{{injected},{rest}")),
+				None => output.push_str(&format!("{name}{{{injected}}}{rest}")),
+			}
+		}
+		output.push('\n');
+	}
+	output
+}
+
+/// A sample of the text exposition format: its name, labels, and value.
+pub type Sample<'a> = (&'a str, Vec<(String, String)>, f64);
+
+/// Parse a sample line of the text exposition format.
+pub fn parse_sample(line: &str) -> Option<Sample<'_>> {
+	if line.starts_with('#') {
+		return None;
+	}
+	let (series, value) = line.rsplit_once(' ')?;
+	let value = value.parse().ok()?;
+	let Some((name, mut rest)) = series.split_once('{') else {
+		return Some((series, Vec::new(), value));
+	};
+	let mut labels = Vec::new();
+	while let Some((key, after_key)) = rest.split_once("=\"") {
+		let mut label_value = String::new();
+		let mut chars = after_key.char_indices();
+		let end = loop {
+			match chars.next()? {
+				(_, '\\') => match chars.next()?.1 {
+					'n' => label_value.push('\n'),
+					c => label_value.push(c),
+				},
+				(i, '"') => break i,
+				(_, c) => label_value.push(c),
+			}
+		};
+		labels.push((key.trim_start_matches(',').to_string(), label_value));
+		rest = &after_key[end + 1..];
+	}
+	Some((name, labels, value))
+}
+
+/// Render a label template as documented for [`LabelOptions::ups_template`], or `None` if a key is missing or the template is malformed.
+pub fn render_template(template: &str, apcupsd_data: &HashMap<String, String>, slug: &str) -> Option<String> {
+	let mut rendered = String::new();
+	let mut rest = template;
+	while let Some(start) = rest.find("{{") {
+		rendered.push_str(&rest[..start]);
+		let (placeholder, after) = rest[start + 2..].split_once("}}")?;
+		rendered.push_str(&placeholder_value(placeholder.trim(), apcupsd_data, slug)?);
+		rest = after;
+	}
+	rendered.push_str(rest);
+	Some(rendered)
+}
+
+fn placeholder_value(placeholder: &str, apcupsd_data: &HashMap<String, String>, slug: &str) -> Option<String> {
+	let (key, slice) = match placeholder.split_once('[') {
+		Some((key, slice)) => (key, Some(slice.strip_suffix(']')?)),
+		None => (placeholder, None),
+	};
+	let value = if key == "slug" { slug } else { apcupsd_data.get(key)? };
+	let Some(slice) = slice else {
+		return Some(value.to_string());
+	};
+
+	// Python style slice, where negative indices count from the end and out of range indices are clamped
+	let chars = value.chars().collect::<Vec<_>>();
+	let index = |index: &str, default: usize| -> Option<usize> {
+		if index.is_empty() {
+			return Some(default);
+		}
+		let index = index.parse::<isize>().ok()?;
+		Some(if index < 0 {
+			chars.len().saturating_sub(index.unsigned_abs())
+		} else {
+			index.unsigned_abs().min(chars.len())
+		})
+	};
+	let (start, end) = slice.split_once(':')?;
+	let (start, end) = (index(start, 0)?, index(end, chars.len())?);
+	Some(chars[start..end.max(start)].iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use super::{escape_label_value, inject_labels, render_template};
+
+	#[test]
+	fn test_render_template() {
+		let apcupsd_data = HashMap::from([
+			("UPSNAME".to_string(), "rack1".to_string()),
+			("SERIALNO".to_string(), "0B2412L30199".to_string()),
+		]);
+		assert_eq!(
+			render_template("{{UPSNAME}}-{{SERIALNO[-4:]}}", &apcupsd_data, "ups0").as_deref(),
+			Some("rack1-0199")
+		);
+		assert_eq!(
+			render_template("{{ slug }}/{{SERIALNO[:4]}}", &apcupsd_data, "ups0").as_deref(),
+			Some("ups0/0B24")
+		);
+		assert_eq!(render_template("{{UPSNAME[2:100]}}", &apcupsd_data, "ups0").as_deref(), Some("ck1"));
+		assert_eq!(render_template("{{UPSNAME[3:1]}}", &apcupsd_data, "ups0").as_deref(), Some(""));
+		assert_eq!(render_template("{{MODEL}}", &apcupsd_data, "ups0"), None);
+		assert_eq!(render_template("{{UPSNAME", &apcupsd_data, "ups0"), None);
+		assert_eq!(render_template("{{UPSNAME[1]}}", &apcupsd_data, "ups0"), None);
+	}
+
+	#[test]
+	fn test_inject_labels() {
+		let rendered = "# HELP apcupsd_up Up.\n# TYPE apcupsd_up gauge\napcupsd_up{exported_ups=\"ups0\"} 1\nbuild_info 1\nempty{} 2\n";
+		let labels = [("site".to_string(), r#"dc "1""#.to_string())];
+		assert_eq!(
+			inject_labels(rendered, &labels),
+			"# HELP apcupsd_up Up.\n# TYPE apcupsd_up gauge\napcupsd_up{site=\"dc \\\"1\\\"\",exported_ups=\"ups0\"} 1\n\
+			 build_info{site=\"dc \\\"1\\\"\"} 1\nempty{site=\"dc \\\"1\\\"\"} 2\n"
+		);
+		assert_eq!(inject_labels(rendered, &[]), rendered);
+	}
+
+	#[test]
+	fn test_escape_label_value() {
+		assert_eq!(escape_label_value("Back-UPS XS 1500M"), "Back-UPS XS 1500M");
+		assert_eq!(escape_label_value(r#"rack "A""#), r#"rack \"A\""#);
+		assert_eq!(escape_label_value(r"C:\UPS\"), r"C:\\UPS\\");
+		assert_eq!(escape_label_value("line1\nline2"), r"line1\nline2");
+		assert_eq!(escape_label_value(r#"\""#), r#"\\\""#);
+		assert_eq!(escape_label_value("ups\0\r\x1b[31m"), "ups\u{fffd}\u{fffd}\u{fffd}[31m");
+		assert_eq!(escape_label_value("caf\u{e9} \u{fffd}"), "caf\u{e9} \u{fffd}");
+	}
+}
@@ -0,0 +1,3707 @@
+use std::{
+	collections::{hash_map::RandomState, HashMap, HashSet},
+	env, fs,
+	hash::{BuildHasher, Hasher},
+	net::{IpAddr, SocketAddr, ToSocketAddrs},
+	ops::BitAnd,
+	path::{Path, PathBuf},
+	process::ExitCode,
+	sync::{
+		atomic::{AtomicBool, AtomicU64, Ordering},
+		Arc, PoisonError,
+	},
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use archive::ArchiveOptions;
+use chrono::{DateTime, NaiveDate, NaiveTime};
+use clap::{Parser, Subcommand};
+use computed::ComputedMetric;
+use daemon_version::{DaemonVersion, DateFormat};
+use dns::{DnsOptions, ResolvedName};
+use futures::future::join_all;
+pub use grafana::GrafanaArgs;
+use held_values::HeldValues;
+use history::{History, HistoryOptions};
+use history_store::{HistoryStore, HistoryStoreOptions, HostHistoryStore};
+use http_body_util::Full;
+use hyper::{
+	body::{Bytes, Incoming},
+	header::{HeaderValue, CONTENT_TYPE, RETRY_AFTER},
+	HeaderMap, Request, Response, StatusCode,
+};
+use journal::{JournalEvents, JournalOptions};
+pub use labels::LabelOptions;
+use model_families::ModelFamily;
+pub use nagios::CheckArgs;
+use nis::{FetchTimings, NisClient};
+use notifications::{HostNotifier, NotificationOptions, Notifier};
+use num::{Num, Unsigned};
+use otlp::OtlpOptions;
+use prometheus_exporter_base::{prelude::Authorization, MetricType, MissingValue, PrometheusInstance, PrometheusMetric};
+use runtime_degradation::{HostRuntimeBaselines, RuntimeBaselines, RuntimeDegradationOptions};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use server::HttpServerOptions;
+use shared_cache::{SharedCache, SharedCacheOptions};
+use ssh_tunnel::SshTunnelOptions;
+use statistics::{PollStatistics, PollStatisticsOptions};
+use status_file::StatusFileOptions;
+use syslog::SyslogOptions;
+use telemetry::TracingOptions;
+use thiserror::Error;
+use tls::TlsOptions;
+use tokio::{
+	sync::{mpsc, oneshot, Mutex},
+	task::spawn_blocking,
+};
+use ups_commands::UpsCommandOptions;
+use zabbix::ZabbixOptions;
+
+pub use config::ConfigError;
+
+mod admin;
+mod apcupsd_bitmasks;
+mod archive;
+#[allow(dead_code)]
+mod built_info {
+	include!(concat!(env!("OUT_DIR"), "/built.rs"));
+}
+mod apcupsd_conf;
+mod capture;
+mod computed;
+mod config;
+mod daemon_version;
+mod default_config;
+mod dns;
+mod grafana;
+mod held_values;
+mod history;
+mod history_store;
+mod http_client;
+mod journal;
+mod labels;
+mod mock;
+mod model_families;
+mod nagios;
+mod nis;
+mod notifications;
+mod otlp;
+mod persistence;
+mod protobuf;
+mod proxy;
+mod runtime_degradation;
+mod server;
+mod shared_cache;
+mod simulate;
+mod site;
+mod ssh_tunnel;
+mod statistics;
+mod status_file;
+mod status_page;
+mod syslog;
+mod telemetry;
+mod tls;
+mod ups_commands;
+mod zabbix;
+
+/// Prometheus exporter for apcupsd.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+	/// Print a JSON Schema for the configuration file and exit.
+	#[arg(long)]
+	pub print_config_schema: bool,
+	/// Print a commented configuration file with every option at its default and exit.
+	#[arg(long)]
+	pub print_default_config: bool,
+	/// Serve these apcupsd status files from mock NIS servers and export them instead of the configured hosts.
+	#[arg(long, num_args = 1.., value_name = "STATUS_FILE")]
+	pub mock: Vec<PathBuf>,
+	/// Export a simulated UPS going through an outage instead of the configured hosts, following the scenario in this YAML file if given.
+	#[arg(long, value_name = "SCENARIO_FILE", conflicts_with = "mock")]
+	pub simulate: Option<Option<PathBuf>>,
+	/// Allow reloading the configuration with `POST /-/reload` and shutting down with `POST /-/quit` on the admin listener, like
+	/// Prometheus' flag of the same name.
+	#[arg(long = "web.enable-lifecycle")]
+	pub web_enable_lifecycle: bool,
+	#[command(subcommand)]
+	pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+	/// Save the status output of a configured host in the format of the files in tests/*_examples.
+	Capture {
+		/// Slug of the host to capture; defaults to the first configured host.
+		#[arg(long)]
+		target: Option<String>,
+		/// Replace the serial number and hostname with placeholders.
+		#[arg(long)]
+		anonymize: bool,
+		/// File to save the status output to; defaults to stdout.
+		#[arg(long, short)]
+		output: Option<PathBuf>,
+	},
+	/// Render metrics from a saved status file using the configured options and print them, for reproducing parse issues
+	/// without access to the UPS.
+	Render {
+		/// Status file in the format of the files in tests/*_examples, e.g. saved with `apcaccess status` or `capture`.
+		#[arg(long, short)]
+		input: PathBuf,
+		/// Slug to export the status as; defaults to the file name without its extension.
+		#[arg(long)]
+		slug: Option<String>,
+	},
+	/// Check a configured host against thresholds like a Nagios/Icinga plugin, printing a summary with performance data and exiting with
+	/// 0 (OK), 1 (WARNING), 2 (CRITICAL), or 3 (UNKNOWN).
+	Check(CheckArgs),
+	/// Push a dashboard for the exporter's metrics, using the configured label names and percent scale, to Grafana's HTTP API.
+	ProvisionGrafana(GrafanaArgs),
+}
+
+/// Why the exporter couldn't start or stopped, so embedders and the exit status can tell problems with the configuration from ones
+/// outside of the exporter's control.
+#[derive(Error, Debug)]
+pub enum ExporterError {
+	/// The configuration, or a file or argument it refers to, is invalid.
+	#[error(transparent)]
+	Config(#[from] ConfigError),
+	/// apcupsd or another service couldn't be reached.
+	#[error("network error: {0}")]
+	Network(Box<dyn std::error::Error>),
+	/// Metrics couldn't be rendered.
+	#[error(transparent)]
+	Render(#[from] RenderMetricsError),
+	/// A listener or background task couldn't be started, or output couldn't be written.
+	#[error("server error: {0}")]
+	Server(Box<dyn std::error::Error>),
+}
+
+impl ExporterError {
+	fn config(error: impl ToString) -> Self {
+		Self::Config(ConfigError::from(error.to_string()))
+	}
+
+	fn server(error: impl Into<Box<dyn std::error::Error>>) -> Self {
+		Self::Server(error.into())
+	}
+
+	/// Exit status for this error, from sysexits.h where one fits.
+	pub fn exit_code(&self) -> u8 {
+		match self {
+			// EX_CONFIG
+			Self::Config(_) => 78,
+			// EX_UNAVAILABLE
+			Self::Network(_) => 69,
+			// EX_DATAERR
+			Self::Render(_) => 65,
+			Self::Server(_) => 1,
+		}
+	}
+}
+
+/// Load the configuration and serve metrics until the server stops, returning the exit status to use, which subcommands like `check` set.
+pub async fn run(cli: Cli) -> Result<ExitCode, ExporterError> {
+	if cli.print_config_schema {
+		println!(
+			"{}",
+			serde_json::to_string_pretty(&schemars::schema_for!(ApcupsdExporterOptions)).map_err(ExporterError::server)?
+		);
+		return Ok(ExitCode::SUCCESS);
+	}
+	if cli.print_default_config {
+		print!("{}", default_config::render().map_err(ExporterError::server)?);
+		return Ok(ExitCode::SUCCESS);
+	}
+
+	let config_path = env::var("CONFIG_PATH").unwrap_or("/etc/prometheus/apcupsd_exporter_config.yaml".to_owned());
+	let server_options = load_options(&config_path)?;
+	let render_options = render_options(&server_options);
+
+	if let Some(Command::Render { input, slug }) = &cli.command {
+		let status = fs::read_to_string(input).map_err(|e| ExporterError::config(format!("{}: {e}", input.display())))?;
+		let mut apcupsd_data = capture::parse_status(&status);
+		render_options.labels.redact.redact(&mut apcupsd_data);
+		let slug = slug.clone().unwrap_or_else(|| input.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default());
+		let ups_label =
+			render_options.labels.ups_template.as_ref().and_then(|template| labels::render_template(template, &apcupsd_data, &slug)).unwrap_or(slug);
+		let ups_label = labels::escape_label_value(&ups_label);
+		let rendered = render_metrics(apcupsd_data, ups_label, &render_options)?;
+		check_metric_families(&rendered).map_err(ExporterError::config)?;
+		print!("{rendered}");
+		return Ok(ExitCode::SUCCESS);
+	}
+
+	tls::install_default_provider();
+	if let Some(Command::ProvisionGrafana(grafana_args)) = &cli.command {
+		let dashboard = grafana::dashboard(grafana_args, &render_options);
+		if grafana_args.print {
+			println!("{}", serde_json::to_string_pretty(&dashboard).map_err(ExporterError::server)?);
+		} else {
+			let url = grafana::provision(grafana_args, dashboard).await.map_err(|e| ExporterError::Network(e.to_string().into()))?;
+			eprintln!("Provisioned dashboard at {url}");
+		}
+		return Ok(ExitCode::SUCCESS);
+	}
+	if let Some(tracing_options) = &server_options.tracing {
+		telemetry::init(tracing_options).map_err(ExporterError::config)?;
+	}
+	if let Some(syslog_options) = &server_options.syslog {
+		syslog::init(syslog_options).map_err(|e| ExporterError::config(format!("syslog: {e}")))?;
+	}
+
+	// Hosts from the command line replace the configured ones, and stay the same when the configuration is reloaded.
+	let mut fixed_hosts = None;
+	if !cli.mock.is_empty() {
+		let mut mock_hosts = Vec::with_capacity(cli.mock.len());
+		for path in &cli.mock {
+			let status = fs::read_to_string(path).map_err(|e| ExporterError::config(format!("{}: {e}", path.display())))?;
+			let address = mock::spawn(status).await.map_err(ExporterError::server)?;
+			mock_hosts.push(HostSpecificOptions {
+				address: address.ip().to_string(),
+				port: address.port(),
+				slug: path.file_stem().map(|stem| stem.to_string_lossy().into_owned()),
+				..Default::default()
+			});
+		}
+		fixed_hosts = Some(mock_hosts);
+	}
+	if let Some(scenario_path) = &cli.simulate {
+		let scenario = match scenario_path {
+			Some(path) => fs::read_to_string(path)
+				.map_err(|e| e.to_string())
+				.and_then(|scenario| serde_yaml::from_str(&scenario).map_err(|e| e.to_string()))
+				.map_err(|e| ExporterError::config(format!("{}: {e}", path.display())))?,
+			None => simulate::Scenario::default(),
+		};
+		let simulated_ups = simulate::SimulatedUps::new(scenario);
+		let address = mock::spawn_generated(move || simulated_ups.status()).await.map_err(ExporterError::server)?;
+		fixed_hosts = Some(vec![HostSpecificOptions {
+			address: address.ip().to_string(),
+			port: address.port(),
+			slug: Some("simulated".to_string()),
+			..Default::default()
+		}]);
+	}
+	let configured_hosts = configured_hosts(fixed_hosts.as_ref(), &server_options)?;
+
+	if let Some(Command::Capture { target, anonymize, output }) = cli.command {
+		let host_options = find_host_options(&configured_hosts, target.as_deref())?;
+		let mut status = capture::fetch_status(&host_options.address, host_options.port).await.map_err(|e| ExporterError::Network(e.into()))?;
+		if anonymize {
+			status = capture::anonymize(&status);
+		}
+		match output {
+			Some(path) => fs::write(path, status).map_err(ExporterError::server)?,
+			None => print!("{status}"),
+		}
+		return Ok(ExitCode::SUCCESS);
+	}
+	if let Some(Command::Check(check_args)) = cli.command {
+		let host_options = find_host_options(&configured_hosts, check_args.target.as_deref())?;
+		let (state, output) = match capture::fetch_status(&host_options.address, host_options.port).await {
+			Ok(status) => nagios::evaluate(&check_args, &capture::parse_status(&status)),
+			Err(e) => nagios::unknown(format!("failed to fetch from {}:{}: {e}", host_options.address, host_options.port)),
+		};
+		println!("{output}");
+		return Ok(ExitCode::from(state as u8));
+	}
+
+	let exporter = SharedExporter::new(Exporter::new(&server_options, configured_hosts, render_options, None).await?);
+	let (reload_sender, mut reload_requests) = mpsc::channel::<ReloadRequest>(1);
+	let (quit_sender, mut quit_requests) = mpsc::channel::<()>(1);
+	#[cfg(unix)]
+	spawn_hangup_handler(reload_sender.clone()).map_err(ExporterError::server)?;
+	if let Some(admin_address) = server_options.admin_address {
+		let password = match (&server_options.admin_authorization, &server_options.authorization) {
+			(Authorization::Basic(password), _) | (Authorization::None, Authorization::Basic(password)) => Some(password.clone()),
+			(Authorization::None, Authorization::None) => None,
+		};
+		let client_certificates = server_options.tls_options.as_ref().is_some_and(|tls_options| tls_options.client_certificate_ca_file.is_some());
+		if password.is_none() && !client_certificates && !server_options.admin_allow_unauthenticated {
+			let hint = "set admin_authorization or tls_options.client_certificate_ca_file, or admin_allow_unauthenticated if it's firewalled";
+			return Err(ExporterError::config(format!(
+				"admin_address would serve every host's data and configuration to anyone, {hint}"
+			)));
+		}
+		// Client certificates identify machines, which shouldn't be able to stop the exporter just by being allowed to read from it.
+		if cli.web_enable_lifecycle && password.is_none() && !admin_address.ip().is_loopback() {
+			let hint = "set admin_authorization, or bind admin_address to a loopback address";
+			let message = format!("--web.enable-lifecycle would let anyone who can reach admin_address reload or stop the exporter, {hint}");
+			return Err(ExporterError::config(message));
+		}
+		// The ups_commands password would otherwise be sent in the clear with every action.
+		if server_options.ups_commands.is_some() && server_options.tls_options.is_none() {
+			return Err(ExporterError::config(
+				"ups_commands requires tls_options, so its password isn't sent in the clear",
+			));
+		}
+		let state = admin::AdminState {
+			password,
+			exporter: exporter.clone(),
+			metrics_address: server_options.address,
+			metrics_path: server_options.metrics_path.clone(),
+			tls: server_options.tls_options.is_some(),
+			ups_commands: server_options.ups_commands.clone(),
+			reload_requests: cli.web_enable_lifecycle.then(|| reload_sender.clone()),
+			quit_requests: cli.web_enable_lifecycle.then(|| quit_sender.clone()),
+		};
+		admin::spawn(admin_address, server_options.tls_options.as_ref(), &server_options.http_server, state).await.map_err(ExporterError::server)?;
+	}
+	log_startup_probe(&exporter.current().hosts).await;
+	if let Some(otlp_options) = server_options.otlp.clone() {
+		otlp::spawn(otlp_options, exporter.clone()).map_err(ExporterError::config)?;
+	}
+	if let Some(archive_options) = server_options.archive.clone() {
+		archive::spawn(archive_options, exporter.clone()).map_err(ExporterError::config)?;
+	}
+	if let Some(zabbix_options) = server_options.zabbix.clone() {
+		zabbix::spawn(zabbix_options, exporter.clone()).map_err(ExporterError::config)?;
+	}
+
+	let reloader = Reloader {
+		config_path,
+		fixed_hosts,
+		exporter: exporter.clone(),
+	};
+	// Reloads run here rather than where they're requested, so the server doesn't have to wait for them.
+	let reloads = async {
+		while let Some(respond) = reload_requests.recv().await {
+			let result = reloader.reload().await.map_err(|e| e.to_string());
+			match &result {
+				Ok(()) => eprintln!("Reloaded configuration from {}", reloader.config_path),
+				Err(e) => syslog::error(format!("Failed to reload configuration, keeping the current one: {e}")),
+			}
+			// Nobody waits for the result of a reload requested with SIGHUP.
+			let _ = respond.send(result);
+		}
+	};
+	let metrics_listener = Arc::new(MetricsListener {
+		password: match &server_options.authorization {
+			Authorization::Basic(password) => Some(password.clone()),
+			Authorization::None => None,
+		},
+		metrics_path: server_options.metrics_path.clone(),
+		per_host_paths: server_options.per_host_paths,
+	});
+	let tls_options = server_options.tls_options.as_ref();
+	let server = server::spawn(
+		"Metrics server",
+		server_options.address,
+		tls_options,
+		&server_options.http_server,
+		move |request| {
+			let metrics_listener = metrics_listener.clone();
+			let exporter = exporter.current();
+			async move { metrics_listener.serve(request, &exporter).await }
+		},
+	)
+	.await
+	.map_err(ExporterError::server)?;
+	tokio::select! {
+		_ = server => {},
+		() = reloads => {},
+		Some(()) = quit_requests.recv() => {
+			eprintln!("Shutting down as requested with /-/quit");
+			// Give the response to the request a moment to be sent.
+			tokio::time::sleep(Duration::from_millis(100)).await;
+		},
+	}
+	// Kept until here so `reloads` and `quit_requests` never run out of senders.
+	drop((reload_sender, quit_sender));
+
+	Ok(ExitCode::SUCCESS)
+}
+
+/// Options of the listener serving `/metrics`, which only change on restart.
+struct MetricsListener {
+	/// Password required with HTTP basic authentication, unless tenants are configured.
+	password: Option<String>,
+	metrics_path: String,
+	per_host_paths: bool,
+}
+
+impl MetricsListener {
+	/// Answer a request for the metrics of every host the request may see at `metrics_path`, or of a single host with
+	/// `?target=<slug>` or, if enabled, at `<metrics_path>/<slug>`.
+	async fn serve(&self, request: Request<Incoming>, exporter: &Exporter) -> Response<Full<Bytes>> {
+		if let Some(password) = &self.password {
+			if !server::authorized(request.headers(), password) {
+				return server::unauthorized();
+			}
+		}
+		let path = request.uri().path();
+		let target = if path == self.metrics_path {
+			query_parameter(request.uri().query(), "target")
+		} else {
+			let host_path_prefix = format!("{}/", self.metrics_path.trim_end_matches('/'));
+			match path.strip_prefix(&host_path_prefix) {
+				Some(slug) if self.per_host_paths && !slug.is_empty() && !slug.contains('/') => Some(slug.to_string()),
+				_ => return server::text_response(StatusCode::NOT_FOUND, "not found\n".to_string()),
+			}
+		};
+		match scrape(request.headers(), target, exporter).await {
+			Ok(rendered) if protobuf::preferred(request.headers()) => {
+				let mut response = Response::new(Full::new(Bytes::from(protobuf::encode(&rendered))));
+				response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(protobuf::CONTENT_TYPE));
+				response
+			},
+			Ok(rendered) => {
+				let mut response = Response::new(Full::new(Bytes::from(rendered)));
+				response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"));
+				response
+			},
+			Err(ScrapeError::Unauthorized) => server::unauthorized(),
+			Err(e @ ScrapeError::UnknownTarget(_)) => server::error_response(StatusCode::NOT_FOUND, request.headers(), &e.to_string()),
+			Err(e @ ScrapeError::Render(_)) => {
+				let mut response = server::error_response(StatusCode::SERVICE_UNAVAILABLE, request.headers(), &e.to_string());
+				response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(RENDER_RETRY_AFTER_SECONDS));
+				response
+			},
+		}
+	}
+}
+
+/// Seconds after which a scrape that failed to render may be retried, which is only likely to help once the configuration was reloaded.
+const RENDER_RETRY_AFTER_SECONDS: u32 = 30;
+
+/// Why a scrape couldn't be answered with metrics.
+#[derive(Error, Debug)]
+enum ScrapeError {
+	/// Tenants are configured and the request doesn't have the credentials of one.
+	#[error("unauthorized")]
+	Unauthorized,
+	#[error("unknown target \"{0}\"")]
+	UnknownTarget(String),
+	#[error("{0}")]
+	Render(String),
+}
+
+/// Render the metrics of the hosts the tenant whose credentials are in `headers` may see, or only `target` if given.
+async fn scrape(headers: &HeaderMap, target: Option<String>, exporter: &Exporter) -> Result<String, ScrapeError> {
+	let tenant_hosts = if exporter.tenants.is_empty() {
+		None
+	} else {
+		match exporter.tenants.iter().find(|tenant| server::authorized(headers, &tenant.basic)) {
+			Some(tenant) => Some(&tenant.hosts),
+			None => return Err(ScrapeError::Unauthorized),
+		}
+	};
+	let visible_hosts = exporter.hosts.iter().filter(|host| tenant_hosts.is_none_or(|hosts| hosts.contains(&host.slug)));
+	if let Some(target) = &target {
+		if !visible_hosts.clone().any(|host| host.slug == *target) {
+			return Err(ScrapeError::UnknownTarget(target.clone()));
+		}
+	}
+
+	let selected_hosts = visible_hosts.filter(|host| target.as_ref().is_none_or(|target| *target == host.slug));
+	let (rendered, reachable): (Vec<_>, Vec<_>) =
+		join_all(selected_hosts.clone().map(|host| host.render(&exporter.render_options))).await.into_iter().unzip();
+	let mut rendered =
+		rendered.concat() + &render_target_counts(reachable.len(), reachable.iter().filter(|reachable| **reachable).count()) + &render_build_info();
+	if exporter.render_options.site_rollups {
+		// The data each host was just rendered from, without fetching again.
+		let snapshots = join_all(selected_hosts.clone().map(|host| host.access.peek())).await;
+		rendered += &site::render_rollups(snapshots.iter().flatten().map(|snapshot| &snapshot.data));
+	}
+	if let Some(journal) = exporter.journal.as_ref().filter(|journal| selected_hosts.clone().any(|host| host.slug == journal.slug)) {
+		rendered += &journal.render();
+	}
+	let header_labels: Vec<_> = exporter
+		.render_options
+		.labels
+		.from_headers
+		.iter()
+		.filter_map(|(header, label)| Some((label.clone(), headers.get(header)?.to_str().ok()?.to_string())))
+		.collect();
+	let rendered = group_metric_families(&rendered).map_err(ScrapeError::Render)?;
+	Ok(labels::inject_labels(&rendered, &header_labels))
+}
+
+/// The options of the host with slug `target`, or the first host if no target is given.
+fn find_host_options<'a>(configured_hosts: &'a [HostSpecificOptions], target: Option<&str>) -> Result<&'a HostSpecificOptions, ExporterError> {
+	configured_hosts
+		.iter()
+		.enumerate()
+		.find(|(host_index, host_options)| target.is_none_or(|target| host_options.slug(*host_index) == target))
+		.map(|(_, host_options)| host_options)
+		.ok_or_else(|| ExporterError::config(format!("unknown target \"{}\"", target.unwrap_or_default())))
+}
+
+/// Load the configuration file at `config_path`, or the defaults if it doesn't exist.
+fn load_options(config_path: &str) -> Result<ApcupsdExporterOptions, ConfigError> {
+	if !fs::exists(config_path).map_err(|e| format!("{config_path}: {e}"))? {
+		return Ok(Default::default());
+	}
+	let (config, sources) = config::load(Path::new(config_path))?;
+	let (options, unknown_keys): (ApcupsdExporterOptions, _) = config::deserialize(config, &sources)?;
+	for path in unknown_keys {
+		eprintln!("Ignoring unknown configuration key {path}");
+	}
+	// Checked here so startup and reloads both refuse it before anything is started or replaced.
+	if !options.metrics_path.starts_with('/') {
+		return Err(format!("metrics_path {:?} must start with /", options.metrics_path).into());
+	}
+	Ok(options)
+}
+
+/// Number of times render options were created from the configuration, to tell the options of each reload apart.
+static RENDER_OPTIONS_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+fn render_options(server_options: &ApcupsdExporterOptions) -> RenderOptions {
+	RenderOptions {
+		generation: RENDER_OPTIONS_GENERATION.fetch_add(1, Ordering::Relaxed) + 1,
+		status_metrics: server_options.status_metrics,
+		labels: server_options.labels.clone(),
+		hold_last_value_seconds: server_options.hold_last_value_seconds.clone(),
+		special_values: server_options.special_values.clone(),
+		unitless_keys: server_options.unitless_keys.clone(),
+		metric_overrides: server_options.metric_overrides.clone(),
+		plausible_ranges: server_options.plausible_ranges.clone(),
+		model_families: server_options.model_families.iter().cloned().chain(model_families::builtin()).collect(),
+		percent_scale: server_options.percent_scale,
+		timestamp_unit: server_options.timestamp_unit,
+		sample_timestamps: server_options.sample_timestamps,
+		precision: server_options.precision,
+		clock_skew_warning_seconds: server_options.clock_skew_warning_seconds,
+		computed: server_options.computed.clone(),
+		site_rollups: server_options.site_rollups,
+	}
+}
+
+/// The hosts to export: `fixed_hosts` from the command line if given, otherwise the configured hosts, falling back to the one in
+/// `apcupsd_conf` or the default.
+fn configured_hosts(
+	fixed_hosts: Option<&Vec<HostSpecificOptions>>,
+	server_options: &ApcupsdExporterOptions,
+) -> Result<Vec<HostSpecificOptions>, ConfigError> {
+	if let Some(fixed_hosts) = fixed_hosts {
+		return Ok(fixed_hosts.clone());
+	}
+	if !server_options.hosts.is_empty() {
+		return Ok(server_options.hosts.clone());
+	}
+	Ok(vec![match &server_options.apcupsd_conf {
+		Some(path) => apcupsd_conf::discover(path).map_err(|e| format!("{}: {e}", path.display()))?,
+		None => HostSpecificOptions::default(),
+	}])
+}
+
+/// The hosts and options that are replaced when the configuration is reloaded.
+struct Exporter {
+	hosts: Vec<Host>,
+	/// The options each of `hosts` was created from, before any changes like a probed port.
+	configured_hosts: Vec<HostSpecificOptions>,
+	host_defaults: HostDefaults,
+	render_options: RenderOptions,
+	tenants: Vec<Tenant>,
+	/// The effective configuration with secrets redacted, as YAML.
+	effective_config: String,
+	/// Read from startup on like `history_store`, so counts survive reloads.
+	journal: Option<Arc<JournalEvents>>,
+}
+
+impl Exporter {
+	/// Create the hosts in `configured_hosts`, reusing those of `previous` whose options haven't changed so they keep their connections
+	/// and state.
+	async fn new(
+		server_options: &ApcupsdExporterOptions,
+		configured_hosts: Vec<HostSpecificOptions>,
+		render_options: RenderOptions,
+		previous: Option<&Exporter>,
+	) -> Result<Self, ExporterError> {
+		if !server_options.tenants.is_empty() && !matches!(server_options.authorization, Authorization::None) {
+			return Err(ExporterError::config(
+				"tenants can't be combined with authorization, which would be checked first",
+			));
+		}
+		// Any tenant could otherwise read every host's data from the admin endpoints.
+		if !server_options.tenants.is_empty() && server_options.admin_address.is_some() {
+			if let Authorization::None = server_options.admin_authorization {
+				return Err(ExporterError::config(
+					"admin_address requires admin_authorization when tenants are configured",
+				));
+			}
+		}
+		if let Some(ups_commands) = &server_options.ups_commands {
+			ups_commands.timeout().map_err(|e| ExporterError::config(format!("ups_commands: timeout_seconds: {e}")))?;
+		}
+		// Checked here so a conflict is found at startup or on reload, instead of failing scrapes.
+		check_configured_metrics(&render_options).map_err(ExporterError::config)?;
+		let mut effective_config = serde_yaml::to_value(server_options).map_err(ExporterError::server)?;
+		effective_config["hosts"] = serde_yaml::to_value(&configured_hosts).map_err(ExporterError::server)?;
+
+		let history_store = match previous {
+			Some(previous) => previous.host_defaults.history_store.clone(),
+			None => match &server_options.history_store {
+				Some(options) => Some(Arc::new(
+					HistoryStore::open(options).map_err(|e| ExporterError::config(format!("history_store: {e}")))?,
+				)),
+				None => None,
+			},
+		};
+		let notifier = match previous {
+			Some(previous) => previous.host_defaults.notifier.clone(),
+			None => match &server_options.notifications {
+				Some(options) => Some(Arc::new(
+					Notifier::new(options).map_err(|e| ExporterError::config(format!("notifications: {e}")))?,
+				)),
+				None => None,
+			},
+		};
+		let runtime_baselines = match previous {
+			Some(previous) => previous.host_defaults.runtime_baselines.clone(),
+			None => match &server_options.runtime_degradation {
+				Some(options) => Some(Arc::new(
+					RuntimeBaselines::load(options.clone()).map_err(|e| ExporterError::config(format!("runtime_degradation: {e}")))?,
+				)),
+				None => None,
+			},
+		};
+		let journal = match previous {
+			Some(previous) => previous.journal.clone(),
+			None => server_options.journal.clone().map(journal::spawn),
+		};
+		let host_defaults = HostDefaults::new(server_options, history_store, notifier, runtime_baselines);
+		let previous = previous.filter(|previous| previous.host_defaults == host_defaults);
+		let mut hosts = Vec::with_capacity(configured_hosts.len());
+		for (host_index, host_options) in configured_hosts.iter().enumerate() {
+			let slug = host_options.slug(host_index);
+			let unchanged = previous.and_then(|previous| {
+				let mut previous_hosts = previous.configured_hosts.iter().zip(&previous.hosts);
+				let (_, host) = previous_hosts.find(|(options, host)| *options == host_options && host.slug == slug)?;
+				Some(host.clone())
+			});
+			hosts.push(match unchanged {
+				Some(host) => host,
+				None => Host::new(host_options.clone(), host_index, &host_defaults).await?,
+			});
+		}
+		Ok(Self {
+			hosts,
+			configured_hosts,
+			host_defaults,
+			render_options,
+			tenants: server_options.tenants.clone(),
+			effective_config: serde_yaml::to_string(&effective_config).map_err(ExporterError::server)?,
+			journal,
+		})
+	}
+}
+
+/// The current [`Exporter`], replaced as a whole on reload so scrapes in progress finish with the one they started with.
+#[derive(Clone)]
+struct SharedExporter(Arc<std::sync::RwLock<Arc<Exporter>>>);
+
+impl SharedExporter {
+	fn new(exporter: Exporter) -> Self {
+		Self(Arc::new(std::sync::RwLock::new(Arc::new(exporter))))
+	}
+
+	fn current(&self) -> Arc<Exporter> {
+		self.0.read().unwrap_or_else(PoisonError::into_inner).clone()
+	}
+
+	fn replace(&self, exporter: Exporter) {
+		*self.0.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(exporter);
+	}
+}
+
+/// A request to reload the configuration, answered with whether it worked.
+type ReloadRequest = oneshot::Sender<Result<(), String>>;
+
+/// Replaces the [`Exporter`] with one created from the configuration file. Listener addresses, paths, and settings, authorization, TLS,
+/// `ups_commands`, `otlp`, `archive`, `zabbix`, `history_store`, `notifications`, `tracing`, `syslog`, `journal`, and `runtime_degradation`
+/// only change on restart.
+struct Reloader {
+	config_path: String,
+	fixed_hosts: Option<Vec<HostSpecificOptions>>,
+	exporter: SharedExporter,
+}
+
+impl Reloader {
+	async fn reload(&self) -> Result<(), ExporterError> {
+		let server_options = load_options(&self.config_path)?;
+		let previous = self.exporter.current();
+		let render_options = render_options(&server_options);
+		let configured_hosts = configured_hosts(self.fixed_hosts.as_ref(), &server_options)?;
+		let exporter = Exporter::new(&server_options, configured_hosts, render_options, Some(&previous)).await?;
+		self.exporter.replace(exporter);
+		Ok(())
+	}
+}
+
+/// Request a reload of the configuration on every SIGHUP.
+#[cfg(unix)]
+fn spawn_hangup_handler(reload_requests: mpsc::Sender<ReloadRequest>) -> std::io::Result<()> {
+	let mut hangups = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+	tokio::spawn(async move {
+		while hangups.recv().await.is_some() {
+			let (respond, _) = oneshot::channel();
+			if reload_requests.send(respond).await.is_err() {
+				break;
+			}
+		}
+	});
+	Ok(())
+}
+
+/// Fetch from every host concurrently and log whether it worked, so misconfigured hosts are noticed at startup instead of on the first
+/// scrape.
+async fn log_startup_probe(hosts: &[Host]) {
+	let results = join_all(hosts.iter().map(|host| host.access.fetch())).await;
+	let targets: Vec<_> = hosts.iter().map(|host| format!("{} ({}:{})", host.slug, host.options.address, host.options.port)).collect();
+	let width = targets.iter().map(String::len).max().unwrap_or_default();
+	for (target, result) in targets.iter().zip(results) {
+		match result {
+			Ok(snapshot) => {
+				let key = |key: &str| snapshot.data.get(key).map_or("-", String::as_str);
+				eprintln!(
+					"{target:width$} OK   driver {}, model {}, firmware {}",
+					key("DRIVER"),
+					key("MODEL"),
+					key("FIRMWARE")
+				);
+			},
+			Err(e) => syslog::warning(format!("{target:width$} FAIL {e}")),
+		}
+	}
+}
+
+/// Group the metric families rendered for every host so each family's `# HELP` and `# TYPE` appear once, followed by the samples of
+/// every host, as the text exposition format requires. Families keep the order they first appear in, and other comments (such as the
+/// errors of unreachable hosts) come first. Fails if a family is rendered with conflicting help texts or types, since Prometheus rejects
+/// scrapes with those, e.g. when `metric_overrides` or `computed` reuse the name of a built in metric.
+fn group_metric_families(rendered: &str) -> Result<String, String> {
+	let mut comments = String::new();
+	let mut families: Vec<String> = Vec::new();
+	let mut family_indices = HashMap::new();
+	let mut seen_metadata: HashMap<(&str, &str), &str> = HashMap::new();
+	let mut current_family = None;
+	for line in rendered.lines() {
+		let metadata = match (line.strip_prefix("# HELP "), line.strip_prefix("# TYPE ")) {
+			(Some(help), _) => Some(("help text", help)),
+			(_, Some(metric_type)) => Some(("type", metric_type)),
+			(None, None) => None,
+		};
+		if let Some((kind, metadata)) = metadata {
+			let (name, value) = metadata.split_once(' ').unwrap_or((metadata, ""));
+			let family = *family_indices.entry(name).or_insert_with(|| {
+				families.push(String::new());
+				families.len() - 1
+			});
+			current_family = Some(family);
+			match seen_metadata.get(&(name, kind)) {
+				Some(first) if *first != value => {
+					let hint = "rename one with metric_overrides or computed";
+					return Err(format!(
+						"metric {name} would be exported with conflicting {kind}s \"{first}\" and \"{value}\", {hint}"
+					));
+				},
+				Some(_) => continue,
+				None => {
+					seen_metadata.insert((name, kind), value);
+				},
+			}
+		}
+		let output = match current_family {
+			Some(family) if !line.starts_with('#') || line.starts_with("# HELP ") || line.starts_with("# TYPE ") => &mut families[family],
+			_ => &mut comments,
+		};
+		*output += line;
+		output.push('\n');
+	}
+	Ok(comments + &families.concat())
+}
+
+/// Check that every metric family has a single help text and type.
+fn check_metric_families(rendered: &str) -> Result<(), String> {
+	group_metric_families(rendered).map(|_| ())
+}
+
+/// Statuses of UPSes that between them report every key the exporter knows, the first one of which reporting a key is used.
+const SAMPLE_STATUSES: [&str; 7] = [
+	include_str!("../tests/apcupsd_examples/SmartUPS1400.status"),
+	include_str!("../tests/apcupsd_examples/SmartUPS5000.status"),
+	include_str!("../tests/apcupsd_examples/Back-UPS-Pro-1000.status"),
+	include_str!("../tests/apcupsd_examples/BackUPS-USB.status"),
+	include_str!("../tests/apcupsd_examples/newbackupspro1.status"),
+	include_str!("../tests/apcupsd_examples/PowerStack450.status"),
+	include_str!("../tests/user_examples/Back-UPS-XS-1500M_1.status"),
+];
+
+/// A status with every key the exporter knows, combined from [`SAMPLE_STATUSES`].
+fn sample_status() -> HashMap<String, String> {
+	let mut status = HashMap::new();
+	for sample in SAMPLE_STATUSES.iter().rev() {
+		status.extend(capture::parse_status(sample));
+	}
+	status
+}
+
+/// Check that the configured `metric_overrides` and `computed` metrics don't conflict with the built in metrics or each other, by
+/// rendering [`sample_status`] with them along with every computed metric, whether or not the sample has the keys it's computed from.
+fn check_configured_metrics(render_options: &RenderOptions) -> Result<(), String> {
+	let mut rendered = render_metrics(sample_status(), "sample".to_string(), render_options)
+		.map_err(|e| format!("metrics can't be rendered with the configured options: {e}"))?;
+	for metric in &render_options.computed {
+		rendered += &PrometheusMetric::build()
+			.with_name(&format!("apcupsd_{}", metric.name))
+			.with_help(metric.help.as_deref().unwrap_or(metric.expr.source()))
+			.with_metric_type(MetricType::Gauge)
+			.build()
+			.render_and_append_instance(&PrometheusInstance::new().with_value(0))
+			.render();
+	}
+	check_metric_families(&rendered)
+}
+
+/// Render the version of the exporter and how it was built, for fleet inventory.
+fn render_build_info() -> String {
+	PrometheusMetric::build()
+		.with_name("apcupsd_exporter_build_info")
+		.with_help("Version, git revision, and rustc version the exporter was built from.")
+		.with_metric_type(MetricType::Gauge)
+		.build()
+		.render_and_append_instance(
+			&PrometheusInstance::new()
+				.with_label("version", built_info::PKG_VERSION)
+				.with_label("revision", built_info::GIT_COMMIT_HASH.unwrap_or("unknown"))
+				.with_label("rustc", built_info::RUSTC_VERSION)
+				.with_value(1),
+		)
+		.render()
+}
+
+/// Render the number of hosts included in a scrape and how many of them could be fetched from, for fleet-wide alerts.
+fn render_target_counts(configured: usize, reachable: usize) -> String {
+	let mut rendered = String::new();
+	for (name, help, value) in [
+		(
+			"apcupsd_exporter_targets_configured",
+			"Number of apcupsd hosts included in this scrape.",
+			configured,
+		),
+		(
+			"apcupsd_exporter_targets_reachable",
+			"Number of apcupsd hosts included in this scrape that data could be fetched from.",
+			reachable,
+		),
+	] {
+		rendered += &PrometheusMetric::build()
+			.with_name(name)
+			.with_help(help)
+			.with_metric_type(MetricType::Gauge)
+			.build()
+			.render_and_append_instance(&PrometheusInstance::new().with_value(value))
+			.render();
+	}
+	rendered
+}
+
+#[derive(Clone, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(default)]
+struct HostSpecificOptions {
+	address: String,
+	port: u16,
+	slug: Option<String>,
+	/// Where the value of the `exported_ups` label comes from.
+	ups_label: UpsLabelSource,
+	/// Poll apcupsd in the background at this interval instead of fetching data when scraped.
+	poll_interval_seconds: Option<f64>,
+	/// Background poll interval used while the UPS is on battery or apcupsd is fast polling.
+	on_battery_poll_interval_seconds: f64,
+	/// Wait a random extra time of up to this after every background poll, so polls of many hosts or exporters sharing an apcupsd drift
+	/// apart instead of arriving at once.
+	poll_jitter_seconds: f64,
+	/// Delay of the first background poll, to spread the polls of hosts sharing an apcupsd over the interval.
+	poll_phase_seconds: f64,
+	/// If `port` refuses connections at startup or later, try the common NIS ports 3551 and 7000 instead.
+	probe_alternate_ports: bool,
+	/// Other NIS servers reporting the same UPS (e.g. an apcupsd slave, or the same apcupsd over another network), tried in order when
+	/// `address` can't be fetched from.
+	failover_endpoints: Vec<NisEndpoint>,
+	/// SOCKS5 (`socks5://host:port`) or HTTP CONNECT (`http://host:port`) proxy to connect to the NIS servers through.
+	proxy: Option<String>,
+	/// Forward the NIS connections over SSH, with the addresses as seen from the SSH host, for apcupsd listening on its loopback interface.
+	ssh_tunnel: Option<SshTunnelOptions>,
+	/// Read the status file apcupsd writes instead of connecting to its NIS server, which `address` and `port` are then ignored for.
+	status_file: Option<StatusFileOptions>,
+}
+
+#[derive(Clone, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(default)]
+struct NisEndpoint {
+	address: String,
+	port: u16,
+}
+
+impl Default for NisEndpoint {
+	fn default() -> Self {
+		Self {
+			address: "127.0.0.1".into(),
+			port: 3551,
+		}
+	}
+}
+
+impl HostSpecificOptions {
+	/// The configured slug, or a generated one based on the host's position in the config.
+	fn slug(&self, host_index: usize) -> String {
+		self.slug.clone().unwrap_or_else(|| format!("apcupsd{}", host_index))
+	}
+}
+
+impl Default for HostSpecificOptions {
+	fn default() -> Self {
+		Self {
+			address: "127.0.0.1".into(),
+			port: 3551,
+			slug: None,
+			ups_label: Default::default(),
+			poll_interval_seconds: None,
+			on_battery_poll_interval_seconds: 5.,
+			poll_jitter_seconds: 0.,
+			poll_phase_seconds: 0.,
+			probe_alternate_ports: false,
+			failover_endpoints: Vec::new(),
+			proxy: None,
+			ssh_tunnel: None,
+			status_file: None,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum UpsLabelSource {
+	/// The configured slug.
+	#[default]
+	Slug,
+	/// The `HOSTNAME` reported by apcupsd, falling back to the slug if it isn't reported.
+	Hostname,
+	/// The reverse DNS name of the host's address, falling back to the slug if it can't be resolved.
+	ReverseDns,
+}
+
+/// Find a port the NIS server at `address` accepts connections on, trying `port` first and then the common alternates.
+async fn probe_nis_port(address: &str, port: u16) -> u16 {
+	let client = nis_client(address.to_string(), port);
+	match spawn_blocking(move || client.probe_port([port].into_iter().chain(nis::COMMON_PORTS))).await.ok().flatten() {
+		Some(candidate) => {
+			if candidate != port {
+				syslog::warning(format!(
+					"NIS server at {address} doesn't accept connections on port {port}, using port {candidate} instead"
+				));
+			}
+			candidate
+		},
+		None => {
+			syslog::warning(format!(
+				"NIS server at {address} doesn't accept connections on any common port, using port {port}"
+			));
+			port
+		},
+	}
+}
+
+/// Metrics rendered from a snapshot, or why they couldn't be, with when the snapshot was fetched and the generation of the render
+/// options they were rendered with.
+type RenderedSnapshot = (Instant, u64, Result<String, String>);
+
+/// A configured host along with state that lives for the lifetime of the exporter.
+#[derive(Clone)]
+struct Host {
+	options: HostSpecificOptions,
+	slug: String,
+	reverse_dns_name: Option<String>,
+	access: APCThrottledAccess,
+	held_values: Arc<std::sync::Mutex<HeldValues>>,
+	/// Metrics rendered from the most recent snapshot, so scrapes within the same throttle window (e.g. from HA Prometheus pairs) don't
+	/// parse and render the same data again.
+	rendered_cache: Arc<std::sync::Mutex<Option<RenderedSnapshot>>>,
+	/// Whether a warning has been logged since the clock skew went over the threshold.
+	clock_skew_warned: Arc<AtomicBool>,
+	/// The last `VERSION` checked for compatibility, so warnings are only logged when it changes.
+	checked_version: Arc<std::sync::Mutex<Option<String>>>,
+	/// Recent polls, if enabled.
+	history: Option<Arc<std::sync::Mutex<History>>>,
+}
+
+/// Exporter-wide options that apply to every host when it's created.
+#[derive(Clone, Default, PartialEq)]
+struct HostDefaults {
+	history: Option<HistoryOptions>,
+	shared_cache: Option<SharedCacheOptions>,
+	snapshot_directory: Option<PathBuf>,
+	poll_statistics: PollStatisticsOptions,
+	percent_scale: PercentScale,
+	dns: Option<DnsOptions>,
+	/// Opened once at startup, since it only changes on restart.
+	history_store: Option<Arc<HistoryStore>>,
+	/// Created once at startup like `history_store`, so notifications are rate limited across reloads.
+	notifier: Option<Arc<Notifier>>,
+	/// Loaded once at startup like `history_store`, so baselines carry over across reloads.
+	runtime_baselines: Option<Arc<RuntimeBaselines>>,
+}
+
+impl HostDefaults {
+	fn new(
+		server_options: &ApcupsdExporterOptions,
+		history_store: Option<Arc<HistoryStore>>,
+		notifier: Option<Arc<Notifier>>,
+		runtime_baselines: Option<Arc<RuntimeBaselines>>,
+	) -> Self {
+		Self {
+			history: server_options.history.clone(),
+			shared_cache: server_options.shared_cache.clone(),
+			snapshot_directory: server_options.snapshot_directory.clone(),
+			poll_statistics: server_options.poll_statistics.clone(),
+			percent_scale: server_options.percent_scale,
+			dns: server_options.dns.clone(),
+			history_store,
+			notifier,
+			runtime_baselines,
+		}
+	}
+}
+
+impl Host {
+	pub async fn new(mut options: HostSpecificOptions, host_index: usize, host_defaults: &HostDefaults) -> Result<Self, ExporterError> {
+		let slug = options.slug(host_index);
+		if options.proxy.is_some() && options.ssh_tunnel.is_some() {
+			return Err(ExporterError::config(format!("{slug}: proxy and ssh_tunnel can't be combined")));
+		}
+		let remote = options.proxy.is_some() || options.ssh_tunnel.is_some() || !options.failover_endpoints.is_empty();
+		if options.status_file.is_some() && remote {
+			return Err(ExporterError::config(format!(
+				"{slug}: status_file can't be combined with proxy, ssh_tunnel, or failover_endpoints"
+			)));
+		}
+		let probe_alternate_ports =
+			options.probe_alternate_ports && options.proxy.is_none() && options.ssh_tunnel.is_none() && options.status_file.is_none();
+		// Probed again if the port refuses connections later, e.g. after apcupsd was reconfigured, preferring the configured port.
+		let alternate_ports: Vec<_> = if probe_alternate_ports {
+			[options.port].into_iter().chain(nis::COMMON_PORTS).collect()
+		} else {
+			Vec::new()
+		};
+		if probe_alternate_ports {
+			options.port = probe_nis_port(&options.address, options.port).await;
+		}
+		let resolver = match &host_defaults.dns {
+			Some(dns_options) => Some(Arc::new(
+				dns::Resolver::new(dns_options.clone()).map_err(|e| ExporterError::config(format!("dns: {e}")))?,
+			)),
+			None => None,
+		};
+		let mut endpoints = Vec::with_capacity(options.failover_endpoints.len() + 1);
+		let targets = [(options.address.clone(), options.port)]
+			.into_iter()
+			.chain(options.failover_endpoints.iter().map(|endpoint| (endpoint.address.clone(), endpoint.port)));
+		for (index, (host, port)) in targets.enumerate() {
+			let name = match &options.status_file {
+				Some(status_file) => status_file.path.display().to_string(),
+				None => host_port(&host, port),
+			};
+			let local_address = match (&options.proxy, &options.ssh_tunnel) {
+				(Some(proxy), _) => Some(proxy::spawn_forwarder(proxy, (host.clone(), port)).await.map_err(ExporterError::Server)?),
+				(_, Some(ssh_tunnel)) => Some(ssh_tunnel::spawn(ssh_tunnel, (host.clone(), port)).map_err(ExporterError::server)?),
+				(None, None) => None,
+			};
+			// Names are only resolved by the exporter for direct connections, the proxy or SSH host resolves them otherwise.
+			let resolved_name = match (&resolver, local_address) {
+				(Some(resolver), None) if host.parse::<IpAddr>().is_err() => Some(ResolvedName::new(resolver.clone(), host.clone())),
+				_ => None,
+			};
+			let (host, port) = local_address.map_or((host, port), |local_address| (local_address.ip().to_string(), local_address.port()));
+			endpoints.push(Endpoint {
+				name,
+				client: nis_client(host, port),
+				port,
+				resolved_name,
+				alternate_ports: if index == 0 { alternate_ports.clone() } else { Vec::new() },
+			});
+		}
+		let history = host_defaults.history.as_ref().map(|options| Arc::new(std::sync::Mutex::new(History::new(options))));
+		let shared_cache = host_defaults.shared_cache.clone().map(|cache_options| SharedCache::new(cache_options, &endpoints[0].name));
+		let access = APCThrottledAccess::new(
+			endpoints,
+			options
+				.poll_interval_seconds
+				.map_or(Ok(Duration::from_secs(1)), Duration::try_from_secs_f64)
+				.map_err(|e| ExporterError::config(format!("{slug}: poll_interval_seconds: {e}")))?,
+			Duration::try_from_secs_f64(options.on_battery_poll_interval_seconds)
+				.map_err(|e| ExporterError::config(format!("{slug}: on_battery_poll_interval_seconds: {e}")))?,
+			PollStatistics::new(&host_defaults.poll_statistics, host_defaults.percent_scale),
+			history.clone(),
+			host_defaults.history_store.clone().map(|store| HostHistoryStore { store, slug: slug.clone() }),
+			host_defaults.notifier.clone().map(|notifier| HostNotifier::new(notifier, slug.clone())),
+			host_defaults.runtime_baselines.clone().map(|baselines| HostRuntimeBaselines {
+				baselines,
+				slug: slug.clone(),
+			}),
+			shared_cache,
+			host_defaults.snapshot_directory.as_deref().map(|directory| persistence::snapshot_path(directory, &slug)),
+			options.status_file.clone(),
+		);
+		if options.poll_interval_seconds.is_some() {
+			let phase = Duration::try_from_secs_f64(options.poll_phase_seconds)
+				.map_err(|e| ExporterError::config(format!("{slug}: poll_phase_seconds: {e}")))?;
+			let jitter = Duration::try_from_secs_f64(options.poll_jitter_seconds)
+				.map_err(|e| ExporterError::config(format!("{slug}: poll_jitter_seconds: {e}")))?;
+			access.spawn_poller(phase, jitter);
+		}
+
+		let reverse_dns_name = match options.ups_label {
+			UpsLabelSource::ReverseDns => {
+				let address = (options.address.clone(), options.port);
+				spawn_blocking(move || -> std::io::Result<String> {
+					let ip = address.to_socket_addrs()?.next().ok_or(std::io::ErrorKind::NotFound)?.ip();
+					dns_lookup::lookup_addr(&ip)
+				})
+				.await
+				.map_err(std::io::Error::from)
+				.and_then(|result| result)
+				.inspect_err(|e| syslog::error(format!("Unable to resolve reverse DNS for {slug}, using slug as label: {e}")))
+				.ok()
+			},
+			UpsLabelSource::Slug | UpsLabelSource::Hostname => None,
+		};
+		Ok(Self {
+			options,
+			slug,
+			reverse_dns_name,
+			access,
+			held_values: Default::default(),
+			rendered_cache: Default::default(),
+			clock_skew_warned: Default::default(),
+			checked_version: Default::default(),
+			history,
+		})
+	}
+
+	/// Fetch and render metrics for this host for a scrape, which starts a new period for statistics aggregated between scrapes. Failures
+	/// are reported in the output instead of failing the whole scrape, so one unreachable host doesn't cause data for every host to be
+	/// lost. Also returns whether data could be fetched from the host.
+	pub async fn render(&self, render_options: &RenderOptions) -> (String, bool) {
+		self.render_fetched(self.access.fetch().await, render_options)
+	}
+
+	/// Fetch and render metrics like [`Self::render`] for consumers other than scrapes (e.g. OTLP pushes), without starting a new period
+	/// for statistics.
+	pub async fn render_without_scrape(&self, render_options: &RenderOptions) -> (String, bool) {
+		self.render_fetched(self.access.refresh().await, render_options)
+	}
+
+	#[tracing::instrument(name = "render_host", skip_all, fields(slug = %self.slug))]
+	fn render_fetched(&self, fetch_result: Result<ApcupsdSnapshot, std::io::ErrorKind>, render_options: &RenderOptions) -> (String, bool) {
+		let reachable = fetch_result.is_ok();
+		let (ups_label, result) = match fetch_result {
+			Ok(mut snapshot) => {
+				render_options.labels.redact.redact(&mut snapshot.data);
+				let ups_label = self.ups_label(&snapshot.data, &render_options.labels);
+				let mut fetch_metrics = render_fetch_metrics(&ups_label, &snapshot, render_options.precision);
+				self.warn_clock_skew(snapshot.clock_skew_seconds(), render_options.clock_skew_warning_seconds);
+				self.warn_incompatible_version(snapshot.data.get("VERSION"));
+				if let Some(ratio) = snapshot.runtime_degradation_ratio {
+					fetch_metrics += &runtime_degradation::render_degradation_ratio(ratio, &[("exported_ups".to_string(), ups_label.clone())]);
+				}
+				let mut rendered_cache = self.rendered_cache.lock().unwrap_or_else(PoisonError::into_inner);
+				let result = match &*rendered_cache {
+					Some((fetched_at, generation, result)) if *fetched_at == snapshot.fetched_at && *generation == render_options.generation => {
+						result.clone()
+					},
+					_ => {
+						let mut held_values = self.held_values.lock().unwrap_or_else(PoisonError::into_inner);
+						let keys_received = snapshot.data.keys().filter(|key| !FRAMING_KEYS.contains(&key.as_str())).count();
+						let render_started = Instant::now();
+						let result = render_metrics_holding_values(snapshot.data, ups_label.clone(), render_options, &mut held_values)
+							.map(|(rendered, keys_unrecognized)| {
+								rendered
+									+ &render_key_counts(&ups_label, keys_received, keys_unrecognized)
+									+ &render_phase_duration(&ups_label, "render", render_started.elapsed())
+							})
+							.map_err(|e| format!("error rendering {}: {e}", self.slug));
+						*rendered_cache = Some((snapshot.fetched_at, render_options.generation, result.clone()));
+						result
+					},
+				};
+				(ups_label, result.map(|rendered| rendered + &fetch_metrics))
+			},
+			Err(e) => (
+				self.ups_label(&HashMap::new(), &render_options.labels),
+				Err(format!("error fetching {}: {e}", self.slug)),
+			),
+		};
+		let labels = vec![("exported_ups".to_string(), ups_label)];
+		let up = PrometheusMetric::build()
+			.with_name("apcupsd_up")
+			.with_help("Whether data was successfully fetched and rendered from apcupsd.")
+			.with_metric_type(MetricType::Gauge)
+			.build()
+			.render_and_append_instance(&prometheus_instance_with_labels(&labels).with_value(f64::from(result.is_ok())))
+			.render();
+		let abandoned_fetches = PrometheusMetric::build()
+			.with_name("apcupsd_exporter_abandoned_fetches_total")
+			.with_help("Fetches from apcupsd given up on after the deadline, each of which kept a blocking thread busy until it ended.")
+			.with_metric_type(MetricType::Counter)
+			.build()
+			.render_and_append_instance(&prometheus_instance_with_labels(&labels).with_value(self.access.abandoned_fetches.load(Ordering::Relaxed)))
+			.render();
+		let truncated_responses = PrometheusMetric::build()
+			.with_name("apcupsd_exporter_truncated_responses_total")
+			.with_help("Responses from apcupsd still missing their end after a retry, which were exported as far as they were received.")
+			.with_metric_type(MetricType::Counter)
+			.build()
+			.render_and_append_instance(&prometheus_instance_with_labels(&labels).with_value(self.access.truncated_responses.load(Ordering::Relaxed)))
+			.render();
+		let dns_failures = PrometheusMetric::build()
+			.with_name("apcupsd_exporter_dns_failures_total")
+			.with_help("Failed resolutions of the names of the host's NIS servers, after which the last resolved address was used.")
+			.with_metric_type(MetricType::Counter)
+			.build()
+			.render_and_append_instance(&prometheus_instance_with_labels(&labels).with_value(self.access.dns_failures.load(Ordering::Relaxed)))
+			.render();
+		let rendered = match result {
+			Ok(rendered) => rendered + &up + &abandoned_fetches + &truncated_responses + &dns_failures,
+			Err(e) => format!("# {}\n{up}{abandoned_fetches}{truncated_responses}{dns_failures}", e.replace('\n', " ")),
+		};
+		(rendered, reachable)
+	}
+
+	/// Log when the clock skew of the host first exceeds the threshold, since it makes every timestamp metric wrong.
+	fn warn_clock_skew(&self, clock_skew: Option<f64>, threshold_seconds: f64) {
+		let skewed = clock_skew.is_some_and(|clock_skew| clock_skew.abs() > threshold_seconds);
+		if skewed && !self.clock_skew_warned.swap(true, Ordering::Relaxed) {
+			syslog::warning(format!(
+				"Clock of {} is {}s off from the exporter's clock",
+				self.slug,
+				clock_skew.unwrap_or_default()
+			));
+		} else if !skewed {
+			self.clock_skew_warned.store(false, Ordering::Relaxed);
+		}
+	}
+
+	/// Log when the host starts reporting a version of apcupsd whose output is known to differ from the current format.
+	fn warn_incompatible_version(&self, version: Option<&String>) {
+		let mut checked_version = self.checked_version.lock().unwrap_or_else(PoisonError::into_inner);
+		if checked_version.as_ref() == version {
+			return;
+		}
+		if let Some(warning) = version.and_then(|version| DaemonVersion::parse(version)?.compatibility_warning()) {
+			syslog::warning(format!("{} runs {warning}", self.slug));
+		}
+		checked_version.clone_from(&version.cloned());
+	}
+
+	/// Value of the `exported_ups` label for data fetched from this host, escaped for the exposition format.
+	pub fn ups_label(&self, apcupsd_data: &HashMap<String, String>, label_options: &LabelOptions) -> String {
+		let ups_label = label_options
+			.ups_template
+			.as_ref()
+			.and_then(|template| labels::render_template(template, apcupsd_data, &self.slug))
+			.or_else(|| match self.options.ups_label {
+				UpsLabelSource::Slug => None,
+				UpsLabelSource::Hostname => apcupsd_data.get("HOSTNAME").cloned(),
+				UpsLabelSource::ReverseDns => self.reverse_dns_name.clone(),
+			})
+			.unwrap_or_else(|| self.slug.clone());
+		labels::escape_label_value(&ups_label)
+	}
+}
+
+#[derive(Deserialize, JsonSchema, Serialize)]
+#[serde(default)]
+struct ApcupsdExporterOptions {
+	pub address: SocketAddr,
+	#[serde(default, serialize_with = "serialize_authorization")]
+	#[schemars(with = "AuthorizationSchema")]
+	pub authorization: Authorization,
+	#[serde(default)]
+	pub tls_options: Option<TlsOptions>,
+	/// Connection settings of the metrics and admin listeners.
+	pub http_server: HttpServerOptions,
+	/// Path metrics are served at.
+	pub metrics_path: String,
+	/// Also serve the metrics of each host alone at `<metrics_path>/<slug>`, like `?target=<slug>` does, e.g. for scrape jobs that can't
+	/// set parameters.
+	#[serde(default)]
+	pub per_host_paths: bool,
+	#[serde(default)]
+	pub hosts: Vec<HostSpecificOptions>,
+	/// Configuration files, or directories of `.yaml` files, merged into this one, e.g. one file per host.
+	#[serde(default)]
+	pub include: Vec<PathBuf>,
+	/// Fail on unknown configuration keys instead of ignoring them.
+	#[serde(default)]
+	pub strict: bool,
+	/// How status flags are exported.
+	#[serde(default)]
+	pub status_metrics: StatusMetrics,
+	/// Statistics accumulated on every poll, which are most useful with background polling.
+	#[serde(default)]
+	pub poll_statistics: PollStatisticsOptions,
+	/// Names and templates for labels on every metric.
+	#[serde(default)]
+	pub labels: LabelOptions,
+	/// Seconds to keep reporting the last good value of an apcupsd key (e.g. `AMBTEMP`) when it goes missing or can't be parsed,
+	/// for sensors that flap.
+	#[serde(default)]
+	pub hold_last_value_seconds: HashMap<String, f64>,
+	/// Additional sentinel values for apcupsd keys, mapped to the value to export instead, or `null` to treat the key as missing, for
+	/// firmware that reports things like `NA` or `--`.
+	#[serde(default)]
+	pub special_values: HashMap<String, HashMap<String, Option<f64>>>,
+	/// Voltage, temperature, and frequency keys to also accept as a plain number without their unit, for drivers (e.g. Microlink) that
+	/// sometimes leave it off.
+	#[serde(default)]
+	pub unitless_keys: HashSet<String>,
+	/// Name and type to export apcupsd keys as instead of the built in ones, e.g. to match existing recording rules.
+	#[serde(default)]
+	pub metric_overrides: HashMap<String, MetricOverride>,
+	/// Range of plausible values of apcupsd keys, in the units they're reported in (percentages from 0 to 100). Values outside of it,
+	/// like 6553.5 Volts after a firmware glitch, are dropped and counted in `apcupsd_exporter_implausible_values_total`.
+	pub plausible_ranges: HashMap<String, PlausibleRange>,
+	/// Quirks of families of UPS models, detected by their `MODEL`, `DRIVER`, and `FIRMWARE`, such as firmware that reports shutdown
+	/// related keys like `DLOWBATT` in different units or with different meanings. The first family that matches applies, checking these
+	/// before the built in ones.
+	#[serde(default)]
+	pub model_families: Vec<ModelFamily>,
+	/// apcupsd.conf to read the NIS address, port, and UPS name of a local apcupsd from when no hosts are configured.
+	#[serde(default)]
+	pub apcupsd_conf: Option<PathBuf>,
+	/// Customers that may each only scrape a subset of hosts, identified by their credentials. Can't be combined with `authorization`.
+	#[serde(default)]
+	pub tenants: Vec<Tenant>,
+	/// Whether percentages are exported as 0-1 (`fraction`) or 0-100 (`percent`).
+	#[serde(default)]
+	pub percent_scale: PercentScale,
+	/// Whether timestamps (e.g. `apcupsd_last_update_timestamp_seconds`) are exported in `seconds` or `milliseconds`, for downstream
+	/// systems that expect the latter.
+	#[serde(default)]
+	pub timestamp_unit: TimestampUnit,
+	/// Attach the `DATE` reported by apcupsd to every sample as its timestamp, instead of leaving it to be the time of the scrape.
+	#[serde(default)]
+	pub sample_timestamps: bool,
+	/// Round values to this many decimal places.
+	#[serde(default)]
+	pub precision: Option<u32>,
+	/// Log a warning when the clock of an apcupsd host is off from the exporter's by more than this many seconds.
+	pub clock_skew_warning_seconds: f64,
+	/// Track the runtime estimated by each UPS against a rolling baseline at comparable loads and export
+	/// `apcupsd_battery_runtime_degradation_ratio`, for early warning of failing batteries.
+	#[serde(default)]
+	pub runtime_degradation: Option<RuntimeDegradationOptions>,
+	/// Extra metrics computed from apcupsd keys with arithmetic expressions.
+	#[serde(default)]
+	pub computed: Vec<ComputedMetric>,
+	/// Export rollups across the hosts in each scrape: total load in watts, shortest runtime left, and number of UPSes on battery.
+	#[serde(default)]
+	pub site_rollups: bool,
+	/// Keep the most recent polls of each host in memory for the `/history` admin endpoint.
+	#[serde(default)]
+	pub history: Option<HistoryOptions>,
+	/// Record every poll of every host in an SQLite database, queryable with the `/history` admin endpoint.
+	#[serde(default)]
+	pub history_store: Option<HistoryStoreOptions>,
+	/// Share fetched data between replicas through Redis, so HA pairs don't each poll every UPS.
+	#[serde(default)]
+	pub shared_cache: Option<SharedCacheOptions>,
+	/// Directory to save the last successfully fetched data of each host in, which is served (flagged stale) after the exporter restarts
+	/// until a fetch succeeds, so upgrades don't leave gaps.
+	#[serde(default)]
+	pub snapshot_directory: Option<PathBuf>,
+	/// Resolve the names of NIS servers with the exporter's own resolver, caching them for their TTL and resolving them again when
+	/// connecting fails, instead of leaving it to the system on every connection.
+	#[serde(default)]
+	pub dns: Option<DnsOptions>,
+	/// Address to serve endpoints other than `/metrics` on (e.g. `/config`), with the same TLS options and `admin_authorization`, or else
+	/// the same authorization.
+	#[serde(default)]
+	pub admin_address: Option<SocketAddr>,
+	/// HTTP authentication of `admin_address` instead of `authorization`, required with tenants, whose credentials only grant access to
+	/// their own hosts' metrics.
+	#[serde(default, serialize_with = "serialize_authorization")]
+	#[schemars(with = "AuthorizationSchema")]
+	pub admin_authorization: Authorization,
+	/// Serve `admin_address` without authentication if neither authorization nor client certificates are configured, instead of refusing
+	/// to start, e.g. when it's only reachable from a trusted network.
+	#[serde(default)]
+	pub admin_allow_unauthenticated: bool,
+	/// Actions, like a self-test, that can be triggered on hosts with the `/command` admin endpoint.
+	#[serde(default)]
+	pub ups_commands: Option<UpsCommandOptions>,
+	/// Periodically push metrics to an OpenTelemetry collector with OTLP over HTTP.
+	#[serde(default)]
+	pub otlp: Option<OtlpOptions>,
+	/// Periodically upload snapshots of the data of every host to S3 compatible object storage.
+	#[serde(default)]
+	pub archive: Option<ArchiveOptions>,
+	/// Periodically send discovery data and values of every host to Zabbix with the sender protocol.
+	#[serde(default)]
+	pub zabbix: Option<ZabbixOptions>,
+	/// Notify about power events, like a UPS switching to battery, detected between polls.
+	#[serde(default)]
+	pub notifications: Option<NotificationOptions>,
+	/// Export tracing spans of fetching, parsing, and rendering with OTLP.
+	#[serde(default)]
+	pub tracing: Option<TracingOptions>,
+	/// Also log power events and errors to syslog or the systemd journal.
+	#[serde(default)]
+	pub syslog: Option<SyslogOptions>,
+	/// Count the power events the local apcupsd logs to the systemd journal.
+	#[serde(default)]
+	pub journal: Option<JournalOptions>,
+}
+
+/// A customer with their own credentials that only sees the metrics of their own hosts.
+#[derive(Clone, Deserialize, JsonSchema, Serialize)]
+struct Tenant {
+	/// Expected HTTP basic authentication credentials, in the same format as `authorization`.
+	#[serde(serialize_with = "serialize_redacted")]
+	basic: String,
+	/// Slugs of the hosts this tenant can scrape.
+	hosts: Vec<String>,
+}
+
+/// Error for options of a subsystem left out of the build, which are still accepted by the configuration so it's the same for every build.
+#[cfg_attr(
+	all(feature = "archive", feature = "dns", feature = "email", feature = "history-store", feature = "telemetry"),
+	allow(dead_code)
+)]
+fn feature_disabled(feature: &str) -> String {
+	format!("the \"{feature}\" feature isn't in this build, rebuild with `--features {feature}` or `--features full`")
+}
+
+/// Serialize a secret as a placeholder, for showing the effective configuration.
+fn serialize_redacted<S: serde::Serializer>(_: &str, serializer: S) -> Result<S::Ok, S::Error> {
+	serializer.serialize_str("<redacted>")
+}
+
+/// Serialize [`Authorization`], which doesn't implement [`Serialize`], without its password.
+fn serialize_authorization<S: serde::Serializer>(authorization: &Authorization, serializer: S) -> Result<S::Ok, S::Error> {
+	match authorization {
+		Authorization::None => serializer.serialize_unit_variant("Authorization", 0, "None"),
+		Authorization::Basic(_) => serializer.serialize_newtype_variant("Authorization", 1, "Basic", "<redacted>"),
+	}
+}
+
+/// Schema stand-in for [`Authorization`], which doesn't implement [`JsonSchema`].
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+enum AuthorizationSchema {
+	None,
+	Basic(String),
+}
+
+impl Default for ApcupsdExporterOptions {
+	fn default() -> Self {
+		ApcupsdExporterOptions {
+			address: SocketAddr::new([127, 0, 0, 1].into(), 9175),
+			authorization: Default::default(),
+			tls_options: Default::default(),
+			http_server: Default::default(),
+			metrics_path: "/metrics".to_string(),
+			per_host_paths: false,
+			hosts: vec![],
+			include: vec![],
+			strict: false,
+			status_metrics: Default::default(),
+			poll_statistics: Default::default(),
+			labels: Default::default(),
+			hold_last_value_seconds: Default::default(),
+			special_values: Default::default(),
+			unitless_keys: Default::default(),
+			metric_overrides: Default::default(),
+			plausible_ranges: default_plausible_ranges(),
+			model_families: vec![],
+			apcupsd_conf: None,
+			tenants: vec![],
+			percent_scale: Default::default(),
+			timestamp_unit: Default::default(),
+			sample_timestamps: false,
+			precision: None,
+			clock_skew_warning_seconds: 60.,
+			runtime_degradation: None,
+			computed: vec![],
+			site_rollups: false,
+			history: None,
+			history_store: None,
+			shared_cache: None,
+			snapshot_directory: None,
+			dns: None,
+			admin_address: None,
+			admin_authorization: Default::default(),
+			admin_allow_unauthenticated: false,
+			ups_commands: None,
+			otlp: None,
+			archive: None,
+			zabbix: None,
+			notifications: None,
+			tracing: None,
+			syslog: None,
+			journal: None,
+		}
+	}
+}
+
+/// Find the first value for `name` in a URL query string.
+fn query_parameter(query: Option<&str>, name: &str) -> Option<String> {
+	query?.split('&').find_map(|pair| match pair.split_once('=') {
+		Some((key, value)) if key == name => Some(value.to_string()),
+		_ => None,
+	})
+}
+
+fn prometheus_instance_with_labels<N: Num + std::fmt::Display + std::fmt::Debug>(
+	labels: &[(String, String)],
+) -> PrometheusInstance<'_, N, MissingValue> {
+	let mut instance = PrometheusInstance::new();
+	for (key, val) in labels {
+		instance = instance.with_label(key.as_ref(), val.as_ref());
+	}
+	instance
+}
+
+/// Render metrics describing how a snapshot was fetched from apcupsd.
+fn render_fetch_metrics(ups_label: &str, snapshot: &ApcupsdSnapshot, precision: Option<u32>) -> String {
+	let labels = vec![("exported_ups".to_string(), ups_label.to_string())];
+	let mut rendered = PrometheusMetric::build()
+		.with_name("apcupsd_data_stale")
+		.with_help("Whether the data was served from the exporter's cache instead of being fetched from apcupsd for this scrape.")
+		.with_metric_type(MetricType::Gauge)
+		.build()
+		.render_and_append_instance(&prometheus_instance_with_labels(&labels).with_value(f64::from(snapshot.stale)))
+		.render();
+	if let Some(restored) = snapshot.restored {
+		rendered += &PrometheusMetric::build()
+			.with_name("apcupsd_exporter_snapshot_stale")
+			.with_help("Whether the data was saved before the exporter restarted, because no fetch from apcupsd has succeeded since.")
+			.with_metric_type(MetricType::Gauge)
+			.build()
+			.render_and_append_instance(&prometheus_instance_with_labels(&labels).with_value(f64::from(restored)))
+			.render();
+	}
+	if let Some(last_success) = snapshot.last_success.and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+		rendered += &PrometheusMetric::build()
+			.with_name("apcupsd_last_successful_fetch_timestamp_seconds")
+			.with_help("Time data was last successfully fetched from apcupsd.")
+			.with_metric_type(MetricType::Gauge)
+			.build()
+			.render_and_append_instance(&prometheus_instance_with_labels(&labels).with_value(last_success.as_secs_f64()))
+			.render();
+	}
+	rendered += &PrometheusMetric::build()
+		.with_name("apcupsd_endpoint_info")
+		.with_help("The NIS server (address:port) the data was fetched from, out of the host's address and its failover endpoints.")
+		.with_metric_type(MetricType::Gauge)
+		.build()
+		.render_and_append_instance(&prometheus_instance_with_labels(&labels).with_label("endpoint", snapshot.endpoint.as_str()).with_value(1))
+		.render();
+	if let Some(clock_skew) = snapshot.clock_skew_seconds() {
+		rendered += &PrometheusMetric::build()
+			.with_name("apcupsd_clock_skew_seconds")
+			.with_help("Difference between the DATE reported by apcupsd and the exporter's clock when the data was fetched.")
+			.with_metric_type(MetricType::Gauge)
+			.build()
+			.render_and_append_instance(&prometheus_instance_with_labels(&labels).with_value(clock_skew))
+			.render();
+	}
+	match snapshot.fetch_timings {
+		Some(timings) => {
+			for (phase, duration) in timings.phases() {
+				rendered += &render_phase_duration(ups_label, phase, duration);
+			}
+		},
+		None => rendered += &render_phase_duration(ups_label, "fetch", snapshot.fetch_duration),
+	}
+	rendered += &snapshot.statistics.render(&labels, precision);
+	rendered
+}
+
+/// Render how long a phase of scraping a host took, to tell slow NIS servers apart from slow rendering.
+fn render_phase_duration(ups_label: &str, phase: &str, duration: Duration) -> String {
+	let labels = vec![("exported_ups".to_string(), ups_label.to_string())];
+	PrometheusMetric::build()
+		.with_name("apcupsd_exporter_fetch_duration_seconds")
+		.with_help(
+			"Time taken to connect to apcupsd, read its response, parse it, or render it, by phase. Reading from the shared cache or a status file \
+			 is timed as a single fetch phase.",
+		)
+		.with_metric_type(MetricType::Gauge)
+		.build()
+		.render_and_append_instance(&prometheus_instance_with_labels(&labels).with_label("phase", phase).with_value(duration.as_secs_f64()))
+		.render()
+}
+
+/// Render how many keys apcupsd sent and how many of them the exporter doesn't know, so keys added by new firmware don't go unnoticed.
+fn render_key_counts(ups_label: &str, received: usize, unrecognized: usize) -> String {
+	let labels = vec![("exported_ups".to_string(), ups_label.to_string())];
+	let mut rendered = String::new();
+	for (name, help, value) in [
+		("apcupsd_exporter_keys_received", "Number of keys received from apcupsd.", received),
+		(
+			"apcupsd_exporter_keys_unrecognized",
+			"Number of keys received from apcupsd that the exporter doesn't export.",
+			unrecognized,
+		),
+	] {
+		rendered += &PrometheusMetric::build()
+			.with_name(name)
+			.with_help(help)
+			.with_metric_type(MetricType::Gauge)
+			.build()
+			.render_and_append_instance(&prometheus_instance_with_labels(&labels).with_value(value))
+			.render();
+	}
+	rendered
+}
+
+pub fn render_metrics(apcupsd_data: HashMap<String, String>, slug: String, render_options: &RenderOptions) -> Result<String, RenderMetricsError> {
+	render_metrics_holding_values(apcupsd_data, slug, render_options, &mut HeldValues::default()).map(|(rendered, _)| rendered)
+}
+
+/// The header and footer of apcupsd's status output, which aren't data. Some old versions leave the space out of the footer.
+const FRAMING_KEYS: [&str; 3] = ["APC", "END APC", "ENDAPC"];
+
+/// Like [`render_metrics`], but keys in [`RenderOptions::hold_last_value_seconds`] that are missing or unparsable are replaced by
+/// their last good value from `held_values`. Also returns the number of keys that weren't recognized.
+#[tracing::instrument(skip_all)]
+fn render_metrics_holding_values(
+	mut apcupsd_data: HashMap<String, String>,
+	slug: String,
+	render_options: &RenderOptions,
+	held_values: &mut HeldValues,
+) -> Result<(String, usize), RenderMetricsError> {
+	let mut rendered = String::new();
+
+	// Evaluated before keys are taken out of the data as they're rendered.
+	let computed_values: Vec<_> = render_options.computed.iter().map(|metric| (metric, metric.expr.evaluate(&apcupsd_data))).collect();
+
+	let model_family = render_options.model_families.iter().find(|family| family.matches(&apcupsd_data));
+
+	let mut labels = Vec::new();
+	labels.push(("exported_ups".to_string(), slug));
+	let label_keys = [
+		("UPSNAME", &render_options.labels.ups_name),
+		("MODEL", &render_options.labels.model),
+		("SERIALNO", &render_options.labels.serial_number),
+	];
+	for (key, label) in label_keys {
+		if let Some(val) = apcupsd_data.remove(key) {
+			labels.push((label.to_string(), labels::escape_label_value(&val)));
+		}
+	}
+
+	let info_keys = [
+		("HOSTNAME", "hostname"),
+		("VERSION", "version"),
+		("CABLE", "cable"),
+		("DRIVER", "driver"),
+		("UPSMODE", "ups_mode"),
+		("SHARE", "sharenet_name"),
+		("MASTER", "master_name"),
+		("SENSE", "sensitivity"),
+		("ALARMDEL", "alarm_delay"),
+		("LASTXFER", "last_transfer_reason"),
+		("SELFTEST", "last_self_test_result"),
+		("STESTI", "self_test_interval"),
+		("MANDATE", "manufacture_date"),
+		("FIRMWARE", "firmware_version"),
+	];
+
+	let info_values: Vec<_> =
+		info_keys.iter().filter_map(|(key, label)| Some((*label, labels::escape_label_value(apcupsd_data.get(*key)?)))).collect();
+	let mut info = prometheus_instance_with_labels(&labels).with_value(1);
+	for (label, val) in &info_values {
+		info = info.with_label(label, val.as_str());
+	}
+	rendered += &PrometheusMetric::build()
+		.with_name("apcupsd_info")
+		.with_help("Metadata for apcupsd.")
+		.with_metric_type(MetricType::Gauge)
+		.build()
+		.render_and_append_instance(&info)
+		.render();
+
+	let daemon_version = apcupsd_data.get("VERSION").and_then(|version| DaemonVersion::parse(version));
+	if let Some(daemon_version) = &daemon_version {
+		let daemon_info_values: Vec<_> = [
+			("version", Some(&daemon_version.number)),
+			("release_date", daemon_version.release_date.as_ref()),
+			("platform", daemon_version.platform.as_ref()),
+		]
+		.into_iter()
+		.filter_map(|(label, val)| Some((label, labels::escape_label_value(val?))))
+		.collect();
+		let mut daemon_info = prometheus_instance_with_labels(&labels).with_value(1);
+		for (label, val) in &daemon_info_values {
+			daemon_info = daemon_info.with_label(label, val.as_str());
+		}
+		rendered += &PrometheusMetric::build()
+			.with_name("apcupsd_daemon_info")
+			.with_help("Version of apcupsd reporting the data.")
+			.with_metric_type(MetricType::Gauge)
+			.build()
+			.render_and_append_instance(&daemon_info)
+			.render();
+	}
+	let date_format = daemon_version.map(|daemon_version| daemon_version.date_format()).unwrap_or_default();
+
+	// Computed before `STESTI` is removed along with the other keys exported as labels of `apcupsd_info`.
+	let next_self_test = next_self_test_timestamp(&apcupsd_data);
+	for (key, _) in info_keys {
+		apcupsd_data.remove(key);
+	}
+
+	// Some old versions of apcupsd for Back-UPS models report whether line power is OK as MAINS instead of LINEFAIL.
+	if !apcupsd_data.contains_key("LINEFAIL") {
+		if let Some(mains) = apcupsd_data.remove("MAINS") {
+			apcupsd_data.insert("LINEFAIL".to_string(), mains);
+		}
+	}
+
+	let sample_timestamp = if render_options.sample_timestamps {
+		apcupsd_data.get("DATE").and_then(|date| parse_metric(date.clone(), MetricParseType::Timestamp.into()).ok().flatten())
+	} else {
+		None
+	};
+	let transfer_window_margin = transfer_window_margin(&apcupsd_data);
+	let status_numeric = status_numeric(&apcupsd_data);
+	let mut renderer = MetricRenderer::new(labels, apcupsd_data, render_options, held_values, model_family, date_format);
+
+	rendered += &renderer.render_metric(
+		"DATE",
+		MetricParseType::Timestamp,
+		"apcupsd_last_update_timestamp_seconds",
+		"Date and time of last update from UPS.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"STARTTIME",
+		MetricParseType::Timestamp,
+		"apcupsd_start_timestamp_seconds",
+		"Date and time apcupsd was started.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"MASTERUPD",
+		MetricParseConfig {
+			special_values: [("No connection to Master", None)].into(),
+			..MetricParseType::Timestamp.into()
+		},
+		"apcupsd_master_update_timestamp_seconds",
+		"Last time the master sent an update to the slave.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"LINEV",
+		MetricParseType::Voltage,
+		"apcupsd_line_volts",
+		"Current input line voltage.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"LOADPCT",
+		MetricParseType::Percentage,
+		"apcupsd_ups_load_percent",
+		"Percentage of UPS load capacity used.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"LOADAPNT",
+		MetricParseType::Percentage,
+		"apcupsd_ups_load_apparent_power_percent",
+		"Percentage of UPS load apparent power capacity used.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"BCHARGE",
+		MetricParseType::Percentage,
+		"apcupsd_battery_charge_percent",
+		"Current battery capacity charge percentage.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"TIMELEFT",
+		MetricParseType::Duration,
+		"apcupsd_battery_time_left_seconds",
+		"Remaining runtime left on battery as estimated by the UPS.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"MBATTCHG",
+		MetricParseType::Percentage,
+		"apcupsd_battery_charge_required_for_shutdown_percent",
+		"Min battery charge % (BCHARGE) required for system shutdown.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"MINTIMEL",
+		MetricParseType::Duration,
+		"apcupsd_battery_runtime_required_for_shutdown_seconds",
+		"Min battery runtime required for system shutdown.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"MAXTIME",
+		MetricParseType::Duration,
+		"apcupsd_battery_runtime_trigger_shutdown_seconds",
+		"Max battery runtime after which system is shutdown.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"MAXLINEV",
+		MetricParseType::Voltage,
+		"apcupsd_max_since_startup_volts",
+		"Maximum input line voltage since apcupsd startup.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"MINLINEV",
+		MetricParseType::Voltage,
+		"apcupsd_min_since_startup_volts",
+		"Minimum input line voltage since apcupsd startup.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"OUTPUTV",
+		MetricParseType::Voltage,
+		"apcupsd_output_volts",
+		"Current UPS output voltage.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"DWAKE",
+		MetricParseType::Duration,
+		"apcupsd_power_on_delay_seconds",
+		"Time UPS waits after power off when the power is restored.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"DSHUTD",
+		MetricParseType::Duration,
+		"apcupsd_power_off_delay_seconds",
+		"Delay before UPS powers down after command received.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"DLOWBATT",
+		MetricParseType::Duration,
+		"apcupsd_battery_low_signal_time_left_seconds",
+		"Low battery signal sent when this much runtime remains.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"LOTRANS",
+		MetricParseType::Voltage,
+		"apcupsd_transfer_low_volts",
+		"Input line voltage below which UPS will switch to battery.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"HITRANS",
+		MetricParseType::Voltage,
+		"apcupsd_transfer_high_volts",
+		"Input line voltage above which UPS will switch to battery.",
+		MetricType::Gauge,
+	)?;
+	if let Some(margin) = transfer_window_margin {
+		rendered += &renderer.render_computed_metric(
+			"apcupsd_line_voltage_within_transfer_window",
+			"Whether the input line voltage is between the low and high transfer voltages.",
+			MetricType::Gauge,
+			f64::from(margin >= 0.),
+		);
+		rendered += &renderer.render_computed_metric(
+			"apcupsd_line_voltage_margin_volts",
+			"Distance from the input line voltage to the nearer transfer voltage, negative when outside of them.",
+			MetricType::Gauge,
+			margin,
+		);
+	}
+	rendered += &renderer.render_metric(
+		"RETPCT",
+		MetricParseType::Percentage,
+		"apcupsd_power_on_required_charge_percent",
+		"Battery charge % required after power off to restore power.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"ITEMP",
+		MetricParseType::Temperature,
+		"apcupsd_internal_temperature_celsius",
+		"UPS internal temperature in degrees Celcius.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"BATTV",
+		MetricParseType::Voltage,
+		"apcupsd_battery_volts",
+		"Current battery voltage.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"LINEFREQ",
+		MetricParseType::Frequency,
+		"apcupsd_line_frequency_hertz",
+		"Current line frequency in Hertz.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"OUTCURNT",
+		MetricParseType::Current,
+		"apcupsd_output_current_amps",
+		"Output current in Amps.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"NUMXFERS",
+		MetricParseType::Count,
+		"apcupsd_battery_number_transfers_total",
+		"Number of transfers to battery since apcupsd startup.",
+		MetricType::Counter,
+	)?;
+	rendered += &renderer.render_metric(
+		"XONBATT",
+		MetricParseType::Timestamp,
+		"apcupsd_last_transfer_on_battery_timestamp_seconds",
+		"Date, time of last transfer to battery since apcupsd startup.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"TONBATT",
+		MetricParseType::Duration,
+		"apcupsd_battery_time_on_seconds",
+		"Seconds currently on battery.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"CUMONBATT",
+		MetricParseType::Duration,
+		"apcupsd_battery_cumulative_time_on_seconds_total",
+		"Cumulative seconds on battery since apcupsd startup.",
+		MetricType::Counter,
+	)?;
+	rendered += &renderer.render_metric(
+		"XOFFBATT",
+		MetricParseConfig {
+			special_values: [("N/A", None)].into(),
+			..MetricParseType::Timestamp.into()
+		},
+		"apcupsd_last_transfer_off_battery_timestamp_seconds",
+		"Date, time of last transfer off battery since apcupsd startup.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"LASTSTEST",
+		MetricParseType::Timestamp,
+		"apcupsd_last_self_test_timestamp_seconds",
+		"Date, time of last self test.",
+		MetricType::Gauge,
+	)?;
+	if let Some(next_self_test) = next_self_test {
+		rendered += &renderer.render_computed_metric(
+			&render_options.timestamp_unit.metric_name("apcupsd_next_self_test_timestamp_seconds"),
+			"Date, time the next automatic self test is expected, based on the last self test and the self test interval.",
+			MetricType::Gauge,
+			render_options.timestamp_unit.scale(next_self_test),
+		);
+	}
+	if let Some(mut status_text_renderer) = renderer.status_text_renderer() {
+		rendered += &status_text_renderer.render_bitfield_metric("apcupsd_status_on_line", "On line.", apcupsd_bitmasks::status::UPS_ONLINE);
+		rendered += &status_text_renderer.render_bitfield_metric("apcupsd_status_on_battery", "On battery.", apcupsd_bitmasks::status::UPS_ONBATT);
+		rendered += &status_text_renderer.render_bitfield_metric("apcupsd_status_battery_low", "Battery low.", apcupsd_bitmasks::status::UPS_BATTLOW);
+	}
+	rendered += &renderer.render_raw_bitfields(
+		"apcupsd_status_flag_raw",
+		"Raw value of STATFLAG, for looking into bits that aren't decoded.",
+		&[("STATFLAG", None)],
+	);
+	if let Some(mut stat_renderer) = renderer.bitfield_renderer::<u32>("STATFLAG")? {
+		rendered += &stat_renderer.render_bitfield_metric(
+			"apcupsd_status_calibration",
+			"Runtime calibration occurring.",
+			apcupsd_bitmasks::status::UPS_CALIBRATION,
+		);
+		rendered += &stat_renderer.render_bitfield_metric("apcupsd_status_trim", "SmartTrim.", apcupsd_bitmasks::status::UPS_TRIM);
+		rendered += &stat_renderer.render_bitfield_metric("apcupsd_status_boost", "SmartBoost.", apcupsd_bitmasks::status::UPS_BOOST);
+		rendered += &stat_renderer.render_bitfield_metric("apcupsd_status_on_line", "On line.", apcupsd_bitmasks::status::UPS_ONLINE);
+		rendered += &stat_renderer.render_bitfield_metric("apcupsd_status_on_battery", "On battery.", apcupsd_bitmasks::status::UPS_ONBATT);
+		rendered += &stat_renderer.render_bitfield_metric(
+			"apcupsd_status_overloaded_output",
+			"Overloaded output.",
+			apcupsd_bitmasks::status::UPS_OVERLOAD,
+		);
+		rendered += &stat_renderer.render_bitfield_metric("apcupsd_status_battery_low", "Battery low.", apcupsd_bitmasks::status::UPS_BATTLOW);
+		rendered += &stat_renderer.render_bitfield_metric(
+			"apcupsd_status_replace_battery",
+			"Replace battery.",
+			apcupsd_bitmasks::status::UPS_REPLACEBATT,
+		);
+
+		rendered += &stat_renderer.render_bitfield_metric(
+			"apcupsd_status_communication_lost",
+			"Communications with UPS lost.",
+			apcupsd_bitmasks::status::UPS_COMMLOST,
+		);
+		rendered += &stat_renderer.render_bitfield_metric(
+			"apcupsd_status_shutdown_in_progress",
+			"Shutdown in progress.",
+			apcupsd_bitmasks::status::UPS_SHUTDOWN,
+		);
+		rendered += &stat_renderer.render_bitfield_metric("apcupsd_status_slave", "Set if this is a slave.", apcupsd_bitmasks::status::UPS_SLAVE);
+		rendered += &stat_renderer.render_bitfield_metric(
+			"apcupsd_status_slave_down",
+			"Slave not responding.",
+			apcupsd_bitmasks::status::UPS_SLAVEDOWN,
+		);
+		rendered += &stat_renderer.render_bitfield_metric(
+			"apcupsd_status_on_battery_message_sent",
+			"Set when UPS_ONBATT message is sent.",
+			apcupsd_bitmasks::status::UPS_ONBATT_MSG,
+		);
+		rendered += &stat_renderer.render_bitfield_metric(
+			"apcupsd_status_fast_poll",
+			"Set on power failure to poll faster.",
+			apcupsd_bitmasks::status::UPS_FASTPOLL,
+		);
+		rendered += &stat_renderer.render_bitfield_metric(
+			"apcupsd_status_shutdown_load",
+			"Set when BatLoad <= percent.",
+			apcupsd_bitmasks::status::UPS_SHUT_LOAD,
+		);
+		rendered += &stat_renderer.render_bitfield_metric(
+			"apcupsd_status_shutdown_time",
+			"Set when time on batts > maxtime.",
+			apcupsd_bitmasks::status::UPS_SHUT_BTIME,
+		);
+		rendered += &stat_renderer.render_bitfield_metric(
+			"apcupsd_status_shutdown_time_left",
+			"Set when TimeLeft <= runtime.",
+			apcupsd_bitmasks::status::UPS_SHUT_LTIME,
+		);
+		rendered += &stat_renderer.render_bitfield_metric(
+			"apcupsd_status_emergency_shutdown",
+			"Set when battery power has failed.",
+			apcupsd_bitmasks::status::UPS_SHUT_EMERG,
+		);
+		rendered += &stat_renderer.render_bitfield_metric(
+			"apcupsd_status_remote_shutdown",
+			"Set when remote shutdown.",
+			apcupsd_bitmasks::status::UPS_SHUT_REMOTE,
+		);
+		rendered += &stat_renderer.render_bitfield_metric(
+			"apcupsd_status_plugged_in",
+			"Set if computer is plugged into UPS.",
+			apcupsd_bitmasks::status::UPS_PLUGGED,
+		);
+		rendered += &stat_renderer.render_bitfield_metric(
+			"apcupsd_status_battery_present",
+			"Indicates if battery is connected.",
+			apcupsd_bitmasks::status::UPS_BATTPRESENT,
+		);
+	}
+	if let Some(mut dip_switch_renderer) = renderer.bitfield_renderer::<u8>("DIPSW")? {
+		rendered += &dip_switch_renderer.render_bitfield_metric(
+			"apcupsd_status_low_battery_alarm_delayed",
+			"Low battery alarm changed from 2 to 5 mins. Autostartup disabled on SU370ci and 400.",
+			apcupsd_bitmasks::dip_switch::LOW_BATTERY_5_MIN,
+		);
+		rendered += &dip_switch_renderer.render_bitfield_metric(
+			"apcupsd_status_audible_alarm_delayed",
+			"Audible alarm delayed 30 seconds.",
+			apcupsd_bitmasks::dip_switch::ALARM_DELAY_30_SEC,
+		);
+		rendered += &dip_switch_renderer.render_bitfield_metric(
+			"apcupsd_status_output_transfer_voltage_changed",
+			"Output transfer set to 115 VAC (from 120 VAC) or to 240 VAC (from 230 VAC).",
+			apcupsd_bitmasks::dip_switch::OUTPUT_TRANSFER_115_240_VOLTS,
+		);
+		rendered += &dip_switch_renderer.render_bitfield_metric(
+			"apcupsd_status_input_voltage_range_expanded",
+			"UPS desensitized - input voltage range expanded.",
+			apcupsd_bitmasks::dip_switch::INPUT_VOLTAGE_RANGE_EXPANDED,
+		);
+	}
+	rendered += &renderer.render_raw_bitfields(
+		"apcupsd_register_raw",
+		"Raw value of the Smart-UPS registers REG1-REG3, labelled by register, for looking into bits that aren't decoded.",
+		&[("REG1", Some("1")), ("REG2", Some("2")), ("REG3", Some("3"))],
+	);
+	if let Some(mut register_one_renderer) = renderer.bitfield_renderer::<u8>("REG1")? {
+		rendered += &register_one_renderer.render_bitfield_metric(
+			"apcupsd_status_wakeup_mode",
+			"In wakeup mode (typically lasts < 2s).",
+			apcupsd_bitmasks::register_one::WAKEUP_MODE,
+		);
+		rendered += &register_one_renderer.render_bitfield_metric(
+			"apcupsd_status_bypass_mode_from_internal_fault",
+			"In bypass mode due to internal fault.",
+			apcupsd_bitmasks::register_one::BYPASS_MODE_INTERNAL_FAULT,
+		);
+		rendered += &register_one_renderer.render_bitfield_metric(
+			"apcupsd_status_entering_bypass_mode_from_command",
+			"Going to bypass mode due to command.",
+			apcupsd_bitmasks::register_one::ENTERING_BYPASS_MODE_COMMAND,
+		);
+		rendered += &register_one_renderer.render_bitfield_metric(
+			"apcupsd_status_in_bypass_mode_from_command",
+			"In bypass mode due to command.",
+			apcupsd_bitmasks::register_one::IN_BYPASS_MODE_COMMAND,
+		);
+		rendered += &register_one_renderer.render_bitfield_metric(
+			"apcupsd_status_leaving_bypass_mode",
+			"Returning from bypass mode.",
+			apcupsd_bitmasks::register_one::LEAVING_BYPASS_MODE,
+		);
+		rendered += &register_one_renderer.render_bitfield_metric(
+			"apcupsd_status_in_bypass_mode_from_manual_control",
+			"In bypass mode due to manual bypass control.",
+			apcupsd_bitmasks::register_one::IN_BYPASS_MODE_MANUAL,
+		);
+		rendered += &register_one_renderer.render_bitfield_metric(
+			"apcupsd_status_ready_power_load_on_command",
+			"Ready to power load on user command.",
+			apcupsd_bitmasks::register_one::READY_POWER_LOAD_COMMAND,
+		);
+		rendered += &register_one_renderer.render_bitfield_metric(
+			"apcupsd_status_ready_power_load_on_command_or_line",
+			"Ready to power load on user command or return of line power.",
+			apcupsd_bitmasks::register_one::READY_POWER_LOAD_COMMAND_OR_LINE,
+		);
+	}
+	if let Some(mut register_two_renderer) = renderer.bitfield_renderer::<u8>("REG2")? {
+		rendered += &register_two_renderer.render_bitfield_metric(
+			"apcupsd_status_bypass_mode_from_electronics_fan_failure",
+			"Fan failure in electronics, UPS in bypass.",
+			apcupsd_bitmasks::register_two::BYPASS_MODE_FAN_FAILURE,
+		);
+		rendered += &register_two_renderer.render_bitfield_metric(
+			"apcupsd_status_isolation_unit_fan_failure",
+			"Fan failure in isolation unit.",
+			apcupsd_bitmasks::register_two::FAN_FAILURE_ISOLATION_UNIT,
+		);
+		rendered += &register_two_renderer.render_bitfield_metric(
+			"apcupsd_status_bypass_supply_failure",
+			"Bypass supply failure.",
+			apcupsd_bitmasks::register_two::BYPASS_SUPPLY_FAILURE,
+		);
+		rendered += &register_two_renderer.render_bitfield_metric(
+			"apcupsd_status_bypass_mode_from_output_voltage_select_failure",
+			"Output voltage select failure, UPS in bypass.",
+			apcupsd_bitmasks::register_two::BYPASS_MODE_OUTPUT_VOLTAGE_SELECT_FAILURE,
+		);
+		rendered += &register_two_renderer.render_bitfield_metric(
+			"apcupsd_status_bypass_mode_from_dc_imbalance",
+			"DC imbalance, UPS in bypass.",
+			apcupsd_bitmasks::register_two::BYPASS_MODE_DC_IMBALANCE,
+		);
+		rendered += &register_two_renderer.render_bitfield_metric(
+			"apcupsd_status_battery_disconnected",
+			"Battery is disconnected.",
+			apcupsd_bitmasks::register_two::BATTERY_DISCONNECTED,
+		);
+		rendered += &register_two_renderer.render_bitfield_metric(
+			"apcupsd_status_relay_fault_smarttrim_or_smartboost",
+			"Relay fault in SmartTrim or SmartBoost.",
+			apcupsd_bitmasks::register_two::RELAY_FAULT_SMARTTRIM_SMARTBOOST,
+		);
+		rendered += &register_two_renderer.render_bitfield_metric(
+			"apcupsd_status_bad_output_voltage",
+			"Bad output voltage.",
+			apcupsd_bitmasks::register_two::BAD_OUTPUT_VOLTAGE,
+		);
+	}
+	if let Some(mut register_three_renderer) = renderer.bitfield_renderer::<u8>("REG3")? {
+		rendered += &register_three_renderer.render_bitfield_metric(
+			"apcupsd_status_output_unpowered_from_low_battery_shutdown",
+			"Output unpowered due to shutdown by low battery.",
+			apcupsd_bitmasks::register_three::OUTPUT_UNPOWERED_LOW_BATTERY,
+		);
+		rendered += &register_three_renderer.render_bitfield_metric(
+			"apcupsd_status_cannot_transfer_to_battery_due_to_overload",
+			"Unable to transfer to battery due to overload.",
+			apcupsd_bitmasks::register_three::NO_TRANSFER_OVERLOAD,
+		);
+		rendered += &register_three_renderer.render_bitfield_metric(
+			"apcupsd_status_ups_off_from_main_relay_failure",
+			"Main relay malfunction - UPS turned off.",
+			apcupsd_bitmasks::register_three::RELAY_MALFUNCTION_POWER_OFF,
+		);
+		rendered += &register_three_renderer.render_bitfield_metric(
+			"apcupsd_status_sleep_mode_from_command",
+			"In sleep mode from @ command (maybe others).",
+			apcupsd_bitmasks::register_three::SLEEP_MODE_COMMAND,
+		);
+		rendered += &register_three_renderer.render_bitfield_metric(
+			"apcupsd_status_shutdown_mode_from_command",
+			"In shutdown mode from S command.",
+			apcupsd_bitmasks::register_three::SHUTDOWN_MODE_COMMAND,
+		);
+		rendered += &register_three_renderer.render_bitfield_metric(
+			"apcupsd_status_battery_charger_failure",
+			"Battery charger failure.",
+			apcupsd_bitmasks::register_three::BATTERY_CHARGER_FAILURE,
+		);
+		rendered += &register_three_renderer.render_bitfield_metric(
+			"apcupsd_status_bypass_relay_failure",
+			"Bypass relay malfunction.",
+			apcupsd_bitmasks::register_three::BYPASS_RELAY_FAILURE,
+		);
+		rendered += &register_three_renderer.render_bitfield_metric(
+			"apcupsd_status_operating_temperature_exceeded",
+			"Normal operating temperature exceeded.",
+			apcupsd_bitmasks::register_three::OPERATING_TEMPERATURE_EXCEEDED,
+		);
+	}
+	rendered += &renderer.render_ok_metric(
+		"LINEFAIL",
+		"apcupsd_line_ok",
+		"Whether line power is OK, as reported by old versions of apcupsd for simple signalling and Back-UPS models.",
+	);
+	rendered += &renderer.render_ok_metric(
+		"BATTSTAT",
+		"apcupsd_battery_ok",
+		"Whether the battery is OK, as reported by old versions of apcupsd for simple signalling and Back-UPS models.",
+	);
+	rendered += &renderer.render_status_flags();
+	if let Some(status_numeric) = status_numeric {
+		rendered += &renderer.render_computed_metric(
+			"apcupsd_status_numeric",
+			"Overall state: 0 unknown, 1 online, 2 on battery, 3 battery low, 4 communication lost, 5 shutting down, 6 overloaded, \
+			 7 replace battery, 8 calibrating, 9 trimming, 10 boosting.",
+			MetricType::Gauge,
+			status_numeric,
+		);
+	}
+	rendered += &renderer.render_metric(
+		"BATTDATE",
+		MetricParseType::Date,
+		"apcupsd_battery_last_replacement_timestamp_seconds",
+		"Date battery last replaced.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"NOMOUTV",
+		MetricParseType::Voltage,
+		"apcupsd_battery_nominal_output_volts",
+		"Nominal output voltage to supply when on battery power.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"NOMINV",
+		MetricParseType::Voltage,
+		"apcupsd_line_nominal_volts",
+		"Nominal AC input line voltage.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"NOMBATTV",
+		MetricParseType::Voltage,
+		"apcupsd_battery_nominal_volts",
+		"Nominal battery voltage.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"NOMPOWER",
+		MetricParseType::Power,
+		"apcupsd_nominal_power_watts",
+		"Nominal power output in watts.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"NOMAPNT",
+		MetricParseType::ApparentPower,
+		"apcupsd_apparent_power_volt_amps",
+		"Apparent power output in volt-amperes.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"HUMIDITY",
+		MetricParseType::Percentage,
+		"apcupsd_humidity_percent",
+		"Ambient humidity.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"AMBTEMP",
+		MetricParseType::Temperature,
+		"apcupsd_ambient_temperature_celsius",
+		"Ambient temperature.",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"EXTBATTS",
+		MetricParseType::Count,
+		"apcupsd_external_battery_count",
+		"Number of external batteries (for XL models).",
+		MetricType::Gauge,
+	)?;
+	rendered += &renderer.render_metric(
+		"BADBATTS",
+		MetricParseType::Count,
+		"apcupsd_external_battery_bad_count",
+		"Number of bad external battery packs (for XL models).",
+		MetricType::Gauge,
+	)?;
+	for (metric, value) in computed_values {
+		if let Some(value) = value {
+			let help = metric.help.as_deref().unwrap_or(metric.expr.source());
+			rendered += &renderer.render_computed_metric(&format!("apcupsd_{}", metric.name), help, MetricType::Gauge, value);
+		}
+	}
+	rendered += &renderer.render_held_value_counts();
+	rendered += &renderer.render_implausible_value_counts();
+
+	let mut apcupsd_data = renderer.into_remaining_data();
+	for ignored in FRAMING_KEYS.into_iter().chain(["STATUS"]) {
+		apcupsd_data.remove(ignored);
+	}
+
+	if !apcupsd_data.is_empty() {
+		syslog::warning(format!("Unknown keys: {:?}", apcupsd_data.keys()));
+	}
+
+	if let Some(sample_timestamp) = sample_timestamp {
+		rendered = with_sample_timestamps(&rendered, (sample_timestamp * 1000.) as i64);
+	}
+
+	Ok((rendered, apcupsd_data.len()))
+}
+
+/// Status flags told from the `STATUS` text, for devices that don't report `STATFLAG`.
+fn status_bits_from_text(status: &str) -> u32 {
+	// Old versions of apcupsd spell some flags out, e.g. "ON BATTERY".
+	let status = status.to_uppercase().replace(' ', "");
+	let mut bitfield = 0;
+	for (text, mask) in [
+		("CAL", apcupsd_bitmasks::status::UPS_CALIBRATION),
+		("TRIM", apcupsd_bitmasks::status::UPS_TRIM),
+		("BOOST", apcupsd_bitmasks::status::UPS_BOOST),
+		("ONLINE", apcupsd_bitmasks::status::UPS_ONLINE),
+		("ONBATT", apcupsd_bitmasks::status::UPS_ONBATT),
+		("OVERLOAD", apcupsd_bitmasks::status::UPS_OVERLOAD),
+		("LOWBATT", apcupsd_bitmasks::status::UPS_BATTLOW),
+		("BATTERYLOW", apcupsd_bitmasks::status::UPS_BATTLOW),
+		("REPLACEBATT", apcupsd_bitmasks::status::UPS_REPLACEBATT),
+		("COMMLOST", apcupsd_bitmasks::status::UPS_COMMLOST),
+		("SHUTTINGDOWN", apcupsd_bitmasks::status::UPS_SHUTDOWN),
+	] {
+		if status.contains(text) {
+			bitfield |= mask;
+		}
+	}
+	bitfield
+}
+
+/// The overall state of the UPS as a stable number, for dashboards that map values to text instead of showing every status flag:
+/// 0 unknown, 1 online, 2 on battery, 3 battery low, 4 communication lost, 5 shutting down, 6 overloaded, 7 replace battery,
+/// 8 calibrating, 9 trimming, 10 boosting. The most severe state wins.
+fn status_numeric(apcupsd_data: &HashMap<String, String>) -> Option<f64> {
+	use apcupsd_bitmasks::status::*;
+	let bitfield = match apcupsd_data.get("STATFLAG") {
+		Some(hex) => u32::from_str_radix(hex.get(2..)?, 16).ok()?,
+		None => status_bits_from_text(apcupsd_data.get("STATUS")?),
+	};
+	let states = [
+		(UPS_COMMLOST, 4.),
+		(UPS_SHUTDOWN, 5.),
+		(UPS_BATTLOW, 3.),
+		(UPS_ONBATT, 2.),
+		(UPS_OVERLOAD, 6.),
+		(UPS_REPLACEBATT, 7.),
+		(UPS_CALIBRATION, 8.),
+		(UPS_TRIM, 9.),
+		(UPS_BOOST, 10.),
+		(UPS_ONLINE, 1.),
+	];
+	Some(states.into_iter().find(|(mask, _)| bitfield & mask != 0).map_or(0., |(_, state)| state))
+}
+
+/// Distance from the line voltage to the nearer of the low (`LOTRANS`) and high (`HITRANS`) transfer voltages, negative when it's
+/// outside of them.
+fn transfer_window_margin(apcupsd_data: &HashMap<String, String>) -> Option<f64> {
+	let voltage = |key: &str| parse_metric(apcupsd_data.get(key)?.clone(), MetricParseType::Voltage.into()).ok()?;
+	let line = voltage("LINEV")?;
+	Some((line - voltage("LOTRANS")?).min(voltage("HITRANS")? - line))
+}
+
+/// When the next automatic self test is due, from the last self test and the self test interval in hours (`STESTI`), which is
+/// `OFF`, `ON` (only at power on), or `None` when there are no periodic self tests.
+fn next_self_test_timestamp(apcupsd_data: &HashMap<String, String>) -> Option<f64> {
+	let interval_hours = apcupsd_data.get("STESTI")?.parse::<f64>().ok()?;
+	let last_self_test = parse_metric(apcupsd_data.get("LASTSTEST")?.clone(), MetricParseType::Timestamp.into()).ok()??;
+	Some(last_self_test + interval_hours * 3600.)
+}
+
+/// Options that affect how apcupsd data is rendered.
+#[derive(Clone, Default)]
+pub struct RenderOptions {
+	/// Which reload of the configuration the options are from, so metrics rendered with the options before a reload aren't served from
+	/// the cache of hosts kept across it.
+	pub generation: u64,
+	pub status_metrics: StatusMetrics,
+	pub labels: LabelOptions,
+	/// Seconds to keep reporting the last good value of each key when it goes missing or can't be parsed.
+	pub hold_last_value_seconds: HashMap<String, f64>,
+	/// Sentinel values of each key in addition to the built in ones, mapped to the value to export instead or `None` if missing.
+	pub special_values: HashMap<String, HashMap<String, Option<f64>>>,
+	/// Voltage, temperature, and frequency keys that may be reported as a plain number without their unit.
+	pub unitless_keys: HashSet<String>,
+	/// Name and type of keys exported differently than built in.
+	pub metric_overrides: HashMap<String, MetricOverride>,
+	/// Range of plausible values of each key, outside of which values are dropped.
+	pub plausible_ranges: HashMap<String, PlausibleRange>,
+	/// Quirks of families of UPS models, the first matching of which applies.
+	pub model_families: Vec<ModelFamily>,
+	/// Whether percentages are exported as 0-1 or 0-100.
+	pub percent_scale: PercentScale,
+	/// Whether timestamps are exported in seconds or milliseconds.
+	pub timestamp_unit: TimestampUnit,
+	/// Whether samples have the `DATE` reported by apcupsd as their timestamp.
+	pub sample_timestamps: bool,
+	/// Number of decimal places values are rounded to, if any.
+	pub precision: Option<u32>,
+	/// Clock skew above which a warning is logged.
+	pub clock_skew_warning_seconds: f64,
+	/// Extra metrics computed from apcupsd keys.
+	pub computed: Vec<ComputedMetric>,
+	/// Whether rollups across the hosts in a scrape are exported.
+	pub site_rollups: bool,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PercentScale {
+	/// Divide percentages by 100, keeping the `_percent` suffix they've always been exported with.
+	#[default]
+	Fraction,
+	/// Divide percentages by 100, exported with a `_ratio` suffix as Prometheus' naming conventions recommend.
+	Ratio,
+	/// Export percentages as reported, from 0 to 100, with a `_percent` suffix like other UPS exporters.
+	Percent,
+}
+
+impl PercentScale {
+	/// The value a percentage of 100 is exported as.
+	pub fn full(self) -> f64 {
+		match self {
+			PercentScale::Fraction | PercentScale::Ratio => 1.,
+			PercentScale::Percent => 100.,
+		}
+	}
+
+	/// The name of a percentage metric with its `_percent` suffix replaced by `_ratio` if it's exported as a ratio.
+	pub fn metric_name(self, name: &str) -> String {
+		match (self, name.strip_suffix("_percent")) {
+			(PercentScale::Ratio, Some(name)) => format!("{name}_ratio"),
+			_ => name.to_string(),
+		}
+	}
+
+	/// The help of a percentage metric with the range it's exported in, except for `fraction`, whose help is kept as it was.
+	pub fn help(self, help: &str) -> String {
+		match self {
+			PercentScale::Fraction => help.to_string(),
+			PercentScale::Ratio | PercentScale::Percent => format!("{}, from 0 to {}.", help.trim_end_matches('.'), self.full()),
+		}
+	}
+}
+
+#[derive(Clone, Default, Deserialize, JsonSchema, Serialize)]
+pub struct MetricOverride {
+	/// Full name of the metric, used as is (no `_ratio` suffix is applied to percentages); default the built in name.
+	#[serde(default)]
+	pub name: Option<String>,
+	/// Type of the metric; default the built in type.
+	#[serde(default, rename = "type")]
+	pub metric_type: Option<OverrideMetricType>,
+}
+
+#[derive(Clone, Copy, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverrideMetricType {
+	Gauge,
+	Counter,
+}
+
+impl From<OverrideMetricType> for MetricType {
+	fn from(metric_type: OverrideMetricType) -> Self {
+		match metric_type {
+			OverrideMetricType::Gauge => MetricType::Gauge,
+			OverrideMetricType::Counter => MetricType::Counter,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema, Serialize)]
+pub struct PlausibleRange {
+	#[serde(default)]
+	pub min: Option<f64>,
+	#[serde(default)]
+	pub max: Option<f64>,
+}
+
+impl PlausibleRange {
+	/// Whether the number a value is reported with (e.g. 120 in `120.0 Volts`) is within the range. Values that don't start with a number
+	/// are left to be parsed.
+	pub fn contains(&self, value: &str) -> bool {
+		match value.split_whitespace().next().and_then(|number| number.parse::<f64>().ok()) {
+			Some(number) => self.min.is_none_or(|min| number >= min) && self.max.is_none_or(|max| number <= max),
+			None => true,
+		}
+	}
+}
+
+fn default_plausible_ranges() -> HashMap<String, PlausibleRange> {
+	let voltage = PlausibleRange {
+		min: Some(0.),
+		max: Some(500.),
+	};
+	let percentage = PlausibleRange {
+		min: Some(0.),
+		max: Some(100.),
+	};
+	[
+		("LINEV", voltage),
+		("MAXLINEV", voltage),
+		("MINLINEV", voltage),
+		("OUTPUTV", voltage),
+		("BATTV", voltage),
+		("BCHARGE", percentage),
+		("LOADPCT", percentage),
+		("LOADAPNT", percentage),
+	]
+	.into_iter()
+	.map(|(key, range)| (key.to_string(), range))
+	.collect()
+}
+
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampUnit {
+	/// Seconds since the Unix epoch, with a `_seconds` suffix.
+	#[default]
+	Seconds,
+	/// Milliseconds since the Unix epoch, with a `_milliseconds` suffix.
+	Milliseconds,
+}
+
+impl TimestampUnit {
+	/// The name of a timestamp metric with its `_seconds` suffix replaced by `_milliseconds` if it's exported in milliseconds.
+	pub fn metric_name(self, name: &str) -> String {
+		match (self, name.strip_suffix("_seconds")) {
+			(TimestampUnit::Milliseconds, Some(name)) => format!("{name}_milliseconds"),
+			_ => name.to_string(),
+		}
+	}
+
+	/// A timestamp in seconds converted to this unit.
+	pub fn scale(self, seconds: f64) -> f64 {
+		match self {
+			TimestampUnit::Seconds => seconds,
+			TimestampUnit::Milliseconds => seconds * 1000.,
+		}
+	}
+}
+
+/// Append `timestamp_millis` to every sample line of `rendered`.
+fn with_sample_timestamps(rendered: &str, timestamp_millis: i64) -> String {
+	rendered
+		.lines()
+		.map(|line| {
+			if line.is_empty() || line.starts_with('#') {
+				format!("{line}\n")
+			} else {
+				format!("{line} {timestamp_millis}\n")
+			}
+		})
+		.collect()
+}
+
+/// Round `value` to `precision` decimal places, to avoid long float tails like `0.30000000000000004`.
+pub fn round_to_precision(value: f64, precision: Option<u32>) -> f64 {
+	match precision {
+		Some(precision) => {
+			let scale = 10f64.powi(precision.try_into().unwrap_or(i32::MAX));
+			(value * scale).round() / scale
+		},
+		None => value,
+	}
+}
+
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusMetrics {
+	/// A separate metric for each status flag.
+	#[default]
+	Individual,
+	/// A single `apcupsd_status_flag` metric with a `flag` label, to reduce the number of metric names.
+	SingleLabeled,
+	/// Both individual and single labeled metrics.
+	Both,
+}
+
+struct MetricRenderer<'a> {
+	labels: Vec<(String, String)>,
+	apcupsd_data: HashMap<String, String>,
+	status_metrics: StatusMetrics,
+	status_flags: Vec<(String, f64)>,
+	hold_last_value_seconds: &'a HashMap<String, f64>,
+	held_values: &'a mut HeldValues,
+	special_values: &'a HashMap<String, HashMap<String, Option<f64>>>,
+	unitless_keys: &'a HashSet<String>,
+	metric_overrides: &'a HashMap<String, MetricOverride>,
+	plausible_ranges: &'a HashMap<String, PlausibleRange>,
+	model_family: Option<&'a ModelFamily>,
+	date_format: DateFormat,
+	percent_scale: PercentScale,
+	timestamp_unit: TimestampUnit,
+	precision: Option<u32>,
+}
+
+impl<'a> MetricRenderer<'a> {
+	pub fn new(
+		labels: Vec<(String, String)>,
+		apcupsd_data: HashMap<String, String>,
+		render_options: &'a RenderOptions,
+		held_values: &'a mut HeldValues,
+		model_family: Option<&'a ModelFamily>,
+		date_format: DateFormat,
+	) -> Self {
+		Self {
+			labels,
+			apcupsd_data,
+			status_metrics: render_options.status_metrics,
+			status_flags: Vec::new(),
+			hold_last_value_seconds: &render_options.hold_last_value_seconds,
+			held_values,
+			special_values: &render_options.special_values,
+			unitless_keys: &render_options.unitless_keys,
+			metric_overrides: &render_options.metric_overrides,
+			plausible_ranges: &render_options.plausible_ranges,
+			model_family,
+			date_format,
+			percent_scale: render_options.percent_scale,
+			timestamp_unit: render_options.timestamp_unit,
+			precision: render_options.precision,
+		}
+	}
+
+	pub fn render_metric(
+		&mut self,
+		key: &str,
+		parse_config: impl Into<MetricParseConfig>,
+		name: &str,
+		help: &str,
+		metric_type: MetricType,
+	) -> Result<String, RenderMetricsError> {
+		let mut parse_config = MetricParseConfig {
+			unit_optional: self.unitless_keys.contains(key) || self.model_family.is_some_and(|family| family.unitless_keys.contains(key)),
+			percent_scale: self.percent_scale,
+			date_format: self.date_format,
+			..parse_config.into()
+		};
+		if let Some(&parse_type) = self.model_family.and_then(|family| family.parse_as.get(key)) {
+			parse_config.parse_type = parse_type;
+		}
+		let metric_override = self.model_family.and_then(|family| family.metric_overrides.get(key)).or_else(|| self.metric_overrides.get(key));
+		let help = match parse_config.parse_type {
+			MetricParseType::Percentage => self.percent_scale.help(help),
+			_ => help.to_string(),
+		};
+		let name = match (metric_override.and_then(|o| o.name.as_ref()), parse_config.parse_type) {
+			(Some(name), _) => name.clone(),
+			(None, MetricParseType::Percentage) => self.percent_scale.metric_name(name),
+			(None, MetricParseType::Timestamp | MetricParseType::Date) => self.timestamp_unit.metric_name(name),
+			(None, _) => name.to_string(),
+		};
+		let metric_type = metric_override.and_then(|o| o.metric_type).map_or(metric_type, MetricType::from);
+		let is_timestamp = matches!(parse_config.parse_type, MetricParseType::Timestamp | MetricParseType::Date);
+		let mut value = self
+			.apcupsd_data
+			.remove(key)
+			.filter(|v| match self.plausible_ranges.get(key) {
+				Some(range) if !range.contains(v) => {
+					self.held_values.count_implausible(key);
+					false
+				},
+				_ => true,
+			})
+			.and_then(|v| {
+				match self.model_family.and_then(|family| family.special_values.get(key)?.get(&v)).or_else(|| self.special_values.get(key)?.get(&v)) {
+					Some(special_value) => special_value.map(Ok),
+					None => parse_metric(v, parse_config).transpose(),
+				}
+			})
+			.transpose()
+			.map_err(|e| RenderMetricsError::ParseMetricError {
+				key: key.to_string(),
+				error: e,
+			});
+		if self.model_family.is_some_and(|family| family.fraction_keys.contains(key)) {
+			value = value.map(|value| value.map(|value| value * 100.));
+		}
+		if is_timestamp {
+			value = value.map(|value| value.map(|value| self.timestamp_unit.scale(value)));
+		}
+		if let Some(&hold_seconds) = self.hold_last_value_seconds.get(key) {
+			value = Ok(self.held_values.hold(key, value.ok().flatten(), hold_seconds));
+		}
+		if let Some(value) = value? {
+			Ok(PrometheusMetric::build()
+				.with_name(&name)
+				.with_help(&help)
+				.with_metric_type(metric_type)
+				.build()
+				.render_and_append_instance(&prometheus_instance_with_labels(&self.labels).with_value(round_to_precision(value, self.precision)))
+				.render())
+		} else {
+			Ok(String::new())
+		}
+	}
+
+	/// Render a metric whose value is computed from other keys rather than parsed from a single one.
+	pub fn render_computed_metric(&self, name: &str, help: &str, metric_type: MetricType, value: f64) -> String {
+		PrometheusMetric::build()
+			.with_name(name)
+			.with_help(help)
+			.with_metric_type(metric_type)
+			.build()
+			.render_and_append_instance(&prometheus_instance_with_labels(&self.labels).with_value(round_to_precision(value, self.precision)))
+			.render()
+	}
+
+	/// Render a gauge that is 1 if the value of `key` is `OK` and 0 otherwise.
+	pub fn render_ok_metric(&mut self, key: &str, name: &str, help: &str) -> String {
+		match self.apcupsd_data.remove(key) {
+			Some(value) => self.render_computed_metric(name, help, MetricType::Gauge, f64::from(value == "OK")),
+			None => String::new(),
+		}
+	}
+
+	pub fn render_held_value_counts(&self) -> String {
+		self.held_values.render(self.hold_last_value_seconds.keys(), &self.labels)
+	}
+
+	pub fn render_implausible_value_counts(&self) -> String {
+		self.held_values.render_implausible(self.plausible_ranges.keys(), &self.labels)
+	}
+
+	pub fn bitfield_renderer<T: BitfieldType>(&mut self, key: &str) -> Result<Option<BitfieldMetricRenderer<'_, T>>, RenderMetricsError> {
+		if let Some(hex) = self.apcupsd_data.remove(key) {
+			let bitfield =
+				hex.get(2..).map(|h| T::from_str_radix(h, 16)).transpose().ok().flatten().ok_or_else(|| RenderMetricsError::ParseMetricError {
+					key: key.to_string(),
+					error: ParseMetricError::InvalidHex(hex),
+				})?;
+			Ok(Some(BitfieldMetricRenderer {
+				labels: self.labels.clone(),
+				bitfield,
+				status_metrics: self.status_metrics,
+				status_flags: &mut self.status_flags,
+			}))
+		} else {
+			Ok(None)
+		}
+	}
+
+	/// Render the raw value of each of the hex bitfield `keys` that's present, with a `register` label if given.
+	pub fn render_raw_bitfields(&self, name: &str, help: &str, keys: &[(&str, Option<&str>)]) -> String {
+		let values: Vec<_> = keys
+			.iter()
+			.filter_map(|(key, register)| Some((*register, u32::from_str_radix(self.apcupsd_data.get(*key)?.get(2..)?, 16).ok()?)))
+			.collect();
+		if values.is_empty() {
+			return String::new();
+		}
+		let mut metric = PrometheusMetric::build().with_name(name).with_help(help).with_metric_type(MetricType::Gauge).build();
+		for (register, value) in values {
+			let instance = prometheus_instance_with_labels(&self.labels).with_value(value);
+			metric.render_and_append_instance(&match register {
+				Some(register) => instance.with_label("register", register),
+				None => instance,
+			});
+		}
+		metric.render()
+	}
+
+	/// A bitfield renderer for the flags that can be told from the `STATUS` text, for devices that don't report `STATFLAG` (such as UPSes
+	/// on simple signalling cables). Only the online, on battery, and battery low flags should be rendered with it.
+	pub fn status_text_renderer(&mut self) -> Option<BitfieldMetricRenderer<'_, u32>> {
+		if self.apcupsd_data.contains_key("STATFLAG") {
+			return None;
+		}
+		let bitfield = status_bits_from_text(self.apcupsd_data.get("STATUS")?);
+		Some(BitfieldMetricRenderer {
+			labels: self.labels.clone(),
+			bitfield,
+			status_metrics: self.status_metrics,
+			status_flags: &mut self.status_flags,
+		})
+	}
+
+	/// Render the flags collected by bitfield renderers as a single metric labelled by flag.
+	pub fn render_status_flags(&mut self) -> String {
+		let status_flags = std::mem::take(&mut self.status_flags);
+		if status_flags.is_empty() {
+			return String::new();
+		}
+		let mut metric = PrometheusMetric::build()
+			.with_name("apcupsd_status_flag")
+			.with_help("Status flags reported by apcupsd, labelled by flag.")
+			.with_metric_type(MetricType::Gauge)
+			.build();
+		for (flag, value) in &status_flags {
+			metric.render_and_append_instance(&prometheus_instance_with_labels(&self.labels).with_label("flag", flag.as_str()).with_value(*value));
+		}
+		metric.render()
+	}
+
+	pub fn into_remaining_data(self) -> HashMap<String, String> {
+		self.apcupsd_data
+	}
+}
+
+trait BitfieldType: Unsigned + BitAnd<Self, Output = Self> + PartialEq + Copy {}
+impl<T: Unsigned + BitAnd<Self, Output = Self> + PartialEq + Copy> BitfieldType for T {}
+
+struct BitfieldMetricRenderer<'a, T: BitfieldType> {
+	labels: Vec<(String, String)>,
+	bitfield: T,
+	status_metrics: StatusMetrics,
+	status_flags: &'a mut Vec<(String, f64)>,
+}
+
+impl<T: BitfieldType> BitfieldMetricRenderer<'_, T> {
+	pub fn render_bitfield_metric(&mut self, name: &str, help: &str, mask: T) -> String {
+		let value = f64::from(self.bitfield & mask != T::zero());
+		if matches!(self.status_metrics, StatusMetrics::SingleLabeled | StatusMetrics::Both) {
+			self.status_flags.push((name.strip_prefix("apcupsd_status_").unwrap_or(name).to_string(), value));
+		}
+		if matches!(self.status_metrics, StatusMetrics::Individual | StatusMetrics::Both) {
+			PrometheusMetric::build()
+				.with_name(name)
+				.with_help(help)
+				.with_metric_type(MetricType::Gauge)
+				.build()
+				.render_and_append_instance(&prometheus_instance_with_labels(&self.labels).with_value(value))
+				.render()
+		} else {
+			String::new()
+		}
+	}
+}
+
+#[derive(Error, Debug)]
+pub enum RenderMetricsError {
+	#[error("{key}: {error}")]
+	ParseMetricError { key: String, error: ParseMetricError },
+}
+
+pub struct MetricParseConfig {
+	parse_type: MetricParseType,
+	special_values: HashMap<&'static str, Option<f64>>,
+	/// Accept a plain number for units that are normally suffixed, as reported by some drivers (e.g. `27.1` instead of `27.1 Volts`).
+	unit_optional: bool,
+	percent_scale: PercentScale,
+	/// Format timestamps are tried in first, from the version of apcupsd that reported them.
+	date_format: DateFormat,
+}
+
+#[derive(Clone, Copy, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricParseType {
+	Timestamp,
+	Date,
+	Duration,
+	Percentage,
+	Voltage,
+	Temperature,
+	Frequency,
+	Current,
+	Count,
+	Power,
+	ApparentPower,
+}
+
+impl From<MetricParseType> for MetricParseConfig {
+	fn from(value: MetricParseType) -> Self {
+		Self {
+			parse_type: value,
+			special_values: HashMap::new(),
+			unit_optional: false,
+			percent_scale: PercentScale::Fraction,
+			date_format: DateFormat::default(),
+		}
+	}
+}
+
+pub fn parse_metric(value: String, parse_config: MetricParseConfig) -> Result<Option<f64>, ParseMetricError> {
+	if let Some(special_value) = parse_config.special_values.get(value.as_str()) {
+		return Ok(*special_value);
+	}
+	match parse_config.parse_type {
+		MetricParseType::Timestamp => {
+			let formats = match parse_config.date_format {
+				DateFormat::Iso => ["%Y-%m-%d %H:%M:%S %z", "%a %b %d %X %z %Y"],
+				DateFormat::Historic => ["%a %b %d %X %z %Y", "%Y-%m-%d %H:%M:%S %z"],
+			};
+			DateTime::parse_from_str(&value, formats[0])
+				.or_else(|_| DateTime::parse_from_str(&value, formats[1]))
+				.map(|t| Some(t.timestamp() as f64))
+				.map_err(|e| ParseMetricError::InvalidTimestamp(value, e.to_string()))
+		},
+		MetricParseType::Date => NaiveDate::parse_from_str(&value, "%Y-%m-%d")
+			.or_else(|_| NaiveDate::parse_from_str(&value, "%m/%d/%y"))
+			.map(|t| Some(t.and_time(NaiveTime::MIN).and_utc().timestamp() as f64))
+			.map_err(|e| ParseMetricError::InvalidDate(value, e.to_string())),
+		MetricParseType::Duration => match value.split_once(" ") {
+			Some((s, "Seconds")) => s.parse::<f64>().map(Some).map_err(|_| ()),
+			Some((s, "Minutes")) => s.parse::<f64>().map(|m| Some(m * 60.)).map_err(|_| ()),
+			Some((_, _)) => Err(()),
+			None => Err(()),
+		}
+		.map_err(|_| ParseMetricError::InvalidDuration(value)),
+		MetricParseType::Percentage => match value.strip_suffix(" Percent") {
+			Some(v) => v
+				.parse::<f64>()
+				.map(|v| match parse_config.percent_scale {
+					PercentScale::Fraction | PercentScale::Ratio => Some(v / 100.),
+					PercentScale::Percent => Some(v),
+				})
+				.map_err(|_| ParseMetricError::InvalidPercentage(value)),
+			None => Err(ParseMetricError::InvalidPercentage(value)),
+		},
+		MetricParseType::Voltage => match value.strip_suffix(" Volts").or(parse_config.unit_optional.then_some(value.as_str())) {
+			Some(v) => v.parse::<f64>().map(Some).map_err(|_| ParseMetricError::InvalidVoltage(value)),
+			None => Err(ParseMetricError::InvalidVoltage(value)),
+		},
+		// Temperatures are exported in Celsius, which is also what apcupsd means by internal units.
+		MetricParseType::Temperature => match value.split_once(' ') {
+			Some((v, "C" | "internal")) => v.parse::<f64>().map(Some).map_err(|_| ParseMetricError::InvalidTemperature(value)),
+			Some((v, "F")) => v.parse::<f64>().map(|f| Some((f - 32.) * 5. / 9.)).map_err(|_| ParseMetricError::InvalidTemperature(value)),
+			None if parse_config.unit_optional => value.parse::<f64>().map(Some).map_err(|_| ParseMetricError::InvalidTemperature(value)),
+			_ => Err(ParseMetricError::InvalidTemperature(value)),
+		},
+		MetricParseType::Frequency => match value.strip_suffix(" Hz").or(parse_config.unit_optional.then_some(value.as_str())) {
+			Some(v) => v.parse::<f64>().map(Some).map_err(|_| ParseMetricError::InvalidFrequency(value)),
+			None => Err(ParseMetricError::InvalidFrequency(value)),
+		},
+		MetricParseType::Current => match value.strip_suffix(" Amps") {
+			Some(v) => v.parse::<f64>().map(Some).map_err(|_| ParseMetricError::InvalidCurrent(value)),
+			None => Err(ParseMetricError::InvalidCurrent(value)),
+		},
+		MetricParseType::Count => value.parse::<f64>().map(Some).map_err(|_| ParseMetricError::InvalidCount(value)),
+		MetricParseType::Power => match value.strip_suffix(" Watts") {
+			Some(v) => v.parse::<f64>().map(Some).map_err(|_| ParseMetricError::InvalidPower(value)),
+			None => Err(ParseMetricError::InvalidPower(value)),
+		},
+		MetricParseType::ApparentPower => match value.strip_suffix(" VA") {
+			Some(v) => v.parse::<f64>().map(Some).map_err(|_| ParseMetricError::InvalidApparentPower(value)),
+			None => Err(ParseMetricError::InvalidApparentPower(value)),
+		},
+	}
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Error, Debug)]
+pub enum ParseMetricError {
+	#[error("invalid timestamp \"{0}\" {1}")]
+	InvalidTimestamp(String, String),
+	#[error("invalid date \"{0}\" {1}")]
+	InvalidDate(String, String),
+	#[error("invalid duration \"{0}\"")]
+	InvalidDuration(String),
+	#[error("invalid percentage \"{0}\"")]
+	InvalidPercentage(String),
+	#[error("invalid voltage \"{0}\"")]
+	InvalidVoltage(String),
+	#[error("invalid temperature \"{0}\"")]
+	InvalidTemperature(String),
+	#[error("invalid frequency \"{0}\"")]
+	InvalidFrequency(String),
+	#[error("invalid current \"{0}\"")]
+	InvalidCurrent(String),
+	#[error("invalid count \"{0}\"")]
+	InvalidCount(String),
+	#[error("invalid power \"{0}\"")]
+	InvalidPower(String),
+	#[error("invalid apparent power \"{0}\"")]
+	InvalidApparentPower(String),
+	#[error("invalid hex value \"{0}\"")]
+	InvalidHex(String),
+}
+
+/// Throttle the number of times data is fetched from apcupsd, returning previous data instead if the wait time hasn't been reached.
+#[derive(Clone)]
+struct APCThrottledAccess {
+	inner: Arc<Mutex<APCThrottledAccessInner>>,
+	/// Number of fetches that didn't finish before the deadline, shared with `inner` so it can be read while a fetch is running.
+	abandoned_fetches: Arc<AtomicU64>,
+	/// Number of responses still missing their footer after a retry, shared with `inner` like `abandoned_fetches`.
+	truncated_responses: Arc<AtomicU64>,
+	/// Number of failed resolutions of endpoint names, shared with `inner` like `abandoned_fetches`.
+	dns_failures: Arc<AtomicU64>,
+}
+
+/// Timeout for connecting to and reading from a NIS server.
+const NIS_TIMEOUT: Duration = Duration::from_millis(500);
+
+fn nis_client(host: String, port: u16) -> NisClient {
+	NisClient {
+		host,
+		port,
+		timeout: NIS_TIMEOUT,
+	}
+}
+
+/// `host:port`, with IPv6 addresses in brackets like in URLs, `ssh -L` forwarding specifications, and `CONNECT` requests.
+fn host_port(host: &str, port: u16) -> String {
+	match host.parse::<std::net::Ipv6Addr>() {
+		Ok(_) => format!("[{host}]:{port}"),
+		Err(_) => format!("{host}:{port}"),
+	}
+}
+
+/// A NIS server to fetch from.
+struct Endpoint {
+	/// `address:port` as configured.
+	name: String,
+	client: NisClient,
+	/// Port `client` connects to, kept to connect to newly resolved addresses.
+	port: u16,
+	/// The configured address, if it's a name resolved by the exporter.
+	resolved_name: Option<ResolvedName>,
+	/// Ports probed in order when `port` refuses connections, switching `port` to the first that accepts them; empty unless
+	/// `probe_alternate_ports` is set.
+	alternate_ports: Vec<u16>,
+}
+
+struct APCThrottledAccessInner {
+	/// The NIS servers to fetch from in order of preference.
+	endpoints: Vec<Endpoint>,
+	/// Index of the endpoint that answered the last successful fetch.
+	endpoint: usize,
+	wait_time: Duration,
+	on_battery_wait_time: Duration,
+	last_call: Instant,
+	last_success: Option<SystemTime>,
+	fetch_duration: Duration,
+	/// How long each phase of fetching from the endpoint that answered took, if the data was fetched from a NIS server.
+	fetch_timings: Option<FetchTimings>,
+	data: Result<HashMap<String, String>, std::io::ErrorKind>,
+	statistics: PollStatistics,
+	history: Option<Arc<std::sync::Mutex<History>>>,
+	history_store: Option<HostHistoryStore>,
+	/// Detects power events to notify about, if enabled.
+	notifier: Option<HostNotifier>,
+	/// Compared to the runtime estimate of every fetch, if enabled.
+	runtime_baselines: Option<HostRuntimeBaselines>,
+	/// Runtime estimate of the last successful fetch compared to its baseline, if comparable.
+	runtime_degradation_ratio: Option<f64>,
+	abandoned_fetches: Arc<AtomicU64>,
+	truncated_responses: Arc<AtomicU64>,
+	dns_failures: Arc<AtomicU64>,
+	shared_cache: Option<SharedCache>,
+	/// File the last successfully fetched data is saved to, if enabled.
+	snapshot_path: Option<PathBuf>,
+	/// Data loaded from `snapshot_path` at startup, served in place of failed fetches until one succeeds.
+	saved: Option<HashMap<String, String>>,
+	/// The status file read instead of `endpoints`, if configured.
+	status_file: Option<StatusFileOptions>,
+}
+
+/// How long a fetch may take for each endpoint it tries before it's abandoned, well past `NIS_TIMEOUT`.
+const FETCH_DEADLINE_PER_ENDPOINT: Duration = Duration::from_secs(2);
+
+/// A random number in `[0, 1)`, from the randomly keyed hasher of the standard library so no random number generator is needed.
+fn random_fraction() -> f64 {
+	(RandomState::new().build_hasher().finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Whether a response is missing the footer apcupsd ends its status output with.
+fn is_truncated(apcupsd_data: &HashMap<String, String>) -> bool {
+	!FRAMING_KEYS[1..].iter().any(|footer| apcupsd_data.contains_key(*footer))
+}
+
+/// Data fetched from apcupsd along with information about how it was fetched.
+#[derive(Clone)]
+struct ApcupsdSnapshot {
+	data: HashMap<String, String>,
+	/// Whether the data came from the cache instead of being fetched by this call.
+	stale: bool,
+	/// When the data was fetched, which identifies snapshots of the same data.
+	fetched_at: Instant,
+	last_success: Option<SystemTime>,
+	/// How long the fetch the data came from took.
+	fetch_duration: Duration,
+	/// How long each phase of the fetch took, if the data was fetched from a NIS server.
+	fetch_timings: Option<FetchTimings>,
+	/// `address:port` of the NIS server the data came from.
+	endpoint: String,
+	statistics: PollStatistics,
+	/// Whether the data was saved before the exporter restarted, since no fetch has succeeded yet; `None` without `snapshot_directory`.
+	restored: Option<bool>,
+	/// Port the NIS server the data came from was found on, if `probe_alternate_ports` is set.
+	probed_port: Option<u16>,
+	/// Runtime estimate compared to its baseline, if `runtime_degradation` is enabled and the estimate is comparable.
+	runtime_degradation_ratio: Option<f64>,
+}
+
+impl ApcupsdSnapshot {
+	/// How far ahead of the exporter's clock the `DATE` reported by apcupsd was when the data was fetched.
+	pub fn clock_skew_seconds(&self) -> Option<f64> {
+		let date = parse_metric(self.data.get("DATE")?.clone(), MetricParseType::Timestamp.into()).ok()??;
+		Some(date - self.last_success?.duration_since(UNIX_EPOCH).ok()?.as_secs_f64())
+	}
+}
+
+impl APCThrottledAccess {
+	/// Create access to a UPS through `endpoints`, tried in order on every fetch.
+	#[allow(clippy::too_many_arguments)]
+	pub fn new(
+		endpoints: Vec<Endpoint>,
+		wait_time: Duration,
+		on_battery_wait_time: Duration,
+		statistics: PollStatistics,
+		history: Option<Arc<std::sync::Mutex<History>>>,
+		history_store: Option<HostHistoryStore>,
+		notifier: Option<HostNotifier>,
+		runtime_baselines: Option<HostRuntimeBaselines>,
+		shared_cache: Option<SharedCache>,
+		snapshot_path: Option<PathBuf>,
+		status_file: Option<StatusFileOptions>,
+	) -> Self {
+		let abandoned_fetches = Arc::new(AtomicU64::new(0));
+		let truncated_responses = Arc::new(AtomicU64::new(0));
+		let dns_failures = Arc::new(AtomicU64::new(0));
+		// Saved data is served until the first fetch is due, instead of fetching on the first scrape.
+		let saved = snapshot_path.as_deref().and_then(persistence::load);
+		Self {
+			inner: Arc::new(Mutex::new(APCThrottledAccessInner {
+				endpoints,
+				endpoint: 0,
+				wait_time,
+				on_battery_wait_time,
+				last_call: if saved.is_some() { Instant::now() } else { Instant::now() - wait_time },
+				last_success: saved.as_ref().map(|(fetched_at, _)| *fetched_at),
+				fetch_duration: Duration::ZERO,
+				fetch_timings: None,
+				data: Ok(saved.as_ref().map(|(_, data)| data.clone()).unwrap_or_default()),
+				statistics,
+				history,
+				history_store,
+				notifier,
+				runtime_baselines,
+				runtime_degradation_ratio: None,
+				abandoned_fetches: abandoned_fetches.clone(),
+				truncated_responses: truncated_responses.clone(),
+				dns_failures: dns_failures.clone(),
+				shared_cache,
+				snapshot_path,
+				saved: saved.map(|(_, data)| data),
+				status_file,
+			})),
+			abandoned_fetches,
+			truncated_responses,
+			dns_failures,
+		}
+	}
+
+	/// Fetch data for a scrape, which also starts a new period for statistics aggregated between scrapes.
+	#[tracing::instrument(skip_all)]
+	pub async fn fetch(&self) -> Result<ApcupsdSnapshot, std::io::ErrorKind> {
+		let mut inner = self.inner.lock().await;
+		let stale = !inner.refresh_if_due().await;
+		let snapshot = inner.snapshot(stale)?;
+		inner.statistics.reset_aggregates();
+		Ok(snapshot)
+	}
+
+	/// Fetch data like [`Self::fetch`] but without starting a new period for statistics, for consumers other than scrapes.
+	pub async fn refresh(&self) -> Result<ApcupsdSnapshot, std::io::ErrorKind> {
+		let mut inner = self.inner.lock().await;
+		let stale = !inner.refresh_if_due().await;
+		inner.snapshot(stale)
+	}
+
+	/// The most recently fetched data without fetching, for debugging.
+	pub async fn peek(&self) -> Result<ApcupsdSnapshot, std::io::ErrorKind> {
+		self.inner.lock().await.snapshot(true)
+	}
+
+	/// Keep fetching in the background so scrapes are served from the most recent data, until the host is dropped (e.g. when a reload
+	/// removes it). The first fetch is delayed by `phase`, and every wait is extended by a random time of up to `jitter`.
+	pub fn spawn_poller(&self, phase: Duration, jitter: Duration) {
+		let weak_inner = Arc::downgrade(&self.inner);
+		tokio::spawn(async move {
+			tokio::time::sleep(phase).await;
+			while let Some(inner) = weak_inner.upgrade() {
+				let wait_time = {
+					let mut inner = inner.lock().await;
+					inner.refresh_if_due().await;
+					inner.current_wait_time()
+				};
+				drop(inner);
+				tokio::time::sleep(wait_time + jitter.mul_f64(random_fraction())).await;
+			}
+		});
+	}
+}
+
+impl APCThrottledAccessInner {
+	fn snapshot(&self, stale: bool) -> Result<ApcupsdSnapshot, std::io::ErrorKind> {
+		Ok(ApcupsdSnapshot {
+			data: self.data.clone()?,
+			stale,
+			fetched_at: self.last_call,
+			last_success: self.last_success,
+			fetch_duration: self.fetch_duration,
+			fetch_timings: self.fetch_timings,
+			endpoint: self.endpoints[self.endpoint].name.clone(),
+			statistics: self.statistics.clone(),
+			restored: self.snapshot_path.as_ref().map(|_| self.saved.is_some()),
+			probed_port: Some(&self.endpoints[self.endpoint]).filter(|endpoint| !endpoint.alternate_ports.is_empty()).map(|endpoint| endpoint.port),
+			runtime_degradation_ratio: self.runtime_degradation_ratio,
+		})
+	}
+
+	/// Fetch data from apcupsd (or the shared cache) if the wait time has been reached, returning whether it was fetched (and isn't the
+	/// saved data served in place of a failed fetch).
+	#[tracing::instrument(skip_all)]
+	async fn refresh_if_due(&mut self) -> bool {
+		let wait_time = self.current_wait_time();
+		if self.last_call.elapsed() < wait_time {
+			return false;
+		}
+		let fetch_started = Instant::now();
+		let shared = match &self.shared_cache {
+			Some(shared_cache) => shared_cache.get(wait_time).await,
+			None => None,
+		};
+		self.fetch_timings = None;
+		let fetched_at = match (shared, &self.status_file) {
+			(Some((fetched_at, data)), _) => {
+				self.data = Ok(data);
+				fetched_at
+			},
+			// Data from the status file is as old as when apcupsd wrote it.
+			(None, Some(status_file)) => {
+				let read = status_file::read(status_file).await;
+				let fetched_at = read.as_ref().map_or_else(|_| SystemTime::now(), |(modified, _)| *modified);
+				self.data = read.map(|(_, data)| data);
+				fetched_at
+			},
+			(None, None) => {
+				self.data = self.fetch_from_nis().await.map(|(data, fetch_timings)| {
+					self.fetch_timings = Some(fetch_timings);
+					data
+				});
+				if let (Ok(data), Some(shared_cache)) = (&self.data, &self.shared_cache) {
+					shared_cache.set(data, wait_time).await;
+				}
+				SystemTime::now()
+			},
+		};
+		// Data from the shared cache is as old as when another replica fetched it.
+		self.last_call = Instant::now().checked_sub(fetched_at.elapsed().unwrap_or_default()).unwrap_or_else(Instant::now);
+		self.fetch_duration = fetch_started.elapsed();
+		match (&self.data, &self.saved) {
+			// Keep serving the saved data if apcupsd is still unreachable after a restart, e.g. while the UPS's host is also rebooting.
+			(Err(_), Some(saved)) => {
+				self.data = Ok(saved.clone());
+				return false;
+			},
+			(Ok(_), Some(_)) => self.saved = None,
+			_ => {},
+		}
+		if let Ok(data) = &self.data {
+			self.statistics.observe(data);
+			if let Some(notifier) = &mut self.notifier {
+				notifier.observe(data);
+			}
+			if let Some(runtime_baselines) = &self.runtime_baselines {
+				self.runtime_degradation_ratio = runtime_baselines.observe(data);
+			}
+			if let Some(history) = &self.history {
+				history.lock().unwrap_or_else(PoisonError::into_inner).observe(data);
+			}
+			if let Some(history_store) = &self.history_store {
+				history_store.record(fetched_at, data).await;
+			}
+			if let Some(snapshot_path) = &self.snapshot_path {
+				persistence::save(snapshot_path, fetched_at, data).await;
+			}
+			self.last_success = Some(fetched_at);
+		}
+		true
+	}
+
+	/// Connect to the current addresses of endpoints whose names are due to be resolved again. Endpoints that fail to resolve keep their
+	/// last address, or leave resolving to the system if there is none.
+	async fn resolve_endpoints(&mut self) {
+		for endpoint in &mut self.endpoints {
+			let Some(resolved_name) = &mut endpoint.resolved_name else {
+				continue;
+			};
+			match resolved_name.refresh().await {
+				Some(Ok(address)) => endpoint.client = nis_client(address.to_string(), endpoint.port),
+				Some(Err(e)) => {
+					self.dns_failures.fetch_add(1, Ordering::Relaxed);
+					syslog::error(format!("Failed to resolve {}: {e}", resolved_name.name()));
+				},
+				None => {},
+			}
+		}
+	}
+
+	/// Fetch data from the first endpoint that answers, along with how long each phase of fetching from it took.
+	async fn fetch_from_nis(&mut self) -> Result<(HashMap<String, String>, FetchTimings), std::io::ErrorKind> {
+		self.resolve_endpoints().await;
+		let clients: Vec<_> = self.endpoints.iter().map(|endpoint| (endpoint.client.clone(), endpoint.alternate_ports.clone())).collect();
+		// Allow for one retry of a truncated response.
+		let deadline = FETCH_DEADLINE_PER_ENDPOINT * (clients.len() as u32 + 1);
+		let truncated_responses = self.truncated_responses.clone();
+		let fetch = spawn_blocking(move || {
+			let mut error = std::io::ErrorKind::NotFound;
+			for (index, (mut client, alternate_ports)) in clients.into_iter().enumerate() {
+				let mut result = client.fetch();
+				if result.as_ref().is_err_and(|e| e.kind() == std::io::ErrorKind::ConnectionRefused) && !alternate_ports.is_empty() {
+					let current_port = client.port;
+					if let Some(port) = client.probe_port(alternate_ports.into_iter().filter(|port| *port != current_port)) {
+						client.port = port;
+						result = client.fetch();
+					}
+				}
+				match result {
+					Ok((data, timings)) if is_truncated(&data) => {
+						// apcupsd sometimes cuts responses short under load, so try again and settle for what was received if that's also cut short.
+						let (retried, retry_timings) = client.fetch().unwrap_or_default();
+						let data = if retried.len() > data.len() { retried } else { data };
+						if is_truncated(&data) {
+							truncated_responses.fetch_add(1, Ordering::Relaxed);
+						}
+						return Ok((index, client.port, data, timings + retry_timings));
+					},
+					Ok((data, timings)) => return Ok((index, client.port, data, timings)),
+					Err(e) => error = e.kind(),
+				}
+			}
+			Err(error)
+		});
+		// The blocking fetch can't be cancelled, so past the deadline it's left to finish in the background with its result discarded.
+		let result = match tokio::time::timeout(deadline, fetch).await {
+			// A fetch that panicked failed, so saved data is served and nothing is recorded like after any other failure.
+			Ok(result) => result.unwrap_or(Err(std::io::ErrorKind::Other)),
+			Err(_) => {
+				self.abandoned_fetches.fetch_add(1, Ordering::Relaxed);
+				Err(std::io::ErrorKind::TimedOut)
+			},
+		};
+		// Endpoints before the one that answered failed, so their names are resolved again in case they moved.
+		let failed = result.as_ref().map_or(self.endpoints.len(), |(endpoint, ..)| *endpoint);
+		for endpoint in &mut self.endpoints[..failed] {
+			if let Some(resolved_name) = &mut endpoint.resolved_name {
+				resolved_name.invalidate();
+			}
+		}
+		result.map(|(endpoint, port, data, timings)| {
+			self.endpoint = endpoint;
+			let endpoint = &mut self.endpoints[endpoint];
+			if port != endpoint.port {
+				syslog::warning(format!(
+					"NIS server at {} refused connections on port {}, using port {port} instead",
+					endpoint.client.host, endpoint.port
+				));
+				endpoint.port = port;
+				endpoint.client.port = port;
+			}
+			(data, timings)
+		})
+	}
+
+	/// The wait time, shortened while the UPS is on battery or apcupsd is fast polling it.
+	fn current_wait_time(&self) -> Duration {
+		let on_battery =
+			self.data.as_ref().is_ok_and(
+				|data| match data.get("STATFLAG").and_then(|hex| u32::from_str_radix(hex.get(2..)?, 16).ok()) {
+					Some(status) => status & (apcupsd_bitmasks::status::UPS_ONBATT | apcupsd_bitmasks::status::UPS_FASTPOLL) != 0,
+					None => data.get("STATUS").is_some_and(|status| status.split_whitespace().any(|flag| flag == "ONBATT")),
+				},
+			);
+		if on_battery {
+			self.wait_time.min(self.on_battery_wait_time)
+		} else {
+			self.wait_time
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{
+		collections::HashMap,
+		fs::{self, File},
+		io::{BufRead, BufReader},
+		path::PathBuf,
+	};
+
+	use insta::with_settings;
+	use proptest::prelude::*;
+	use rstest::rstest;
+
+	use crate::{
+		check_configured_metrics, check_metric_families, mock, parse_metric, render_metrics, sample_status, Host, HostSpecificOptions,
+		MetricParseConfig, MetricParseType, PercentScale, RenderMetricsError, RenderOptions,
+	};
+
+	#[rstest]
+	fn test_examples(#[files("tests/*_examples/*.status")] path: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+		let test_data = BufReader::new(File::open(path.clone())?)
+			.lines()
+			.map(|lr| lr.map(|l| l.split_once(":").ok_or("invalid test file").map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))))
+			.collect::<Result<Result<HashMap<_, _>, _>, _>>()??;
+		with_settings!(
+			{
+				prepend_module_to_snapshot => false,
+				snapshot_path => "../tests/snapshots",
+				snapshot_suffix => (|| Some([path.parent()?.file_name()?.to_str()?, path.file_name()?.to_str()?].join("/")))().ok_or("bad filename")?
+			},
+			{ Ok::<_, RenderMetricsError>(insta::assert_snapshot!(render_metrics(test_data, "ups0".to_string(), &Default::default())?)) }
+		)?;
+		Ok(())
+	}
+
+	#[test]
+	fn test_hostile_label_values() -> Result<(), RenderMetricsError> {
+		let apcupsd_data = HashMap::from([
+			("UPSNAME".to_string(), r#"rack "A"\ups"#.to_string()),
+			("MODEL".to_string(), "Smart-UPS\n# TYPE fake gauge".to_string()),
+			("VERSION".to_string(), "3.14.14\0".to_string()),
+			("LINEV".to_string(), "122.0 Volts".to_string()),
+		]);
+		let rendered = render_metrics(apcupsd_data, "ups0".to_string(), &Default::default())?;
+		assert!(
+			rendered.contains(r#"apcupsd_line_volts{exported_ups="ups0",ups_name="rack \"A\"\\ups",model="Smart-UPS\n# TYPE fake gauge"} 122"#),
+			"{rendered}"
+		);
+		assert!(rendered.contains("version=\"3.14.14\u{fffd}\""), "{rendered}");
+		assert!(!rendered.lines().any(|line| line.starts_with("# TYPE fake")), "{rendered}");
+		Ok(())
+	}
+
+	#[test]
+	fn test_statflag_bits() -> Result<(), RenderMetricsError> {
+		use crate::apcupsd_bitmasks::status::*;
+		let defined = [
+			UPS_CALIBRATION,
+			UPS_TRIM,
+			UPS_BOOST,
+			UPS_ONLINE,
+			UPS_ONBATT,
+			UPS_OVERLOAD,
+			UPS_BATTLOW,
+			UPS_REPLACEBATT,
+			UPS_COMMLOST,
+			UPS_SHUTDOWN,
+			UPS_SLAVE,
+			UPS_SLAVEDOWN,
+			UPS_ONBATT_MSG,
+			UPS_FASTPOLL,
+			UPS_SHUT_LOAD,
+			UPS_SHUT_BTIME,
+			UPS_SHUT_LTIME,
+			UPS_SHUT_EMERG,
+			UPS_SHUT_REMOTE,
+			UPS_PLUGGED,
+			UPS_BATTPRESENT,
+		];
+		// Bits missing from defines.h, like the battery charging bit some builds set, are only in the raw metric.
+		for (statflag, decoded) in [(defined.iter().fold(0, |a, b| a | b), "1"), (0x02000000, "0")] {
+			let apcupsd_data = HashMap::from([("STATFLAG".to_string(), format!("0x{statflag:08X}"))]);
+			let rendered = render_metrics(apcupsd_data, "ups0".to_string(), &Default::default())?;
+			assert!(
+				rendered.contains(&format!("apcupsd_status_flag_raw{{exported_ups=\"ups0\"}} {statflag}\n")),
+				"{rendered}"
+			);
+			let values: Vec<_> = rendered
+				.lines()
+				.filter(|line| {
+					line.starts_with("apcupsd_status_") && !line.starts_with("apcupsd_status_flag_raw") && !line.starts_with("apcupsd_status_numeric")
+				})
+				.filter_map(|line| line.rsplit_once(' ').map(|(_, value)| value))
+				.collect();
+			assert_eq!(values.len(), defined.len(), "{rendered}");
+			assert!(values.iter().all(|value| *value == decoded), "{rendered}");
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn test_check_metric_families() -> Result<(), Box<dyn std::error::Error>> {
+		let rendered = render_metrics(sample_status(), "sample".to_string(), &Default::default())?;
+		check_metric_families(&rendered)?;
+		let conflicting = "# TYPE apcupsd_load gauge\napcupsd_load 1\n# TYPE apcupsd_load counter\napcupsd_load 2\n";
+		assert!(check_metric_families(conflicting).is_err());
+
+		check_configured_metrics(&Default::default())?;
+		// Conflicts are found even if the sample doesn't report the keys a computed metric needs.
+		let computed = serde_yaml::from_str("[{name: line_volts, expr: NOT_REPORTED * 2}]")?;
+		assert!(check_configured_metrics(&RenderOptions {
+			computed,
+			..Default::default()
+		})
+		.is_err());
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn test_mock_server() -> Result<(), Box<dyn std::error::Error>> {
+		let address = mock::spawn(fs::read_to_string("tests/user_examples/Back-UPS-XS-1500M_1.status")?).await?;
+		let host = Host::new(
+			HostSpecificOptions {
+				address: address.ip().to_string(),
+				port: address.port(),
+				slug: Some("ups0".to_string()),
+				..Default::default()
+			},
+			0,
+			&Default::default(),
+		)
+		.await?;
+		let (rendered, reachable) = host.render(&RenderOptions::default()).await;
+		assert!(reachable);
+		assert!(rendered.contains("apcupsd_up{exported_ups=\"ups0\"} 1\n"), "{rendered}");
+		assert!(
+			rendered.contains(
+				"apcupsd_line_volts{exported_ups=\"ups0\",ups_name=\"Test-Host\",model=\"Back-UPS XS 1500M\",serial_number=\"0B2412L30199\"} 122\n"
+			),
+			"{rendered}"
+		);
+
+		// Options from a reload aren't served metrics rendered with the previous ones from the cache.
+		let charge = |rendered: &str| rendered.lines().find(|line| line.starts_with("apcupsd_battery_charge_percent{")).map(str::to_string);
+		let reloaded = RenderOptions {
+			generation: 1,
+			percent_scale: PercentScale::Percent,
+			..Default::default()
+		};
+		let (rendered_after_reload, _) = host.render(&reloaded).await;
+		assert_ne!(charge(&rendered_after_reload), charge(&rendered), "{rendered_after_reload}");
+		Ok(())
+	}
+
+	#[rstest]
+	#[case::timestamp(MetricParseType::Timestamp, "2024-05-01 12:00:00 -0400", 1714579200.)]
+	#[case::timestamp_historic(MetricParseType::Timestamp, "Wed May 01 16:00:00 +0000 2024", 1714579200.)]
+	#[case::date(MetricParseType::Date, "2024-05-01", 1714521600.)]
+	#[case::date_us(MetricParseType::Date, "05/01/24", 1714521600.)]
+	#[case::duration_seconds(MetricParseType::Duration, "0 Seconds", 0.)]
+	#[case::duration_minutes(MetricParseType::Duration, "1.5 Minutes", 90.)]
+	#[case::percentage_full(MetricParseType::Percentage, "100.0 Percent", 1.)]
+	#[case::percentage_empty(MetricParseType::Percentage, "0.0 Percent", 0.)]
+	#[case::voltage(MetricParseType::Voltage, "122.0 Volts", 122.)]
+	#[case::temperature_negative(MetricParseType::Temperature, "-5.5 C", -5.5)]
+	#[case::temperature_fahrenheit(MetricParseType::Temperature, "212.0 F", 100.)]
+	#[case::temperature_internal(MetricParseType::Temperature, "29.2 internal", 29.2)]
+	#[case::frequency(MetricParseType::Frequency, "60.0 Hz", 60.)]
+	#[case::current(MetricParseType::Current, "0.00 Amps", 0.)]
+	#[case::count(MetricParseType::Count, "3", 3.)]
+	#[case::power(MetricParseType::Power, "900 Watts", 900.)]
+	#[case::apparent_power(MetricParseType::ApparentPower, "1500 VA", 1500.)]
+	fn test_parse_metric(#[case] parse_type: MetricParseType, #[case] value: &str, #[case] expected: f64) {
+		assert_eq!(parse_metric(value.to_string(), parse_type.into()).unwrap(), Some(expected));
+	}
+
+	#[rstest]
+	#[case::timestamp_without_zone(MetricParseType::Timestamp, "2024-05-01 12:00:00")]
+	#[case::date_invalid_day(MetricParseType::Date, "2024-02-30")]
+	#[case::duration_hours(MetricParseType::Duration, "2 Hours")]
+	#[case::duration_without_unit(MetricParseType::Duration, "30")]
+	#[case::percentage_without_unit(MetricParseType::Percentage, "50.0")]
+	#[case::percentage_localized(MetricParseType::Percentage, "50,0 Percent")]
+	#[case::voltage_localized(MetricParseType::Voltage, "122,0 Volts")]
+	#[case::voltage_without_unit(MetricParseType::Voltage, "122.0")]
+	#[case::voltage_wrong_unit(MetricParseType::Voltage, "122.0 V")]
+	#[case::temperature_sentinel(MetricParseType::Temperature, "NA C")]
+	#[case::temperature_kelvin(MetricParseType::Temperature, "300.0 K")]
+	#[case::frequency_empty(MetricParseType::Frequency, "")]
+	#[case::current_without_unit(MetricParseType::Current, "1.2")]
+	#[case::count_not_a_number(MetricParseType::Count, "three")]
+	#[case::power_wrong_unit(MetricParseType::Power, "900 VA")]
+	#[case::apparent_power_wrong_unit(MetricParseType::ApparentPower, "1500 Watts")]
+	fn test_parse_metric_invalid(#[case] parse_type: MetricParseType, #[case] value: &str) {
+		assert!(parse_metric(value.to_string(), parse_type.into()).is_err());
+	}
+
+	#[rstest]
+	#[case::voltage(MetricParseType::Voltage, "27.1", Some(27.1))]
+	#[case::temperature(MetricParseType::Temperature, "29.2", Some(29.2))]
+	#[case::frequency(MetricParseType::Frequency, "50.0", Some(50.))]
+	#[case::unit_still_accepted(MetricParseType::Voltage, "27.1 Volts", Some(27.1))]
+	#[case::current_unit_required(MetricParseType::Current, "1.2", None)]
+	fn test_parse_metric_unit_optional(#[case] parse_type: MetricParseType, #[case] value: &str, #[case] expected: Option<f64>) {
+		let parse_config = MetricParseConfig {
+			unit_optional: true,
+			..parse_type.into()
+		};
+		assert_eq!(parse_metric(value.to_string(), parse_config).ok().flatten(), expected);
+	}
+
+	#[rstest]
+	#[case::fraction(PercentScale::Fraction, 0.5)]
+	#[case::ratio(PercentScale::Ratio, 0.5)]
+	#[case::percent(PercentScale::Percent, 50.)]
+	fn test_parse_metric_percent_scale(#[case] percent_scale: PercentScale, #[case] expected: f64) {
+		let parse_config = MetricParseConfig {
+			percent_scale,
+			..MetricParseType::Percentage.into()
+		};
+		assert_eq!(parse_metric("50.0 Percent".to_string(), parse_config).unwrap(), Some(expected));
+	}
+
+	#[rstest]
+	#[case::fraction(PercentScale::Fraction, "apcupsd_ups_load_percent", "Percentage of UPS load capacity used.")]
+	#[case::ratio(PercentScale::Ratio, "apcupsd_ups_load_ratio", "Percentage of UPS load capacity used, from 0 to 1.")]
+	#[case::percent(PercentScale::Percent, "apcupsd_ups_load_percent", "Percentage of UPS load capacity used, from 0 to 100.")]
+	fn test_percent_scale_metadata(#[case] percent_scale: PercentScale, #[case] name: &str, #[case] help: &str) {
+		assert_eq!(percent_scale.metric_name("apcupsd_ups_load_percent"), name);
+		assert_eq!(percent_scale.help("Percentage of UPS load capacity used."), help);
+	}
+
+	#[test]
+	fn test_parse_metric_special_values() {
+		let parse_config = || MetricParseConfig {
+			special_values: [("N/A", None), ("Unknown", Some(0.))].into(),
+			..MetricParseType::Timestamp.into()
+		};
+		assert_eq!(parse_metric("N/A".to_string(), parse_config()).unwrap(), None);
+		assert_eq!(parse_metric("Unknown".to_string(), parse_config()).unwrap(), Some(0.));
+		assert!(parse_metric("n/a".to_string(), parse_config()).is_err());
+	}
+
+	proptest! {
+		#[test]
+		fn test_duration_round_trip(seconds in 0f64..1e9) {
+			prop_assert_eq!(parse_metric(format!("{seconds} Seconds"), MetricParseType::Duration.into()).unwrap(), Some(seconds));
+		}
+
+		#[test]
+		fn test_duration_minutes_round_trip(minutes in 0u32..1_000_000) {
+			let parsed = parse_metric(format!("{minutes}.0 Minutes"), MetricParseType::Duration.into()).unwrap();
+			prop_assert_eq!(parsed, Some(f64::from(minutes) * 60.));
+		}
+
+		#[test]
+		fn test_voltage_round_trip(volts in -1e4f64..1e4) {
+			prop_assert_eq!(parse_metric(format!("{volts} Volts"), MetricParseType::Voltage.into()).unwrap(), Some(volts));
+			let rounded = format!("{volts:.1}");
+			prop_assert_eq!(parse_metric(format!("{rounded} Volts"), MetricParseType::Voltage.into()).unwrap(), rounded.parse().ok());
+		}
+	}
+}
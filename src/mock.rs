@@ -0,0 +1,60 @@
+//! A minimal apcupsd NIS server serving canned or generated status output, for tests and for trying the exporter without a UPS.
+
+use std::{io, net::SocketAddr, sync::Arc};
+
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt},
+	net::{TcpListener, TcpStream},
+};
+
+/// Serve `status` as the response to NIS status requests on an ephemeral localhost port, returning the address being listened on.
+pub async fn spawn(status: String) -> io::Result<SocketAddr> {
+	spawn_generated(move || status.clone()).await
+}
+
+/// Like [`spawn`], but with status output generated by `status` for each request.
+pub async fn spawn_generated(status: impl Fn() -> String + Send + Sync + 'static) -> io::Result<SocketAddr> {
+	let status = Arc::new(status);
+	let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+	let address = listener.local_addr()?;
+	tokio::spawn(async move {
+		loop {
+			let stream = match listener.accept().await {
+				Ok((stream, _)) => stream,
+				Err(e) => {
+					eprintln!("Mock NIS server failed to accept connection: {e}");
+					continue;
+				},
+			};
+			let status = status.clone();
+			tokio::spawn(async move {
+				if let Err(e) = handle_connection(stream, &*status).await {
+					eprintln!("Mock NIS server connection failed: {e}");
+				}
+			});
+		}
+	});
+	Ok(address)
+}
+
+/// NIS messages are a big endian u16 length followed by that many bytes. Responses are one message per line, terminated by an empty message.
+async fn handle_connection(mut stream: TcpStream, status: &(impl Fn() -> String + Sync)) -> io::Result<()> {
+	loop {
+		let length = match stream.read_u16().await {
+			Ok(length) => length,
+			Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+			Err(e) => return Err(e),
+		};
+		let mut command = vec![0; length.into()];
+		stream.read_exact(&mut command).await?;
+		if command == b"status" {
+			for line in status().lines() {
+				let message = format!("{line}\n");
+				let length = u16::try_from(message.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "status line too long"))?;
+				stream.write_u16(length).await?;
+				stream.write_all(message.as_bytes()).await?;
+			}
+		}
+		stream.write_u16(0).await?;
+	}
+}
@@ -0,0 +1,57 @@
+//! Quirks of families of UPS models, such as shutdown related keys (like `DLOWBATT`) that mean something different or use different units
+//! than most, or values reported without their unit. Known quirks are built in, and more can be configured.
+
+use std::collections::{HashMap, HashSet};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{MetricOverride, MetricParseType};
+
+#[derive(Clone, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(default)]
+pub struct ModelFamily {
+	/// Text the `MODEL` reported by apcupsd must contain for the family to apply, e.g. `Back-UPS ES`.
+	pub model: Option<String>,
+	/// Text the `DRIVER` reported by apcupsd must contain for the family to apply, e.g. `MODBUS`.
+	pub driver: Option<String>,
+	/// Text the `FIRMWARE` reported by apcupsd must contain for the family to apply, e.g. `UPS 09.3`.
+	pub firmware: Option<String>,
+	/// What the value of each key is reported as, if it's different than usual. The metric should also be renamed with `metric_overrides`
+	/// when its unit changes, e.g. `apcupsd_battery_low_signal_charge_percent` for a `DLOWBATT` reported as a percentage.
+	pub parse_as: HashMap<String, MetricParseType>,
+	/// Sentinel values of each key, like the global `special_values`, which they take precedence over.
+	pub special_values: HashMap<String, HashMap<String, Option<f64>>>,
+	/// Keys that may be reported as a plain number without their unit, in addition to the global `unitless_keys`.
+	pub unitless_keys: HashSet<String>,
+	/// Percentage keys reported from 0 to 1 instead of from 0 to 100.
+	pub fraction_keys: HashSet<String>,
+	/// Name and type of each key, like the global `metric_overrides`, which they take precedence over.
+	pub metric_overrides: HashMap<String, MetricOverride>,
+}
+
+impl ModelFamily {
+	/// Whether data with this `MODEL`, `DRIVER`, and `FIRMWARE` belongs to the family. A family without any of them matches nothing.
+	pub fn matches(&self, apcupsd_data: &HashMap<String, String>) -> bool {
+		let contains = |key: &str, text: &Option<String>| match text {
+			Some(text) => apcupsd_data.get(key).is_some_and(|value| value.contains(text.as_str())),
+			None => true,
+		};
+		(self.model.is_some() || self.driver.is_some() || self.firmware.is_some())
+			&& contains("MODEL", &self.model)
+			&& contains("DRIVER", &self.driver)
+			&& contains("FIRMWARE", &self.firmware)
+	}
+}
+
+/// Quirks of models known to need them, which apply after configured families.
+pub fn builtin() -> Vec<ModelFamily> {
+	vec![
+		// The Modbus driver (used for Microlink models over USB or serial) sometimes leaves units off.
+		ModelFamily {
+			driver: Some("MODBUS".to_string()),
+			unitless_keys: ["LINEV", "OUTPUTV", "BATTV", "NOMBATTV", "ITEMP", "LINEFREQ"].into_iter().map(String::from).collect(),
+			..Default::default()
+		},
+	]
+}
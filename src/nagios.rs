@@ -0,0 +1,134 @@
+//! Checking a host like a Nagios/Icinga plugin, with a one line summary, performance data, and the standard exit status, so the same
+//! binary can serve both monitoring stacks.
+
+use std::collections::HashMap;
+
+use clap::Args;
+
+use crate::{parse_metric, MetricParseConfig, MetricParseType, PercentScale};
+
+#[derive(Args)]
+pub struct CheckArgs {
+	/// Slug of the host to check; defaults to the first configured host.
+	#[arg(long)]
+	pub target: Option<String>,
+	/// Warn when the battery charge is below this percentage.
+	#[arg(long, default_value_t = 50.)]
+	pub charge_warning: f64,
+	/// Be critical when the battery charge is below this percentage.
+	#[arg(long, default_value_t = 25.)]
+	pub charge_critical: f64,
+	/// Warn when the estimated runtime is below this many minutes.
+	#[arg(long, default_value_t = 10.)]
+	pub runtime_warning: f64,
+	/// Be critical when the estimated runtime is below this many minutes.
+	#[arg(long, default_value_t = 5.)]
+	pub runtime_critical: f64,
+	/// Flags in STATUS that are a warning, comma separated.
+	#[arg(long, value_delimiter = ',', default_value = "ONBATT,OVERLOAD,REPLACEBATT")]
+	pub warning_status: Vec<String>,
+	/// Flags in STATUS that are critical, comma separated. `SHUTTING` stands for `SHUTTING DOWN`.
+	#[arg(long, value_delimiter = ',', default_value = "LOWBATT,COMMLOST,NOBATT,SHUTTING")]
+	pub critical_status: Vec<String>,
+}
+
+/// Plugin states, whose values are the exit status.
+#[derive(Clone, Copy, Eq, Ord, PartialEq, PartialOrd)]
+pub enum CheckState {
+	Ok = 0,
+	Warning = 1,
+	Critical = 2,
+	Unknown = 3,
+}
+
+impl CheckState {
+	fn name(self) -> &'static str {
+		match self {
+			Self::Ok => "OK",
+			Self::Warning => "WARNING",
+			Self::Critical => "CRITICAL",
+			Self::Unknown => "UNKNOWN",
+		}
+	}
+}
+
+/// The state of a host that couldn't be fetched from, with the line to print.
+pub fn unknown(error: impl std::fmt::Display) -> (CheckState, String) {
+	(CheckState::Unknown, format!("APCUPSD UNKNOWN - {error}"))
+}
+
+/// Evaluate the thresholds against data from apcupsd, returning the state and the line to print. Thresholds on keys that weren't
+/// reported are skipped.
+pub fn evaluate(args: &CheckArgs, apcupsd_data: &HashMap<String, String>) -> (CheckState, String) {
+	let value = |key: &str, parse_type: MetricParseType| {
+		let parse_config = MetricParseConfig {
+			percent_scale: PercentScale::Percent,
+			..parse_type.into()
+		};
+		parse_metric(apcupsd_data.get(key)?.clone(), parse_config).ok().flatten()
+	};
+	let charge = value("BCHARGE", MetricParseType::Percentage);
+	let runtime = value("TIMELEFT", MetricParseType::Duration);
+	let load = value("LOADPCT", MetricParseType::Percentage);
+	let status = apcupsd_data.get("STATUS").map_or("", |status| status.trim());
+
+	let mut state = CheckState::Ok;
+	let mut problems = Vec::new();
+	for flag in status.split_whitespace() {
+		let flag_state = if args.critical_status.iter().any(|critical| critical == flag) {
+			CheckState::Critical
+		} else if args.warning_status.iter().any(|warning| warning == flag) {
+			CheckState::Warning
+		} else {
+			continue;
+		};
+		state = state.max(flag_state);
+		problems.push(flag.to_string());
+	}
+	if let Some(charge) = charge {
+		let threshold_state = threshold_state(charge, args.charge_warning, args.charge_critical);
+		if threshold_state != CheckState::Ok {
+			state = state.max(threshold_state);
+			problems.push(format!("charge {charge}% low"));
+		}
+	}
+	if let Some(runtime) = runtime {
+		let threshold_state = threshold_state(runtime / 60., args.runtime_warning, args.runtime_critical);
+		if threshold_state != CheckState::Ok {
+			state = state.max(threshold_state);
+			problems.push(format!("runtime {:.1} min low", runtime / 60.));
+		}
+	}
+
+	let mut summary = vec![if status.is_empty() { "no STATUS" } else { status }.to_string()];
+	summary.extend(charge.map(|charge| format!("charge {charge}%")));
+	summary.extend(runtime.map(|runtime| format!("runtime {:.1} min", runtime / 60.)));
+	let summary = if problems.is_empty() { summary.join(", ") } else { problems.join(", ") };
+
+	let mut perfdata = Vec::new();
+	if let Some(charge) = charge {
+		perfdata.push(format!("charge={charge}%;{}:;{}:;0;100", args.charge_warning, args.charge_critical));
+	}
+	if let Some(runtime) = runtime {
+		perfdata.push(format!(
+			"runtime={runtime}s;{}:;{}:;0",
+			args.runtime_warning * 60.,
+			args.runtime_critical * 60.
+		));
+	}
+	if let Some(load) = load {
+		perfdata.push(format!("load={load}%;;;0;100"));
+	}
+	(state, format!("APCUPSD {} - {summary} | {}", state.name(), perfdata.join(" ")))
+}
+
+/// The state of a value that's a problem below the thresholds.
+fn threshold_state(value: f64, warning: f64, critical: f64) -> CheckState {
+	if value < critical {
+		CheckState::Critical
+	} else if value < warning {
+		CheckState::Warning
+	} else {
+		CheckState::Ok
+	}
+}
@@ -0,0 +1,180 @@
+//! Fetching status from apcupsd's NIS server, timing connecting, reading, and parsing separately so slow scrapes can be traced to the
+//! network or to apcupsd.
+
+use std::{
+	collections::HashMap,
+	io::{self, Read, Write},
+	net::{TcpStream, ToSocketAddrs},
+	time::{Duration, Instant},
+};
+
+/// A NIS server to fetch status from.
+#[derive(Clone, Debug)]
+pub struct NisClient {
+	pub host: String,
+	pub port: u16,
+	/// Timeout for connecting to each address of `host` and for each read and write.
+	pub timeout: Duration,
+}
+
+/// Ports NIS servers commonly listen on, tried when the configured port refuses connections if `probe_alternate_ports` is set.
+pub const COMMON_PORTS: [u16; 2] = [3551, 7000];
+
+/// How long each phase of a fetch took.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FetchTimings {
+	pub connect: Duration,
+	pub read: Duration,
+	pub parse: Duration,
+}
+
+impl FetchTimings {
+	/// The phases with their names, as used for the `phase` label.
+	pub fn phases(&self) -> [(&'static str, Duration); 3] {
+		[("connect", self.connect), ("read", self.read), ("parse", self.parse)]
+	}
+}
+
+impl std::ops::Add for FetchTimings {
+	type Output = Self;
+
+	fn add(self, other: Self) -> Self {
+		Self {
+			connect: self.connect + other.connect,
+			read: self.read + other.read,
+			parse: self.parse + other.parse,
+		}
+	}
+}
+
+impl NisClient {
+	/// Fetch the key-value pairs of `status`. A response that ends early (without a zero-length message) is returned as far as it was
+	/// received, so it can be told apart from a failed fetch by its missing footer.
+	pub fn fetch(&self) -> io::Result<(HashMap<String, String>, FetchTimings)> {
+		let started = Instant::now();
+		let mut stream = self.connect()?;
+		let connect = started.elapsed();
+
+		let started = Instant::now();
+		stream.set_read_timeout(Some(self.timeout))?;
+		stream.set_write_timeout(Some(self.timeout))?;
+		stream.write_all(&6_u16.to_be_bytes())?;
+		stream.write_all(b"status")?;
+		let mut messages = Vec::new();
+		loop {
+			match read_message(&mut stream) {
+				Ok(Some(message)) => messages.push(message),
+				Ok(None) => break,
+				Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !messages.is_empty() => break,
+				Err(e) => return Err(e),
+			}
+		}
+		let read = started.elapsed();
+
+		let started = Instant::now();
+		let data = messages
+			.iter()
+			.filter_map(|message| {
+				let message = String::from_utf8_lossy(message);
+				let (key, value) = message.split_once(':')?;
+				Some((key.trim().to_string(), value.trim().to_string()))
+			})
+			.collect();
+		Ok((
+			data,
+			FetchTimings {
+				connect,
+				read,
+				parse: started.elapsed(),
+			},
+		))
+	}
+
+	/// The first of `ports` the NIS server accepts connections on.
+	pub fn probe_port(&self, ports: impl IntoIterator<Item = u16>) -> Option<u16> {
+		ports.into_iter().find(|&port| Self { port, ..self.clone() }.connect().is_ok())
+	}
+
+	/// Connect to the first address of `host` that accepts the connection.
+	fn connect(&self) -> io::Result<TcpStream> {
+		let mut error = io::Error::from(io::ErrorKind::NotFound);
+		for address in (self.host.as_str(), self.port).to_socket_addrs()? {
+			match TcpStream::connect_timeout(&address, self.timeout) {
+				Ok(stream) => return Ok(stream),
+				Err(e) => error = e,
+			}
+		}
+		Err(error)
+	}
+}
+
+/// Read a message prefixed with its length, or `None` for the zero-length message that ends a response.
+fn read_message(stream: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+	let mut length = [0; 2];
+	stream.read_exact(&mut length)?;
+	let length = u16::from_be_bytes(length);
+	if length == 0 {
+		return Ok(None);
+	}
+	let mut message = vec![0; length.into()];
+	stream.read_exact(&mut message)?;
+	Ok(Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{net::TcpListener, thread};
+
+	use super::*;
+
+	/// Serve one connection with `messages`, each prefixed with its length, then close it.
+	fn serve(messages: &'static [&'static str]) -> NisClient {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let mut request = [0; 8];
+			stream.read_exact(&mut request).unwrap();
+			for message in messages {
+				stream.write_all(&(message.len() as u16).to_be_bytes()).unwrap();
+				stream.write_all(message.as_bytes()).unwrap();
+			}
+		});
+		NisClient {
+			host: "127.0.0.1".to_string(),
+			port,
+			timeout: Duration::from_secs(5),
+		}
+	}
+
+	#[test]
+	fn test_fetch() {
+		let client = serve(&["STATUS   : ONLINE \n", "no key\n", "END APC  : 2024-01-01 00:00:00 +0000  \n", ""]);
+		let (data, _) = client.fetch().unwrap();
+		assert_eq!(data.len(), 2);
+		assert_eq!(data["STATUS"], "ONLINE");
+		assert_eq!(data["END APC"], "2024-01-01 00:00:00 +0000");
+	}
+
+	#[test]
+	fn test_probe_port() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let client = NisClient {
+			host: "127.0.0.1".to_string(),
+			port,
+			timeout: Duration::from_secs(5),
+		};
+		let refused = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+		assert_eq!(client.probe_port([refused, port]), Some(port));
+		assert_eq!(client.probe_port([refused]), None);
+	}
+
+	#[test]
+	fn test_fetch_returns_responses_cut_short() {
+		let client = serve(&["STATUS   : ONLINE \n"]);
+		let (data, _) = client.fetch().unwrap();
+		assert_eq!(data, HashMap::from([("STATUS".to_string(), "ONLINE".to_string())]));
+		assert!(serve(&[]).fetch().is_err());
+	}
+}
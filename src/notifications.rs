@@ -0,0 +1,483 @@
+//! Notifying about power events detected between polls, like a UPS switching to battery, by email or push notifications, for small
+//! deployments without an alerting stack where the exporter is the only daemon watching the UPS.
+
+#[cfg(feature = "email")]
+mod email;
+
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex, PoisonError},
+	time::{Duration, Instant},
+};
+
+use http_body_util::Full;
+use hyper::{
+	body::Bytes,
+	header::{AUTHORIZATION, CONTENT_TYPE},
+	Request,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[cfg(feature = "email")]
+use email::Mailer;
+
+use crate::{
+	http_client::{self, HttpError},
+	labels,
+	syslog::{self, Priority},
+};
+
+/// A request sending a push notification, or why it couldn't be built.
+type PushRequest = Result<Request<Full<Bytes>>, HttpError>;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerEvent {
+	/// `ONBATT` appeared in `STATUS`.
+	OnBattery,
+	/// `LOWBATT` appeared in `STATUS`.
+	LowBattery,
+	/// `COMMLOST` appeared in `STATUS`, i.e. apcupsd lost communication with the UPS.
+	CommLost,
+	/// `ONBATT` disappeared from `STATUS`.
+	Restored,
+}
+
+/// Flags in `STATUS` whose appearance is an event.
+const EVENT_FLAGS: [(&str, PowerEvent); 3] = [
+	("ONBATT", PowerEvent::OnBattery),
+	("LOWBATT", PowerEvent::LowBattery),
+	("COMMLOST", PowerEvent::CommLost),
+];
+
+impl PowerEvent {
+	pub const ALL: [Self; 4] = [Self::OnBattery, Self::LowBattery, Self::CommLost, Self::Restored];
+
+	pub fn description(self) -> &'static str {
+		match self {
+			Self::OnBattery => "on battery",
+			Self::LowBattery => "battery low",
+			Self::CommLost => "communication with the UPS lost",
+			Self::Restored => "power restored",
+		}
+	}
+
+	/// Whether the event needs attention, which push notifications are sent with a higher priority for.
+	fn is_problem(self) -> bool {
+		self != Self::Restored
+	}
+}
+
+#[derive(Clone, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(default)]
+pub struct NotificationOptions {
+	/// Minimum seconds between notifications of the same event on the same host, so a flapping line doesn't flood inboxes.
+	pub min_interval_seconds: f64,
+	pub email: Option<EmailOptions>,
+	/// Template of the title of push notifications, like [`EmailOptions::subject`].
+	pub title: String,
+	/// Template of the text of push notifications, like [`EmailOptions::subject`].
+	pub message: String,
+	pub ntfy: Option<NtfyOptions>,
+	pub pushover: Option<PushoverOptions>,
+	pub telegram: Option<TelegramOptions>,
+}
+
+impl Default for NotificationOptions {
+	fn default() -> Self {
+		Self {
+			min_interval_seconds: 300.,
+			email: None,
+			title: "{{slug}}: {{event}}".to_string(),
+			message: "Status {{STATUS}}, battery charge {{BCHARGE}}, time left {{TIMELEFT}}".to_string(),
+			ntfy: None,
+			pushover: None,
+			telegram: None,
+		}
+	}
+}
+
+#[derive(Clone, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(default)]
+pub struct NtfyOptions {
+	/// URL of the topic, e.g. `https://ntfy.sh/my-ups-alerts`.
+	pub url: String,
+	/// Access token, if the topic requires one.
+	#[serde(serialize_with = "crate::serialize_redacted")]
+	pub token: String,
+	/// Events notifications are sent for.
+	pub events: Vec<PowerEvent>,
+}
+
+impl Default for NtfyOptions {
+	fn default() -> Self {
+		Self {
+			url: String::new(),
+			token: String::new(),
+			events: PowerEvent::ALL.to_vec(),
+		}
+	}
+}
+
+#[derive(Clone, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(default)]
+pub struct PushoverOptions {
+	/// API token of the application.
+	#[serde(serialize_with = "crate::serialize_redacted")]
+	pub token: String,
+	/// User or group key to notify.
+	#[serde(serialize_with = "crate::serialize_redacted")]
+	pub user: String,
+	/// Events notifications are sent for.
+	pub events: Vec<PowerEvent>,
+}
+
+impl Default for PushoverOptions {
+	fn default() -> Self {
+		Self {
+			token: String::new(),
+			user: String::new(),
+			events: PowerEvent::ALL.to_vec(),
+		}
+	}
+}
+
+#[derive(Clone, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(default)]
+pub struct TelegramOptions {
+	/// Token of the bot sending the messages.
+	#[serde(serialize_with = "crate::serialize_redacted")]
+	pub bot_token: String,
+	/// Chat to send to, as a number or `@channelname`.
+	pub chat_id: String,
+	/// Events notifications are sent for.
+	pub events: Vec<PowerEvent>,
+}
+
+impl Default for TelegramOptions {
+	fn default() -> Self {
+		Self {
+			bot_token: String::new(),
+			chat_id: String::new(),
+			events: PowerEvent::ALL.to_vec(),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+	/// Upgrade the connection with STARTTLS, usually on port 587.
+	#[default]
+	Starttls,
+	/// Connect with TLS, usually on port 465.
+	Tls,
+	/// Send in plain text, e.g. to a local relay.
+	None,
+}
+
+#[derive(Clone, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(default)]
+pub struct EmailOptions {
+	/// SMTP server to send through.
+	pub server: String,
+	pub port: u16,
+	pub tls: SmtpTls,
+	/// User name to authenticate with, if any.
+	pub username: Option<String>,
+	#[serde(serialize_with = "crate::serialize_redacted")]
+	pub password: String,
+	pub from: String,
+	pub to: Vec<String>,
+	/// Events emails are sent for.
+	pub events: Vec<PowerEvent>,
+	/// Template of the subject, where `{{event}}` is replaced with the event, `{{slug}}` with the host's slug, and `{{KEY}}` with the value
+	/// of KEY reported by apcupsd, like in `labels.ups_template`.
+	pub subject: String,
+	/// Template of the body, like `subject`.
+	pub body: String,
+}
+
+impl Default for EmailOptions {
+	fn default() -> Self {
+		Self {
+			server: "localhost".to_string(),
+			port: 587,
+			tls: SmtpTls::default(),
+			username: None,
+			password: String::new(),
+			from: String::new(),
+			to: Vec::new(),
+			events: PowerEvent::ALL.to_vec(),
+			subject: "[apcupsd] {{slug}}: {{event}}".to_string(),
+			body:
+				"{{slug}} ({{UPSNAME}}): {{event}}\n\nStatus: {{STATUS}}\nBattery charge: {{BCHARGE}}\nTime left: {{TIMELEFT}}\nLoad: {{LOADPCT}}\n"
+					.to_string(),
+		}
+	}
+}
+
+/// Sends notifications for every host, opened once at startup since it only changes on restart.
+pub struct Notifier {
+	options: NotificationOptions,
+	min_interval: Duration,
+	mailer: Option<Mailer>,
+	/// When each event was last notified about for each host.
+	last_notified: Mutex<HashMap<(String, PowerEvent), Instant>>,
+}
+
+/// Notifiers are created once and shared, so they're only equal to themselves.
+impl PartialEq for Notifier {
+	fn eq(&self, other: &Self) -> bool {
+		std::ptr::eq(self, other)
+	}
+}
+
+impl Notifier {
+	pub fn new(options: &NotificationOptions) -> Result<Self, Box<dyn std::error::Error>> {
+		Ok(Self {
+			options: options.clone(),
+			min_interval: Duration::try_from_secs_f64(options.min_interval_seconds)?,
+			mailer: options.email.as_ref().map(Mailer::new).transpose()?,
+			last_notified: Mutex::new(HashMap::new()),
+		})
+	}
+
+	/// Notify about `event` on the host with `slug` in the background, unless it was notified about within the minimum interval.
+	fn notify(&self, slug: &str, event: PowerEvent, apcupsd_data: &HashMap<String, String>) {
+		if !self.due(slug, event) {
+			return;
+		}
+		let event_name = format!("{event:?}");
+		syslog::log(
+			Priority::Warning,
+			&format!("{slug}: {}", event.description()),
+			&[("UPS", slug), ("POWER_EVENT", &event_name)],
+		);
+		let render = |template: &str| render_template(template, slug, event, apcupsd_data);
+
+		if let (Some(mailer), Some((subject, body))) = (&self.mailer, self.email(slug, event, apcupsd_data)) {
+			mailer.send(slug, subject, body);
+		}
+		for (sink, request) in self.push_requests(event, &render(&self.options.title), &render(&self.options.message)) {
+			let slug = slug.to_string();
+			tokio::spawn(async move {
+				let result = match request {
+					Ok(request) => http_client::send(request).await,
+					Err(e) => Err(e),
+				};
+				match result {
+					Ok((status, _)) if status.is_success() => {},
+					Ok((status, body)) => crate::syslog::error(format!(
+						"Failed to notify {sink} about {slug}: {status} {}",
+						String::from_utf8_lossy(&body)
+					)),
+					Err(e) => crate::syslog::error(format!("Failed to notify {sink} about {slug}: {e}")),
+				}
+			});
+		}
+	}
+
+	/// Whether `event` on the host with `slug` wasn't notified about within the minimum interval, recording that it's notified about now
+	/// if so.
+	fn due(&self, slug: &str, event: PowerEvent) -> bool {
+		let mut last_notified = self.last_notified.lock().unwrap_or_else(PoisonError::into_inner);
+		let key = (slug.to_string(), event);
+		if last_notified.get(&key).is_some_and(|last| last.elapsed() < self.min_interval) {
+			return false;
+		}
+		last_notified.insert(key, Instant::now());
+		true
+	}
+
+	/// The subject and body of the email about `event`, if emails are sent for it.
+	fn email(&self, slug: &str, event: PowerEvent, apcupsd_data: &HashMap<String, String>) -> Option<(String, String)> {
+		let email_options = self.options.email.as_ref().filter(|email_options| email_options.events.contains(&event))?;
+		let render = |template: &str| render_template(template, slug, event, apcupsd_data);
+		Some((render(&email_options.subject), render(&email_options.body)))
+	}
+
+	/// Requests sending a push notification about `event` to each sink that wants it, with the name of the sink.
+	fn push_requests(&self, event: PowerEvent, title: &str, message: &str) -> Vec<(&'static str, PushRequest)> {
+		let mut requests = Vec::new();
+		if let Some(ntfy) = self.options.ntfy.as_ref().filter(|ntfy| ntfy.events.contains(&event)) {
+			let mut request = Request::post(&ntfy.url)
+				.header("Title", title)
+				.header("Priority", if event.is_problem() { "high" } else { "default" })
+				.header("Tags", if event.is_problem() { "warning" } else { "white_check_mark" });
+			if !ntfy.token.is_empty() {
+				request = request.header(AUTHORIZATION, format!("Bearer {}", ntfy.token));
+			}
+			requests.push(("ntfy", request.body(Full::new(Bytes::from(message.to_string()))).map_err(HttpError::from)));
+		}
+		if let Some(pushover) = self.options.pushover.as_ref().filter(|pushover| pushover.events.contains(&event)) {
+			let body = json!({
+				"token": pushover.token,
+				"user": pushover.user,
+				"title": title,
+				"message": message,
+				"priority": i32::from(event.is_problem()),
+			});
+			let request = Request::post("https://api.pushover.net/1/messages.json")
+				.header(CONTENT_TYPE, "application/json")
+				.body(Full::new(Bytes::from(body.to_string())))
+				.map_err(HttpError::from);
+			requests.push(("Pushover", request));
+		}
+		if let Some(telegram) = self.options.telegram.as_ref().filter(|telegram| telegram.events.contains(&event)) {
+			let body = json!({ "chat_id": telegram.chat_id, "text": format!("{title}\n{message}") });
+			let request = Request::post(format!("https://api.telegram.org/bot{}/sendMessage", telegram.bot_token))
+				.header(CONTENT_TYPE, "application/json")
+				.body(Full::new(Bytes::from(body.to_string())))
+				.map_err(HttpError::from);
+			requests.push(("Telegram", request));
+		}
+		requests
+	}
+}
+
+/// Render a notification template with `{{event}}` standing for the event's description. Templates referring to keys that weren't
+/// reported fall back to the bare event.
+fn render_template(template: &str, slug: &str, event: PowerEvent, apcupsd_data: &HashMap<String, String>) -> String {
+	let mut template_data = apcupsd_data.clone();
+	template_data.insert("event".to_string(), event.description().to_string());
+	labels::render_template(template, &template_data, slug).unwrap_or_else(|| format!("{slug}: {}", event.description()))
+}
+
+/// Stand-in for the mailer in builds without the `email` feature, which can't be created.
+#[cfg(not(feature = "email"))]
+struct Mailer(std::convert::Infallible);
+
+#[cfg(not(feature = "email"))]
+impl Mailer {
+	fn new(_options: &EmailOptions) -> Result<Self, Box<dyn std::error::Error>> {
+		Err(crate::feature_disabled("email").into())
+	}
+
+	fn send(&self, _slug: &str, _subject: String, _body: String) {
+		match self.0 {}
+	}
+}
+
+/// Detects power events on one host by comparing the `STATUS` of each poll to the previous one.
+pub struct HostNotifier {
+	pub notifier: Arc<Notifier>,
+	pub slug: String,
+	/// Whether each of [`EVENT_FLAGS`] was set in the previous poll, or `None` before the first one.
+	previous_flags: Option<[bool; EVENT_FLAGS.len()]>,
+}
+
+impl HostNotifier {
+	pub fn new(notifier: Arc<Notifier>, slug: String) -> Self {
+		Self {
+			notifier,
+			slug,
+			previous_flags: None,
+		}
+	}
+
+	/// Notify about events since the previous poll.
+	pub fn observe(&mut self, apcupsd_data: &HashMap<String, String>) {
+		for event in self.events(apcupsd_data) {
+			self.notifier.notify(&self.slug, event, apcupsd_data);
+		}
+	}
+
+	/// Detect the events since the previous poll. The first poll only sets the baseline, so restarting the exporter during an outage
+	/// doesn't notify again.
+	fn events(&mut self, apcupsd_data: &HashMap<String, String>) -> Vec<PowerEvent> {
+		let Some(status) = apcupsd_data.get("STATUS") else {
+			return Vec::new();
+		};
+		let flags = EVENT_FLAGS.map(|(flag, _)| status.split_whitespace().any(|status_flag| status_flag == flag));
+		let Some(previous_flags) = self.previous_flags.replace(flags) else {
+			return Vec::new();
+		};
+		let mut events: Vec<_> = previous_flags
+			.into_iter()
+			.zip(flags)
+			.zip(EVENT_FLAGS)
+			.filter(|((was_set, is_set), _)| *is_set && !was_set)
+			.map(|(_, (_, event))| event)
+			.collect();
+		let on_battery = |flags: [bool; EVENT_FLAGS.len()]| flags[0];
+		if on_battery(previous_flags) && !on_battery(flags) {
+			events.push(PowerEvent::Restored);
+		}
+		events
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn status(status: &str) -> HashMap<String, String> {
+		HashMap::from([
+			("STATUS".to_string(), status.to_string()),
+			("BCHARGE".to_string(), "87.0 Percent".to_string()),
+			("UPSNAME".to_string(), "rack1".to_string()),
+		])
+	}
+
+	fn test_notifier(options: NotificationOptions) -> Arc<Notifier> {
+		Arc::new(Notifier {
+			min_interval: Duration::try_from_secs_f64(options.min_interval_seconds).unwrap(),
+			options,
+			mailer: None,
+			last_notified: Mutex::new(HashMap::new()),
+		})
+	}
+
+	#[test]
+	fn test_events() {
+		let mut host_notifier = HostNotifier::new(test_notifier(NotificationOptions::default()), "ups0".to_string());
+		// The first poll only sets the baseline.
+		assert_eq!(host_notifier.events(&status("ONBATT")), vec![]);
+		assert_eq!(host_notifier.events(&status("ONBATT")), vec![]);
+		assert_eq!(host_notifier.events(&status("ONBATT LOWBATT")), vec![PowerEvent::LowBattery]);
+		assert_eq!(host_notifier.events(&status("ONLINE")), vec![PowerEvent::Restored]);
+		assert_eq!(
+			host_notifier.events(&status("ONBATT COMMLOST")),
+			vec![PowerEvent::OnBattery, PowerEvent::CommLost]
+		);
+		// Polls without a STATUS are skipped rather than treated as every flag clearing.
+		assert_eq!(host_notifier.events(&HashMap::new()), vec![]);
+		assert_eq!(host_notifier.events(&status("ONBATT COMMLOST")), vec![]);
+	}
+
+	#[test]
+	fn test_due() {
+		let notifier = test_notifier(NotificationOptions::default());
+		assert!(notifier.due("ups0", PowerEvent::OnBattery));
+		assert!(!notifier.due("ups0", PowerEvent::OnBattery));
+		assert!(notifier.due("ups0", PowerEvent::Restored));
+		assert!(notifier.due("ups1", PowerEvent::OnBattery));
+	}
+
+	#[test]
+	fn test_email() {
+		let email = EmailOptions {
+			events: vec![PowerEvent::OnBattery, PowerEvent::Restored],
+			body: "{{slug}} ({{UPSNAME}}): {{event}}, charge {{BCHARGE}}".to_string(),
+			..Default::default()
+		};
+		let notifier = test_notifier(NotificationOptions {
+			email: Some(email),
+			..Default::default()
+		});
+		let (subject, body) = notifier.email("ups0", PowerEvent::OnBattery, &status("ONBATT")).unwrap();
+		assert_eq!(subject, "[apcupsd] ups0: on battery");
+		assert_eq!(body, "ups0 (rack1): on battery, charge 87.0 Percent");
+		assert!(notifier.email("ups0", PowerEvent::LowBattery, &status("ONBATT LOWBATT")).is_none());
+		// Templates referring to keys that weren't reported fall back to the bare event.
+		assert_eq!(
+			notifier.email("ups0", PowerEvent::Restored, &HashMap::new()).unwrap().1,
+			"ups0: power restored"
+		);
+
+		assert!(test_notifier(NotificationOptions::default()).email("ups0", PowerEvent::OnBattery, &status("ONBATT")).is_none());
+	}
+}
@@ -0,0 +1,54 @@
+//! Sending emails over SMTP with lettre.
+
+use lettre::{
+	message::header::ContentType, transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+
+use super::{EmailOptions, SmtpTls};
+
+pub struct Mailer {
+	options: EmailOptions,
+	transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl Mailer {
+	pub fn new(options: &EmailOptions) -> Result<Self, Box<dyn std::error::Error>> {
+		let builder = match options.tls {
+			SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&options.server)?,
+			SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&options.server)?,
+			SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&options.server),
+		};
+		let mut builder = builder.port(options.port);
+		if let Some(username) = &options.username {
+			builder = builder.credentials(Credentials::new(username.clone(), options.password.clone()));
+		}
+		Ok(Self {
+			options: options.clone(),
+			transport: builder.build(),
+		})
+	}
+
+	/// Send an email about the host with `slug` in the background.
+	pub fn send(&self, slug: &str, subject: String, body: String) {
+		match self.message(subject, body) {
+			Ok(message) => {
+				let transport = self.transport.clone();
+				let slug = slug.to_string();
+				tokio::spawn(async move {
+					if let Err(e) = transport.send(message).await {
+						crate::syslog::error(format!("Failed to send email about {slug}: {e}"));
+					}
+				});
+			},
+			Err(e) => crate::syslog::error(format!("Failed to create email about {slug}: {e}")),
+		}
+	}
+
+	fn message(&self, subject: String, body: String) -> Result<Message, Box<dyn std::error::Error>> {
+		let mut builder = Message::builder().from(self.options.from.parse()?).subject(subject).header(ContentType::TEXT_PLAIN);
+		for to in &self.options.to {
+			builder = builder.to(to.parse()?);
+		}
+		Ok(builder.body(body)?)
+	}
+}
@@ -0,0 +1,127 @@
+//! Pushing metrics to an OpenTelemetry collector with OTLP over HTTP, for pipelines that don't scrape Prometheus exporters.
+
+use std::{
+	collections::HashMap,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use http_body_util::Full;
+use hyper::{body::Bytes, header::CONTENT_TYPE, Request, Uri};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::time::timeout;
+
+use crate::{
+	built_info,
+	http_client::{self, HttpError},
+	labels::parse_sample,
+	SharedExporter,
+};
+
+/// How long a push may take before it's given up on, so a collector that stops responding doesn't hold up later pushes.
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Deserialize, JsonSchema, Serialize)]
+#[serde(default)]
+pub struct OtlpOptions {
+	/// OTLP/HTTP metrics endpoint of the collector.
+	pub endpoint: String,
+	/// How often every host is fetched and pushed.
+	pub interval_seconds: f64,
+}
+
+impl Default for OtlpOptions {
+	fn default() -> Self {
+		Self {
+			endpoint: "http://127.0.0.1:4318/v1/metrics".to_string(),
+			interval_seconds: 60.,
+		}
+	}
+}
+
+/// Push the metrics of every host every interval in the background.
+pub fn spawn(options: OtlpOptions, exporter: SharedExporter) -> Result<(), Box<dyn std::error::Error>> {
+	let endpoint: Uri = options.endpoint.parse()?;
+	if !matches!(endpoint.scheme_str(), Some("http" | "https")) {
+		return Err(format!("unsupported OTLP endpoint \"{endpoint}\", only http:// and https:// are supported").into());
+	}
+	let interval = Duration::try_from_secs_f64(options.interval_seconds)?;
+	tokio::spawn(async move {
+		loop {
+			let exporter = exporter.current();
+			let mut resource_metrics = Vec::with_capacity(exporter.hosts.len());
+			for host in &exporter.hosts {
+				let (rendered, _) = host.render_without_scrape(&exporter.render_options).await;
+				resource_metrics.push(resource_metrics_json(&host.slug, &rendered));
+			}
+			if let Err(e) = post(&endpoint, json!({ "resourceMetrics": resource_metrics }).to_string()).await {
+				crate::syslog::error(format!("Failed to push metrics to {endpoint}: {e}"));
+			}
+			tokio::time::sleep(interval).await;
+		}
+	});
+	Ok(())
+}
+
+/// Convert the rendered metrics of a host to OTLP JSON, with the host as the resource. Histograms aren't converted.
+fn resource_metrics_json(slug: &str, rendered: &str) -> Value {
+	let time_unix_nano = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string();
+	let mut help = HashMap::new();
+	let mut types = HashMap::new();
+	let mut metrics: Vec<(&str, Vec<Value>)> = Vec::new();
+	for line in rendered.lines() {
+		if let Some(line) = line.strip_prefix("# HELP ") {
+			if let Some((name, text)) = line.split_once(' ') {
+				help.insert(name, text);
+			}
+		} else if let Some(line) = line.strip_prefix("# TYPE ") {
+			if let Some((name, metric_type)) = line.split_once(' ') {
+				types.insert(name, metric_type);
+			}
+		} else if let Some((name, labels, value)) = parse_sample(line) {
+			if !types.contains_key(name) {
+				continue;
+			}
+			let attributes: Vec<_> =
+				labels.into_iter().filter(|(key, _)| key != "exported_ups").map(|(key, value)| attribute(&key, &value)).collect();
+			let data_point = json!({ "attributes": attributes, "timeUnixNano": time_unix_nano, "asDouble": value });
+			match metrics.last_mut() {
+				Some((last_name, data_points)) if *last_name == name => data_points.push(data_point),
+				_ => metrics.push((name, vec![data_point])),
+			}
+		}
+	}
+	let metrics: Vec<_> = metrics
+		.into_iter()
+		.filter_map(|(name, data_points)| {
+			let mut metric = json!({ "name": name, "description": help.get(name).copied().unwrap_or_default() });
+			match types.get(name).copied() {
+				Some("gauge") => metric["gauge"] = json!({ "dataPoints": data_points }),
+				Some("counter") => metric["sum"] = json!({ "dataPoints": data_points, "aggregationTemporality": 2, "isMonotonic": true }),
+				_ => return None,
+			}
+			Some(metric)
+		})
+		.collect();
+	json!({
+		"resource": { "attributes": [attribute("service.name", "apcupsd-exporter"), attribute("ups.slug", slug)] },
+		"scopeMetrics": [{
+			"scope": { "name": built_info::PKG_NAME, "version": built_info::PKG_VERSION },
+			"metrics": metrics,
+		}],
+	})
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+	json!({ "key": key, "value": { "stringValue": value } })
+}
+
+async fn post(endpoint: &Uri, body: String) -> Result<(), HttpError> {
+	let request = Request::post(endpoint).header(CONTENT_TYPE, "application/json").body(Full::new(Bytes::from(body)))?;
+	let (status, _) = timeout(PUSH_TIMEOUT, http_client::send(request)).await??;
+	if !status.is_success() {
+		return Err(format!("collector responded with {status}").into());
+	}
+	Ok(())
+}
@@ -0,0 +1,108 @@
+//! Keeping the last successfully fetched data of each host on disk, so it can be served (flagged stale) right after the exporter restarts
+//! instead of leaving a gap that trips `absent()` alerts.
+
+use std::{
+	collections::HashMap,
+	io,
+	path::{Path, PathBuf},
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize)]
+struct PersistedSnapshot {
+	fetched_at_seconds: f64,
+	data: HashMap<String, String>,
+}
+
+/// The file the data of the host with `slug` is kept in.
+pub fn snapshot_path(directory: &Path, slug: &str) -> PathBuf {
+	directory.join(format!("{slug}.json"))
+}
+
+/// Data saved at `path`, with when it was fetched, or `None` if there is none or it can't be read.
+pub fn load(path: &Path) -> Option<(SystemTime, HashMap<String, String>)> {
+	let snapshot = match std::fs::read_to_string(path) {
+		Ok(snapshot) => snapshot,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+		Err(e) => {
+			crate::syslog::error(format!("Failed to read saved snapshot {}: {e}", path.display()));
+			return None;
+		},
+	};
+	let snapshot: PersistedSnapshot = match serde_json::from_str(&snapshot) {
+		Ok(snapshot) => snapshot,
+		Err(e) => {
+			eprintln!("Ignoring invalid saved snapshot {}: {e}", path.display());
+			return None;
+		},
+	};
+	Some((UNIX_EPOCH + Duration::try_from_secs_f64(snapshot.fetched_at_seconds).ok()?, snapshot.data))
+}
+
+/// Save data fetched at `fetched_at` to `path`, writing a temporary file first so a crash never leaves a partial snapshot. Failures are
+/// logged.
+pub async fn save(path: &Path, fetched_at: SystemTime, data: &HashMap<String, String>) {
+	let snapshot = PersistedSnapshot {
+		fetched_at_seconds: fetched_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64(),
+		data: data.clone(),
+	};
+	let temporary_path = path.with_extension("json.tmp");
+	let result = async {
+		tokio::fs::write(&temporary_path, serde_json::to_vec(&snapshot)?).await?;
+		tokio::fs::rename(&temporary_path, path).await
+	};
+	if let Err(e) = result.await {
+		crate::syslog::error(format!("Failed to save snapshot {}: {e}", path.display()));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use crate::{nis_client, statistics::PollStatistics, APCThrottledAccess, Endpoint};
+
+	#[tokio::test]
+	async fn test_saved_snapshot_is_served_until_a_fetch_succeeds() {
+		let directory = std::env::temp_dir().join(format!("apcupsd_exporter_persistence_{}", std::process::id()));
+		std::fs::create_dir_all(&directory).unwrap();
+		let path = snapshot_path(&directory, "ups0");
+		let fetched_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+		let data = HashMap::from([("STATUS".to_string(), "ONLINE".to_string())]);
+		save(&path, fetched_at, &data).await;
+		assert_eq!(load(&path), Some((fetched_at, data.clone())));
+
+		// Nothing listens on port 1, so every fetch fails.
+		let endpoint = Endpoint {
+			name: "127.0.0.1:1".to_string(),
+			client: nis_client("127.0.0.1".to_string(), 1),
+			port: 1,
+			resolved_name: None,
+			alternate_ports: Vec::new(),
+		};
+		let statistics = PollStatistics::new(&Default::default(), Default::default());
+		let access = APCThrottledAccess::new(
+			vec![endpoint],
+			Duration::ZERO,
+			Duration::ZERO,
+			statistics,
+			None,
+			None,
+			None,
+			None,
+			None,
+			Some(path),
+			None,
+		);
+		for _ in 0..2 {
+			let snapshot = access.fetch().await.unwrap();
+			assert_eq!(snapshot.data, data);
+			assert!(snapshot.stale);
+			assert_eq!(snapshot.restored, Some(true));
+			assert_eq!(snapshot.last_success, Some(fetched_at));
+		}
+		std::fs::remove_dir_all(&directory).unwrap();
+	}
+}
@@ -0,0 +1,312 @@
+//! The Prometheus protobuf exposition format, served instead of the text format to scrapers that prefer it, since it's smaller to send
+//! and parse with many hosts, and the only format that can carry native histograms.
+
+use std::collections::HashMap;
+
+use hyper::{header::ACCEPT, HeaderMap};
+use prost::Message;
+
+use crate::labels::parse_sample;
+
+/// Content type of length-delimited `io.prometheus.client.MetricFamily` messages.
+pub const CONTENT_TYPE: &str = "application/vnd.google.protobuf; proto=io.prometheus.client.MetricFamily; encoding=delimited";
+
+/// Whether the `Accept` header of a request prefers the protobuf format to the text format, going by the quality of each. Prometheus
+/// only asks for protobuf if it's listed in its `scrape_protocols`.
+pub fn preferred(headers: &HeaderMap) -> bool {
+	let Some(accept) = headers.get(ACCEPT).and_then(|accept| accept.to_str().ok()) else {
+		return false;
+	};
+	let (mut protobuf, mut text) = (0_f64, 0_f64);
+	for media_range in accept.split(',') {
+		let mut parameters = media_range.split(';').map(str::trim);
+		let media_type = parameters.next().unwrap_or_default().to_ascii_lowercase();
+		let (mut quality, mut proto, mut encoding) = (1., None, None);
+		for parameter in parameters {
+			match parameter.split_once('=') {
+				Some(("q", value)) => quality = value.parse().unwrap_or(0.),
+				Some(("proto", value)) => proto = Some(value),
+				Some(("encoding", value)) => encoding = Some(value),
+				_ => {},
+			}
+		}
+		match media_type.as_str() {
+			"application/vnd.google.protobuf" if proto == Some("io.prometheus.client.MetricFamily") && encoding == Some("delimited") => {
+				protobuf = protobuf.max(quality)
+			},
+			"text/plain" | "text/*" | "*/*" => text = text.max(quality),
+			_ => {},
+		}
+	}
+	protobuf > 0. && protobuf >= text
+}
+
+/// Convert metrics rendered in the text format to length-delimited metric families. The `_bucket`, `_sum`, and `_count` samples of a
+/// histogram are combined into one metric per set of labels, and samples without a `# TYPE` become untyped families.
+pub fn encode(rendered: &str) -> Vec<u8> {
+	let mut families: Vec<MetricFamily> = Vec::new();
+	let mut family_indexes = HashMap::new();
+	let mut help = HashMap::new();
+	for line in rendered.lines() {
+		if let Some(line) = line.strip_prefix("# HELP ") {
+			if let Some((name, text)) = line.split_once(' ') {
+				help.insert(name, unescape_help(text));
+			}
+		} else if let Some(line) = line.strip_prefix("# TYPE ") {
+			if let Some((name, metric_type)) = line.split_once(' ') {
+				family_indexes.entry(name).or_insert_with(|| {
+					families.push(MetricFamily::new(name, MetricType::from_text(metric_type)));
+					families.len() - 1
+				});
+			}
+		} else if let Some((name, labels, value)) = parse_sample(line) {
+			let histogram_name = ["_bucket", "_sum", "_count"].iter().find_map(|suffix| name.strip_suffix(suffix));
+			let index = match histogram_name.and_then(|histogram_name| family_indexes.get(histogram_name)) {
+				Some(&index) if families[index].r#type == Some(MetricType::Histogram as i32) => index,
+				_ => *family_indexes.entry(name).or_insert_with(|| {
+					families.push(MetricFamily::new(name, MetricType::Untyped));
+					families.len() - 1
+				}),
+			};
+			families[index].add_sample(name, labels, value);
+		}
+	}
+
+	let mut encoded = Vec::new();
+	for mut family in families {
+		family.help = family.name.as_deref().and_then(|name| help.remove(name));
+		family.encode_length_delimited(&mut encoded).expect("a Vec has room for any message");
+	}
+	encoded
+}
+
+/// Undo the escaping of backslashes and newlines in `# HELP` lines.
+fn unescape_help(text: &str) -> String {
+	let mut unescaped = String::with_capacity(text.len());
+	let mut chars = text.chars();
+	while let Some(c) = chars.next() {
+		match (c, chars.clone().next()) {
+			('\\', Some('n')) => {
+				unescaped.push('\n');
+				chars.next();
+			},
+			('\\', Some('\\')) => {
+				unescaped.push('\\');
+				chars.next();
+			},
+			(c, _) => unescaped.push(c),
+		}
+	}
+	unescaped
+}
+
+// The messages of the exposition format's metrics.proto that the exporter renders, with the same field tags.
+
+#[derive(Clone, PartialEq, Message)]
+struct MetricFamily {
+	#[prost(string, optional, tag = "1")]
+	name: Option<String>,
+	#[prost(string, optional, tag = "2")]
+	help: Option<String>,
+	#[prost(enumeration = "MetricType", optional, tag = "3")]
+	r#type: Option<i32>,
+	#[prost(message, repeated, tag = "4")]
+	metric: Vec<Metric>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+enum MetricType {
+	Counter = 0,
+	Gauge = 1,
+	Untyped = 3,
+	Histogram = 4,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Metric {
+	#[prost(message, repeated, tag = "1")]
+	label: Vec<LabelPair>,
+	#[prost(message, optional, tag = "2")]
+	gauge: Option<Value>,
+	#[prost(message, optional, tag = "3")]
+	counter: Option<Value>,
+	#[prost(message, optional, tag = "5")]
+	untyped: Option<Value>,
+	#[prost(message, optional, tag = "7")]
+	histogram: Option<Histogram>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct LabelPair {
+	#[prost(string, optional, tag = "1")]
+	name: Option<String>,
+	#[prost(string, optional, tag = "2")]
+	value: Option<String>,
+}
+
+/// `Gauge`, `Counter`, and `Untyped`, which all have their value as the first field.
+#[derive(Clone, PartialEq, Message)]
+struct Value {
+	#[prost(double, optional, tag = "1")]
+	value: Option<f64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Histogram {
+	#[prost(uint64, optional, tag = "1")]
+	sample_count: Option<u64>,
+	#[prost(double, optional, tag = "2")]
+	sample_sum: Option<f64>,
+	#[prost(message, repeated, tag = "3")]
+	bucket: Vec<Bucket>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Bucket {
+	#[prost(uint64, optional, tag = "1")]
+	cumulative_count: Option<u64>,
+	#[prost(double, optional, tag = "2")]
+	upper_bound: Option<f64>,
+}
+
+impl MetricType {
+	/// The type named in a `# TYPE` line. Summaries aren't rendered by the exporter, so their samples are sent as untyped.
+	fn from_text(metric_type: &str) -> Self {
+		match metric_type {
+			"counter" => Self::Counter,
+			"gauge" => Self::Gauge,
+			"histogram" => Self::Histogram,
+			_ => Self::Untyped,
+		}
+	}
+}
+
+impl MetricFamily {
+	fn new(name: &str, metric_type: MetricType) -> Self {
+		Self {
+			name: Some(name.to_string()),
+			help: None,
+			r#type: Some(metric_type as i32),
+			metric: Vec::new(),
+		}
+	}
+
+	fn add_sample(&mut self, name: &str, labels: Vec<(String, String)>, value: f64) {
+		let to_pairs = |labels: Vec<(String, String)>| {
+			labels
+				.into_iter()
+				.map(|(name, value)| LabelPair {
+					name: Some(name),
+					value: Some(value),
+				})
+				.collect::<Vec<_>>()
+		};
+		let metric_type = self.r#type.and_then(|metric_type| MetricType::try_from(metric_type).ok());
+		if metric_type != Some(MetricType::Histogram) {
+			let value = Some(Value { value: Some(value) });
+			let mut metric = Metric {
+				label: to_pairs(labels),
+				..Metric::default()
+			};
+			match metric_type {
+				Some(MetricType::Counter) => metric.counter = value,
+				Some(MetricType::Gauge) => metric.gauge = value,
+				_ => metric.untyped = value,
+			}
+			self.metric.push(metric);
+			return;
+		}
+
+		let (le, labels): (Vec<_>, Vec<_>) = labels.into_iter().partition(|(name, _)| name == "le");
+		let label = to_pairs(labels);
+		let index = match self.metric.iter().rposition(|metric| metric.label == label) {
+			Some(index) => index,
+			None => {
+				self.metric.push(Metric {
+					label,
+					histogram: Some(Histogram::default()),
+					..Metric::default()
+				});
+				self.metric.len() - 1
+			},
+		};
+		let histogram = self.metric[index].histogram.get_or_insert_with(Histogram::default);
+		if name.ends_with("_sum") {
+			histogram.sample_sum = Some(value);
+		} else if name.ends_with("_count") {
+			histogram.sample_count = Some(value as u64);
+		} else if let Some(upper_bound) = le.first().and_then(|(_, le)| le.parse::<f64>().ok()) {
+			// The `+Inf` bucket is implied by the sample count.
+			if upper_bound.is_finite() {
+				histogram.bucket.push(Bucket {
+					cumulative_count: Some(value as u64),
+					upper_bound: Some(upper_bound),
+				});
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use hyper::header::HeaderValue;
+
+	use super::*;
+
+	#[test]
+	fn test_preferred() {
+		let mut headers = HeaderMap::new();
+		assert!(!preferred(&headers));
+		let prometheus = "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;encoding=delimited;q=0.6,\
+			application/openmetrics-text;version=1.0.0;q=0.5,text/plain;version=0.0.4;q=0.4,*/*;q=0.1";
+		headers.insert(ACCEPT, HeaderValue::from_static(prometheus));
+		assert!(preferred(&headers));
+		headers.insert(
+			ACCEPT,
+			HeaderValue::from_static("text/plain;version=0.0.4;q=1,application/vnd.google.protobuf;q=0.5"),
+		);
+		assert!(!preferred(&headers));
+		headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
+		assert!(!preferred(&headers));
+	}
+
+	#[test]
+	fn test_encode() {
+		let rendered = "# HELP apcupsd_line_volts Line voltage.\n# TYPE apcupsd_line_volts gauge\napcupsd_line_volts{ups=\"a\\\"b\"} 230.5\n\
+			# HELP apcupsd_poll_line_volts Line \\\\ voltages.\n# TYPE apcupsd_poll_line_volts histogram\n\
+			apcupsd_poll_line_volts_bucket{ups=\"a\",le=\"220\"} 1\napcupsd_poll_line_volts_bucket{ups=\"a\",le=\"+Inf\"} 3\n\
+			apcupsd_poll_line_volts_sum{ups=\"a\"} 690\napcupsd_poll_line_volts_count{ups=\"a\"} 3\nuntyped_sample 1\n";
+		let encoded = encode(rendered);
+		let mut buffer = encoded.as_slice();
+		let mut families = Vec::new();
+		while !buffer.is_empty() {
+			families.push(MetricFamily::decode_length_delimited(&mut buffer).unwrap());
+		}
+		assert_eq!(families.len(), 3);
+
+		let gauge = &families[0];
+		assert_eq!(gauge.name.as_deref(), Some("apcupsd_line_volts"));
+		assert_eq!(gauge.help.as_deref(), Some("Line voltage."));
+		assert_eq!(gauge.r#type, Some(MetricType::Gauge as i32));
+		assert_eq!(gauge.metric[0].label[0].value.as_deref(), Some("a\"b"));
+		assert_eq!(gauge.metric[0].gauge.as_ref().and_then(|gauge| gauge.value), Some(230.5));
+
+		let histogram_family = &families[1];
+		assert_eq!(histogram_family.help.as_deref(), Some("Line \\ voltages."));
+		assert_eq!(histogram_family.metric.len(), 1);
+		let histogram = histogram_family.metric[0].histogram.as_ref().unwrap();
+		assert_eq!(histogram.sample_count, Some(3));
+		assert_eq!(histogram.sample_sum, Some(690.));
+		assert_eq!(
+			histogram.bucket,
+			vec![Bucket {
+				cumulative_count: Some(1),
+				upper_bound: Some(220.)
+			}]
+		);
+
+		assert_eq!(families[2].r#type, Some(MetricType::Untyped as i32));
+		assert_eq!(families[2].metric[0].untyped.as_ref().and_then(|untyped| untyped.value), Some(1.));
+	}
+}
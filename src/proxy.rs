@@ -0,0 +1,129 @@
+//! Reaching NIS servers through a SOCKS5 or HTTP CONNECT proxy. The NIS client only connects directly, so connections are accepted on a
+//! local port and forwarded through the proxy.
+
+use std::{
+	io::{self, ErrorKind},
+	net::{IpAddr, SocketAddr},
+};
+
+use hyper::Uri;
+use tokio::{
+	io::{copy_bidirectional, AsyncReadExt, AsyncWriteExt},
+	net::{TcpListener, TcpStream},
+};
+
+/// Forward connections to a local port to `target` through the proxy at `proxy` (`socks5://host:port` or `http://host:port`) in the
+/// background, returning the local address to connect to instead of `target`.
+pub async fn spawn_forwarder(proxy: &str, target: (String, u16)) -> Result<SocketAddr, Box<dyn std::error::Error>> {
+	let proxy: Uri = proxy.parse()?;
+	let socks5 = match proxy.scheme_str() {
+		Some("socks5" | "socks5h") => true,
+		Some("http") => false,
+		_ => return Err(format!("unsupported proxy \"{proxy}\", only socks5:// and http:// are supported").into()),
+	};
+	// `Uri::host` keeps the brackets around IPv6 addresses, which can't be connected to.
+	let proxy_host = proxy.host().ok_or("proxy has no host")?.trim_start_matches('[').trim_end_matches(']');
+	let proxy_address = (proxy_host.to_string(), proxy.port_u16().unwrap_or(if socks5 { 1080 } else { 8080 }));
+	let listener = TcpListener::bind("127.0.0.1:0").await?;
+	let local_address = listener.local_addr()?;
+	tokio::spawn(async move {
+		loop {
+			let mut client = match listener.accept().await {
+				Ok((client, _)) => client,
+				Err(e) => {
+					crate::syslog::error(format!(
+						"Proxy forwarder for {} failed to accept connection: {e}",
+						crate::host_port(&target.0, target.1)
+					));
+					continue;
+				},
+			};
+			let proxy_address = proxy_address.clone();
+			let target = target.clone();
+			tokio::spawn(async move {
+				let result = async {
+					let mut upstream = TcpStream::connect((proxy_address.0.as_str(), proxy_address.1)).await?;
+					if socks5 {
+						socks5_connect(&mut upstream, &target).await?;
+					} else {
+						http_connect(&mut upstream, &target).await?;
+					}
+					copy_bidirectional(&mut client, &mut upstream).await
+				};
+				if let Err(e) = result.await {
+					crate::syslog::error(format!(
+						"Failed to connect to {} through proxy {}: {e}",
+						crate::host_port(&target.0, target.1),
+						crate::host_port(&proxy_address.0, proxy_address.1)
+					));
+				}
+			});
+		}
+	});
+	Ok(local_address)
+}
+
+/// Ask a SOCKS5 proxy without authentication to connect to `target`, leaving name resolution to the proxy unless it's an address.
+async fn socks5_connect(stream: &mut TcpStream, (host, port): &(String, u16)) -> io::Result<()> {
+	stream.write_all(&[5, 1, 0]).await?;
+	let mut method = [0; 2];
+	stream.read_exact(&mut method).await?;
+	if method != [5, 0] {
+		return Err(io::Error::new(ErrorKind::PermissionDenied, "SOCKS5 proxy requires authentication"));
+	}
+	let mut request = vec![5, 1, 0];
+	match host.parse::<IpAddr>() {
+		Ok(IpAddr::V4(address)) => {
+			request.push(1);
+			request.extend_from_slice(&address.octets());
+		},
+		Ok(IpAddr::V6(address)) => {
+			request.push(4);
+			request.extend_from_slice(&address.octets());
+		},
+		Err(_) => {
+			let host_len = u8::try_from(host.len()).map_err(|_| io::Error::new(ErrorKind::InvalidInput, "host name too long for SOCKS5"))?;
+			request.extend_from_slice(&[3, host_len]);
+			request.extend_from_slice(host.as_bytes());
+		},
+	}
+	request.extend_from_slice(&port.to_be_bytes());
+	stream.write_all(&request).await?;
+	let mut reply = [0; 4];
+	stream.read_exact(&mut reply).await?;
+	if reply[1] != 0 {
+		return Err(io::Error::new(
+			ErrorKind::ConnectionRefused,
+			format!("SOCKS5 proxy replied with error {}", reply[1]),
+		));
+	}
+	// Skip the bound address and port, which aren't needed.
+	let address_len = match reply[3] {
+		1 => 4,
+		3 => usize::from(stream.read_u8().await?),
+		4 => 16,
+		_ => return Err(io::Error::new(ErrorKind::InvalidData, "invalid SOCKS5 reply")),
+	};
+	stream.read_exact(&mut vec![0; address_len + 2]).await?;
+	Ok(())
+}
+
+/// Ask an HTTP proxy to connect to `target` with `CONNECT`.
+async fn http_connect(stream: &mut TcpStream, (host, port): &(String, u16)) -> io::Result<()> {
+	let authority = crate::host_port(host, *port);
+	stream.write_all(format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n\r\n").as_bytes()).await?;
+	// Read byte by byte so nothing after the response head, which belongs to apcupsd, is consumed.
+	let mut response = Vec::new();
+	while !response.ends_with(b"\r\n\r\n") {
+		response.push(stream.read_u8().await?);
+	}
+	let response = String::from_utf8_lossy(&response);
+	let status_line = response.lines().next().unwrap_or_default();
+	if status_line.split_whitespace().nth(1) != Some("200") {
+		return Err(io::Error::new(
+			ErrorKind::ConnectionRefused,
+			format!("HTTP proxy responded with \"{status_line}\""),
+		));
+	}
+	Ok(())
+}
@@ -0,0 +1,151 @@
+//! Tracking the runtime estimated by the UPS against a rolling baseline at comparable loads, to give early warning of failing batteries
+//! before apcupsd reports that they need replacing.
+
+use std::{
+	collections::HashMap,
+	fs,
+	path::PathBuf,
+	sync::{Arc, Mutex, PoisonError},
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use prometheus_exporter_base::{MetricType, PrometheusMetric};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{parse_metric, prometheus_instance_with_labels, MetricParseConfig, MetricParseType, PercentScale};
+
+/// Runtime estimates are only comparable when the battery is (nearly) fully charged.
+const MIN_CHARGE_PERCENT: f64 = 95.;
+/// Baselines are saved at most this often, since they change slowly.
+const SAVE_INTERVAL: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Deserialize, JsonSchema, Serialize)]
+#[serde(default)]
+pub struct RuntimeDegradationOptions {
+	/// JSON file the baselines are persisted in across restarts; baselines start over on every restart if unset.
+	pub state_file: Option<PathBuf>,
+	/// Width of the load ranges, in percent, that runtimes are compared within.
+	pub load_bin_percent: f64,
+	/// Number of days the rolling baseline averages over.
+	pub baseline_days: f64,
+}
+
+impl Default for RuntimeDegradationOptions {
+	fn default() -> Self {
+		Self {
+			state_file: None,
+			load_bin_percent: 10.,
+			baseline_days: 30.,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+struct Baseline {
+	runtime_seconds: f64,
+	updated_timestamp_seconds: f64,
+}
+
+/// Rolling baselines of the estimated runtime of every host, by load range, loaded once at startup since they only change on restart.
+pub struct RuntimeBaselines {
+	options: RuntimeDegradationOptions,
+	state: Mutex<BaselinesState>,
+}
+
+struct BaselinesState {
+	baselines: HashMap<String, HashMap<u32, Baseline>>,
+	last_save: Instant,
+}
+
+/// Baselines are created once and shared, so they're only equal to themselves.
+impl PartialEq for RuntimeBaselines {
+	fn eq(&self, other: &Self) -> bool {
+		std::ptr::eq(self, other)
+	}
+}
+
+impl RuntimeBaselines {
+	/// Create baselines, loading previously saved ones from the state file if it exists.
+	pub fn load(options: RuntimeDegradationOptions) -> Result<Self, Box<dyn std::error::Error>> {
+		let baselines = match &options.state_file {
+			Some(path) if fs::exists(path)? => serde_json::from_str(&fs::read_to_string(path)?)?,
+			_ => HashMap::new(),
+		};
+		Ok(Self {
+			options,
+			state: Mutex::new(BaselinesState {
+				baselines,
+				last_save: Instant::now(),
+			}),
+		})
+	}
+
+	/// Compare the current runtime estimate of `slug` to its baseline at the current load and then fold it into the baseline, returning
+	/// the ratio of the two. Returns `None` if the UPS is on battery or not fully charged, when estimates aren't comparable.
+	pub fn observe(&self, slug: &str, apcupsd_data: &HashMap<String, String>) -> Option<f64> {
+		let value = |key: &str, parse_type: MetricParseType| {
+			let parse_config = MetricParseConfig {
+				percent_scale: PercentScale::Percent,
+				..parse_type.into()
+			};
+			parse_metric(apcupsd_data.get(key)?.clone(), parse_config).ok().flatten()
+		};
+		let on_battery = apcupsd_data.get("STATUS").is_some_and(|status| status.split_whitespace().any(|flag| flag == "ONBATT"));
+		if on_battery || value("BCHARGE", MetricParseType::Percentage)? < MIN_CHARGE_PERCENT {
+			return None;
+		}
+		let runtime = value("TIMELEFT", MetricParseType::Duration)?;
+		let load_bin = (value("LOADPCT", MetricParseType::Percentage)? / self.options.load_bin_percent) as u32;
+
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+		let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+		let baseline = state.baselines.entry(slug.to_string()).or_default().entry(load_bin).or_insert(Baseline {
+			runtime_seconds: runtime,
+			updated_timestamp_seconds: now,
+		});
+		let ratio = runtime / baseline.runtime_seconds;
+		// Exponentially weighted by time, so the baseline doesn't depend on how often the host is scraped.
+		let weight = 1. - (-(now - baseline.updated_timestamp_seconds).max(0.) / (self.options.baseline_days * 86400.)).exp();
+		baseline.runtime_seconds += (runtime - baseline.runtime_seconds) * weight;
+		baseline.updated_timestamp_seconds = now;
+
+		if state.last_save.elapsed() >= SAVE_INTERVAL {
+			self.save(&mut state);
+		}
+		ratio.is_finite().then_some(ratio)
+	}
+
+	fn save(&self, state: &mut BaselinesState) {
+		state.last_save = Instant::now();
+		if let Some(path) = &self.options.state_file {
+			if let Err(e) = serde_json::to_string(&state.baselines).map_err(std::io::Error::from).and_then(|json| fs::write(path, json)) {
+				crate::syslog::error(format!("Failed to save runtime baselines to {}: {e}", path.display()));
+			}
+		}
+	}
+}
+
+/// [`RuntimeBaselines`] shared by every host, with the slug of the host whose fetches they observe.
+#[derive(Clone)]
+pub struct HostRuntimeBaselines {
+	pub baselines: Arc<RuntimeBaselines>,
+	pub slug: String,
+}
+
+impl HostRuntimeBaselines {
+	/// Observe newly fetched data like [`RuntimeBaselines::observe`].
+	pub fn observe(&self, apcupsd_data: &HashMap<String, String>) -> Option<f64> {
+		self.baselines.observe(&self.slug, apcupsd_data)
+	}
+}
+
+pub fn render_degradation_ratio(ratio: f64, labels: &[(String, String)]) -> String {
+	PrometheusMetric::build()
+		.with_name("apcupsd_battery_runtime_degradation_ratio")
+		.with_help("Estimated runtime compared to its rolling baseline at a similar load while fully charged; declines as batteries fail.")
+		.with_metric_type(MetricType::Gauge)
+		.build()
+		.render_and_append_instance(&prometheus_instance_with_labels(labels).with_value(ratio))
+		.render()
+}
@@ -0,0 +1,198 @@
+//! The exporter's HTTP listeners for `/metrics` and the admin endpoints, served with hyper directly so every endpoint gets the same
+//! routing, TLS, and authorization.
+
+use std::{convert::Infallible, future::Future, net::SocketAddr, sync::Arc, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use http_body_util::Full;
+use hyper::{
+	body::{Bytes, Incoming},
+	header::{HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE},
+	service::service_fn,
+	HeaderMap, Request, Response, StatusCode,
+};
+use hyper_util::{
+	rt::{TokioExecutor, TokioIo, TokioTimer},
+	server::conn::auto,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use subtle::ConstantTimeEq;
+use tokio::{
+	io::{AsyncRead, AsyncWrite},
+	net::TcpListener,
+	task::JoinHandle,
+};
+
+use crate::tls::{self, TlsOptions};
+
+/// Connection settings of both listeners, e.g. for a central Prometheus scraping many exporters through a proxy.
+#[derive(Clone, Deserialize, JsonSchema, Serialize)]
+#[serde(default)]
+pub struct HttpServerOptions {
+	/// Keep HTTP/1.1 connections open between requests, so scrapes don't have to connect (and do the TLS handshake) every time.
+	pub keep_alive: bool,
+	/// Seconds a client may take to send the headers of a request, which also closes kept-alive HTTP/1.1 connections left idle as long.
+	pub header_read_timeout_seconds: f64,
+	/// Maximum number of headers in an HTTP/1.1 request.
+	pub max_headers: usize,
+	/// Maximum size of the headers of a request in bytes, at least 8192.
+	pub max_header_bytes: u32,
+	/// Also serve HTTP/2, negotiated with ALPN over TLS or with prior knowledge otherwise.
+	pub http2: bool,
+	/// Maximum number of concurrent requests on an HTTP/2 connection.
+	pub http2_max_concurrent_streams: u32,
+	/// Seconds between pings on HTTP/2 connections, which are closed if a ping isn't acknowledged in time; no pings if not set.
+	pub http2_keep_alive_interval_seconds: Option<f64>,
+	/// Seconds to wait for the acknowledgement of a ping.
+	pub http2_keep_alive_timeout_seconds: f64,
+}
+
+impl Default for HttpServerOptions {
+	fn default() -> Self {
+		Self {
+			keep_alive: true,
+			header_read_timeout_seconds: 30.,
+			max_headers: 100,
+			max_header_bytes: 65536,
+			http2: true,
+			http2_max_concurrent_streams: 100,
+			http2_keep_alive_interval_seconds: None,
+			http2_keep_alive_timeout_seconds: 20.,
+		}
+	}
+}
+
+impl HttpServerOptions {
+	/// The connection builder with these settings, or why they're invalid.
+	fn builder(&self) -> Result<auto::Builder<TokioExecutor>, String> {
+		// hyper panics with a smaller buffer.
+		if self.max_header_bytes < 8192 {
+			return Err(format!(
+				"http_server: max_header_bytes must be at least 8192, not {}",
+				self.max_header_bytes
+			));
+		}
+		let seconds = |key: &str, seconds: f64| Duration::try_from_secs_f64(seconds).map_err(|e| format!("http_server: {key}: {e}"));
+		let mut builder = auto::Builder::new(TokioExecutor::new());
+		builder
+			.http1()
+			.timer(TokioTimer::new())
+			.keep_alive(self.keep_alive)
+			.header_read_timeout(seconds("header_read_timeout_seconds", self.header_read_timeout_seconds)?)
+			.max_headers(self.max_headers)
+			.max_buf_size(self.max_header_bytes as usize);
+		builder
+			.http2()
+			.timer(TokioTimer::new())
+			.max_concurrent_streams(self.http2_max_concurrent_streams)
+			.max_header_list_size(self.max_header_bytes)
+			.keep_alive_interval(
+				self.http2_keep_alive_interval_seconds.map(|interval| seconds("http2_keep_alive_interval_seconds", interval)).transpose()?,
+			)
+			.keep_alive_timeout(seconds("http2_keep_alive_timeout_seconds", self.http2_keep_alive_timeout_seconds)?);
+		Ok(if self.http2 { builder } else { builder.http1_only() })
+	}
+}
+
+/// Serve requests on `address` in the background, over TLS if `tls_options` are given, answering each with `handle`. `name` identifies
+/// the listener in logged errors.
+pub async fn spawn<H, F>(
+	name: &'static str,
+	address: SocketAddr,
+	tls_options: Option<&TlsOptions>,
+	http_options: &HttpServerOptions,
+	handle: H,
+) -> Result<JoinHandle<()>, Box<dyn std::error::Error>>
+where
+	H: Fn(Request<Incoming>) -> F + Send + Sync + 'static,
+	F: Future<Output = Response<Full<Bytes>>> + Send + 'static,
+{
+	let builder = Arc::new(http_options.builder()?);
+	let acceptor = tls_options.map(|tls_options| tls::acceptor(tls_options, http_options.http2)).transpose()?;
+	let listener = TcpListener::bind(address).await.map_err(|e| format!("{address}: {e}"))?;
+	let handle = Arc::new(handle);
+	Ok(tokio::spawn(async move {
+		loop {
+			let stream = match listener.accept().await {
+				Ok((stream, _)) => stream,
+				Err(e) => {
+					eprintln!("{name} failed to accept connection: {e}");
+					continue;
+				},
+			};
+			let acceptor = acceptor.clone();
+			let builder = builder.clone();
+			let handle = handle.clone();
+			tokio::spawn(async move {
+				let result = match acceptor {
+					Some(acceptor) => match acceptor.accept(stream).await {
+						Ok(stream) => serve_connection(&builder, stream, handle).await,
+						Err(e) => Err(e.into()),
+					},
+					None => serve_connection(&builder, stream, handle).await,
+				};
+				if let Err(e) = result {
+					eprintln!("{name} connection failed: {e}");
+				}
+			});
+		}
+	}))
+}
+
+async fn serve_connection<S, H, F>(
+	builder: &auto::Builder<TokioExecutor>,
+	stream: S,
+	handle: Arc<H>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+	S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+	H: Fn(Request<Incoming>) -> F + Send + Sync + 'static,
+	F: Future<Output = Response<Full<Bytes>>> + Send + 'static,
+{
+	let service = service_fn(move |request| {
+		let response = handle(request);
+		async move { Ok::<_, Infallible>(response.await) }
+	});
+	builder.serve_connection(TokioIo::new(stream), service).await
+}
+
+/// An error as JSON in the format of Prometheus' API (`{"status": "error", "error": ...}`) if the request accepts JSON, or as plain text
+/// otherwise.
+pub fn error_response(status: StatusCode, request_headers: &HeaderMap, error: &str) -> Response<Full<Bytes>> {
+	let accepts_json = request_headers.get(ACCEPT).and_then(|accept| accept.to_str().ok()).is_some_and(|accept| accept.contains("application/json"));
+	if !accepts_json {
+		return text_response(status, format!("{error}\n"));
+	}
+	let mut response = text_response(status, json!({ "status": "error", "error": error }).to_string());
+	response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+	response
+}
+
+/// 401 asking for HTTP basic authentication.
+pub fn unauthorized() -> Response<Full<Bytes>> {
+	let mut response = text_response(StatusCode::UNAUTHORIZED, "unauthorized\n".to_string());
+	response.headers_mut().insert(WWW_AUTHENTICATE, HeaderValue::from_static("Basic"));
+	response
+}
+
+pub fn text_response(status: StatusCode, body: String) -> Response<Full<Bytes>> {
+	let mut response = Response::new(Full::new(Bytes::from(body)));
+	*response.status_mut() = status;
+	response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"));
+	response
+}
+
+/// Whether the password sent with HTTP basic authentication is `expected`, compared in constant time so response times don't tell how
+/// much of a guess was right.
+pub fn authorized(headers: &HeaderMap, expected: &str) -> bool {
+	basic_auth_password(headers).is_some_and(|password| bool::from(password.as_bytes().ct_eq(expected.as_bytes())))
+}
+
+/// The password sent with HTTP basic authentication. The user name is ignored, as it always has been by the exporter.
+fn basic_auth_password(headers: &HeaderMap) -> Option<String> {
+	let credentials = headers.get(AUTHORIZATION)?.to_str().ok()?.strip_prefix("Basic ")?;
+	let credentials = String::from_utf8(STANDARD.decode(credentials).ok()?).ok()?;
+	Some(credentials.split_once(':')?.1.to_string())
+}
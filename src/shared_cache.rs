@@ -0,0 +1,248 @@
+//! Sharing fetched data between exporter replicas through Redis (or a compatible server such as KeyDB), so a UPS's NIS server is polled
+//! once per wait time instead of once per replica.
+
+use std::{
+	collections::HashMap,
+	io::{self, ErrorKind},
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::{
+	io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+	net::TcpStream,
+	time::timeout,
+};
+
+/// How long a Redis command may take before the shared cache is skipped for this fetch.
+const COMMAND_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(default)]
+pub struct SharedCacheOptions {
+	/// `host:port` of the Redis server.
+	pub address: String,
+	/// Password sent with `AUTH`, if the server requires one.
+	#[serde(skip_serializing)]
+	pub password: Option<String>,
+	/// Prefix of the keys, which end with the host's `address:port`.
+	pub key_prefix: String,
+}
+
+impl Default for SharedCacheOptions {
+	fn default() -> Self {
+		Self {
+			address: "127.0.0.1:6379".to_string(),
+			password: None,
+			key_prefix: "apcupsd_exporter:".to_string(),
+		}
+	}
+}
+
+#[derive(Deserialize, Serialize)]
+struct Entry {
+	fetched_at_seconds: f64,
+	data: HashMap<String, String>,
+}
+
+/// The shared cache entry of one host.
+pub struct SharedCache {
+	options: SharedCacheOptions,
+	key: String,
+}
+
+impl SharedCache {
+	/// Create access to the entry of the host at `host` (its `address:port`).
+	pub fn new(options: SharedCacheOptions, host: &str) -> Self {
+		let key = format!("{}{host}", options.key_prefix);
+		Self { options, key }
+	}
+
+	/// Data fetched by any replica at most `max_age` ago, with when it was fetched. Failures are logged and treated as a cache miss.
+	pub async fn get(&self, max_age: Duration) -> Option<(SystemTime, HashMap<String, String>)> {
+		let entry = match self.command(&["GET", &self.key]).await {
+			Ok(entry) => entry?,
+			Err(e) => {
+				crate::syslog::error(format!("Failed to read {} from shared cache: {e}", self.key));
+				return None;
+			},
+		};
+		let entry: Entry = serde_json::from_str(&entry).ok()?;
+		let fetched_at = UNIX_EPOCH + Duration::try_from_secs_f64(entry.fetched_at_seconds).ok()?;
+		(fetched_at.elapsed().ok()? < max_age).then_some((fetched_at, entry.data))
+	}
+
+	/// Share data that was just fetched, expiring after `ttl`. Failures are logged.
+	pub async fn set(&self, data: &HashMap<String, String>, ttl: Duration) {
+		let entry = Entry {
+			fetched_at_seconds: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64(),
+			data: data.clone(),
+		};
+		let result = match serde_json::to_string(&entry) {
+			Ok(entry) => self.command(&["SET", &self.key, &entry, "PX", &ttl.as_millis().max(1).to_string()]).await,
+			Err(e) => Err(e.into()),
+		};
+		if let Err(e) = result {
+			crate::syslog::error(format!("Failed to write {} to shared cache: {e}", self.key));
+		}
+	}
+
+	/// Send a command on a new connection, authenticating first if configured, and return its reply as a string.
+	async fn command(&self, args: &[&str]) -> io::Result<Option<String>> {
+		timeout(COMMAND_TIMEOUT, async {
+			let mut stream = BufReader::new(TcpStream::connect(&self.options.address).await?);
+			if let Some(password) = &self.options.password {
+				stream.write_all(&encode_command(&["AUTH", password])).await?;
+				read_reply(&mut stream).await?;
+			}
+			stream.write_all(&encode_command(args)).await?;
+			read_reply(&mut stream).await
+		})
+		.await?
+	}
+}
+
+/// Encode a command as a RESP array of bulk strings.
+fn encode_command(args: &[&str]) -> Vec<u8> {
+	let mut command = format!("*{}\r\n", args.len()).into_bytes();
+	for arg in args {
+		command.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+		command.extend_from_slice(arg.as_bytes());
+		command.extend_from_slice(b"\r\n");
+	}
+	command
+}
+
+/// Read a simple string, integer, or bulk string reply, returning `None` for a null bulk string.
+async fn read_reply(stream: &mut (impl AsyncBufRead + Unpin)) -> io::Result<Option<String>> {
+	let mut line = String::new();
+	stream.read_line(&mut line).await?;
+	let line = line.trim_end_matches("\r\n");
+	match line.split_at_checked(1) {
+		Some(("+" | ":", value)) => Ok(Some(value.to_string())),
+		Some(("-", error)) => Err(io::Error::other(error.to_string())),
+		Some(("$", "-1")) => Ok(None),
+		Some(("$", length)) => {
+			let length: usize = length.parse().map_err(|_| io::Error::new(ErrorKind::InvalidData, "invalid bulk string length"))?;
+			let mut value = vec![0; length + 2];
+			stream.read_exact(&mut value).await?;
+			value.truncate(length);
+			String::from_utf8(value).map(Some).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+		},
+		_ => Err(io::Error::new(ErrorKind::InvalidData, format!("unexpected reply \"{line}\""))),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{Arc, Mutex};
+
+	use tokio::net::TcpListener;
+
+	use super::*;
+
+	#[test]
+	fn test_encode_command() {
+		assert_eq!(encode_command(&["GET", "key"]), b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n");
+		assert_eq!(encode_command(&["SET", "", "a\r\nb"]), b"*3\r\n$3\r\nSET\r\n$0\r\n\r\n$4\r\na\r\nb\r\n");
+	}
+
+	#[tokio::test]
+	async fn test_read_reply() {
+		assert_eq!(read_reply(&mut &b"+OK\r\n"[..]).await.unwrap(), Some("OK".to_string()));
+		assert_eq!(read_reply(&mut &b":42\r\n"[..]).await.unwrap(), Some("42".to_string()));
+		assert_eq!(read_reply(&mut &b"$-1\r\n"[..]).await.unwrap(), None);
+		assert_eq!(read_reply(&mut &b"$4\r\na\r\nb\r\n"[..]).await.unwrap(), Some("a\r\nb".to_string()));
+		assert_eq!(
+			read_reply(&mut &b"-NOAUTH Authentication required.\r\n"[..]).await.unwrap_err().to_string(),
+			"NOAUTH Authentication required."
+		);
+		assert_eq!(read_reply(&mut &b"*1\r\n"[..]).await.unwrap_err().kind(), ErrorKind::InvalidData);
+		assert!(read_reply(&mut &b"$5\r\nab\r\n"[..]).await.is_err());
+	}
+
+	/// Serve `GET` and `SET` like a Redis server requiring `password`, returning its address and every command it received.
+	async fn serve(password: &'static str) -> (String, Arc<Mutex<Vec<Vec<String>>>>) {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let address = listener.local_addr().unwrap().to_string();
+		let commands = Arc::new(Mutex::new(Vec::new()));
+		let received = commands.clone();
+		tokio::spawn(async move {
+			let mut values = HashMap::<String, String>::new();
+			while let Ok((stream, _)) = listener.accept().await {
+				let mut stream = BufReader::new(stream);
+				let mut authenticated = false;
+				while let Ok(Some(command)) = read_command(&mut stream).await {
+					received.lock().unwrap().push(command.clone());
+					let reply = match command.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+						["AUTH", given] if *given == password => {
+							authenticated = true;
+							"+OK\r\n".to_string()
+						},
+						["AUTH", _] => "-WRONGPASS invalid password\r\n".to_string(),
+						_ if !authenticated => "-NOAUTH Authentication required.\r\n".to_string(),
+						["GET", key] => match values.get(*key) {
+							Some(value) => format!("${}\r\n{value}\r\n", value.len()),
+							None => "$-1\r\n".to_string(),
+						},
+						["SET", key, value, ..] => {
+							values.insert(key.to_string(), value.to_string());
+							"+OK\r\n".to_string()
+						},
+						_ => "-ERR unknown command\r\n".to_string(),
+					};
+					stream.write_all(reply.as_bytes()).await.unwrap();
+				}
+			}
+		});
+		(address, commands)
+	}
+
+	/// Read a command sent as a RESP array of bulk strings, or `None` once the connection is closed.
+	async fn read_command(stream: &mut BufReader<TcpStream>) -> io::Result<Option<Vec<String>>> {
+		let mut line = String::new();
+		if stream.read_line(&mut line).await? == 0 {
+			return Ok(None);
+		}
+		let count: usize = line.trim_end().trim_start_matches('*').parse().unwrap();
+		let mut command = Vec::with_capacity(count);
+		for _ in 0..count {
+			command.push(read_reply(stream).await?.unwrap());
+		}
+		Ok(Some(command))
+	}
+
+	#[tokio::test]
+	async fn test_auth() {
+		let (address, commands) = serve("secret").await;
+		let options = SharedCacheOptions {
+			address,
+			password: Some("secret".to_string()),
+			..Default::default()
+		};
+		let cache = SharedCache::new(options.clone(), "ups:3551");
+		let data = HashMap::from([("STATUS".to_string(), "ONLINE".to_string())]);
+		assert!(cache.get(Duration::from_secs(60)).await.is_none());
+		cache.set(&data, Duration::from_secs(60)).await;
+		let (_, cached) = cache.get(Duration::from_secs(60)).await.unwrap();
+		assert_eq!(cached, data);
+		{
+			let commands = commands.lock().unwrap();
+			assert_eq!(commands.len(), 6);
+			assert!(commands.chunks(2).all(|pair| pair[0] == ["AUTH", "secret"]));
+			assert_eq!(commands[1], ["GET", "apcupsd_exporter:ups:3551"]);
+			assert_eq!(commands[3][..2], ["SET", "apcupsd_exporter:ups:3551"]);
+			assert_eq!(commands[3][3..], ["PX", "60000"]);
+		}
+
+		// A wrong or missing password is a cache miss, not a failed fetch.
+		let wrong_password = SharedCacheOptions {
+			password: Some("wrong".to_string()),
+			..options.clone()
+		};
+		assert!(SharedCache::new(wrong_password, "ups:3551").get(Duration::from_secs(60)).await.is_none());
+		let no_password = SharedCacheOptions { password: None, ..options };
+		assert!(SharedCache::new(no_password, "ups:3551").get(Duration::from_secs(60)).await.is_none());
+	}
+}
@@ -0,0 +1,168 @@
+//! Synthetic apcupsd status output following a scripted outage, served by the mock NIS server to check alert rules and dashboards end to
+//! end without pulling the plug on a real UPS.
+
+use std::time::Instant;
+
+use chrono::{DateTime, Local, TimeDelta};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Nominal battery voltage of the simulated UPS.
+const NOMINAL_BATTERY_VOLTS: f64 = 24.;
+/// Charge below which the simulated UPS reports a low battery.
+const LOW_BATTERY_PERCENT: f64 = 10.;
+
+#[derive(Clone, Deserialize, JsonSchema, Serialize)]
+#[serde(default)]
+pub struct Scenario {
+	/// Seconds after starting until line power fails.
+	pub power_failure_after_seconds: f64,
+	/// Seconds line power stays out.
+	pub power_failure_seconds: f64,
+	/// Seconds after starting until apcupsd loses communication with the UPS, if ever.
+	pub comm_loss_after_seconds: Option<f64>,
+	/// Seconds communication stays lost.
+	pub comm_loss_seconds: f64,
+	/// Runtime on a full battery at `load_percent`.
+	pub full_runtime_minutes: f64,
+	/// Percentage of battery charge regained per minute on line power.
+	pub recharge_percent_per_minute: f64,
+	pub load_percent: f64,
+	/// Nominal line voltage, which is reported with a little noise.
+	pub line_volts: f64,
+	/// Seconds after which the scenario starts over, if ever.
+	pub repeat_after_seconds: Option<f64>,
+}
+
+impl Default for Scenario {
+	fn default() -> Self {
+		Self {
+			power_failure_after_seconds: 60.,
+			power_failure_seconds: 300.,
+			comm_loss_after_seconds: Some(600.),
+			comm_loss_seconds: 60.,
+			full_runtime_minutes: 40.,
+			recharge_percent_per_minute: 5.,
+			load_percent: 25.,
+			line_volts: 120.,
+			repeat_after_seconds: Some(1200.),
+		}
+	}
+}
+
+/// A UPS going through a [`Scenario`], starting when it's created.
+pub struct SimulatedUps {
+	scenario: Scenario,
+	started: Instant,
+	started_at: DateTime<Local>,
+}
+
+impl SimulatedUps {
+	pub fn new(scenario: Scenario) -> Self {
+		Self {
+			scenario,
+			started: Instant::now(),
+			started_at: Local::now(),
+		}
+	}
+
+	/// Status output as of now, in the format of `apcaccess status`.
+	pub fn status(&self) -> String {
+		let elapsed = self.started.elapsed().as_secs_f64();
+		let scenario = &self.scenario;
+		let (cycle, t) = match scenario.repeat_after_seconds.filter(|repeat| *repeat > 0.) {
+			Some(repeat) => ((elapsed / repeat).floor(), elapsed % repeat),
+			None => (0., elapsed),
+		};
+		let now = Local::now();
+		let timestamp = |offset_seconds: f64| {
+			let time = now - TimeDelta::milliseconds((offset_seconds * 1000.) as i64);
+			time.format("%Y-%m-%d %H:%M:%S %z").to_string()
+		};
+		let failure_start = scenario.power_failure_after_seconds;
+		let failure_end = failure_start + scenario.power_failure_seconds;
+		let seconds_on_battery = (t.min(failure_end) - failure_start).max(0.);
+		let on_battery = t >= failure_start && t < failure_end;
+		let transfers = cycle + f64::from(t >= failure_start);
+		let full_runtime_seconds = scenario.full_runtime_minutes * 60.;
+		let charge_at_restore = (100. - 100. * seconds_on_battery / full_runtime_seconds).max(0.);
+		let charge = if t < failure_end {
+			charge_at_restore
+		} else {
+			(charge_at_restore + scenario.recharge_percent_per_minute * (t - failure_end) / 60.).min(100.)
+		};
+		let comm_lost = scenario.comm_loss_after_seconds.is_some_and(|start| t >= start && t < start + scenario.comm_loss_seconds);
+
+		let mut status = vec![
+			("APC", "001,036,0870".to_string()),
+			("DATE", timestamp(0.)),
+			("HOSTNAME", "simulated".to_string()),
+			("VERSION", "3.14.14 (31 May 2016) simulated".to_string()),
+			("UPSNAME", "simulated".to_string()),
+			("CABLE", "USB Cable".to_string()),
+			("DRIVER", "USB UPS Driver".to_string()),
+			("UPSMODE", "Stand Alone".to_string()),
+			("STARTTIME", self.started_at.format("%Y-%m-%d %H:%M:%S %z").to_string()),
+			("MODEL", "Simulated UPS".to_string()),
+		];
+		if comm_lost {
+			status.extend([("STATUS", "COMMLOST".to_string()), ("STATFLAG", "0x05000100".to_string())]);
+		} else {
+			let battery_low = charge < LOW_BATTERY_PERCENT;
+			let (status_text, mut status_flag) = if on_battery { ("ONBATT", 0x10) } else { ("ONLINE", 0x08) };
+			let status_text = if battery_low {
+				format!("{status_text} LOWBATT")
+			} else {
+				status_text.to_string()
+			};
+			if battery_low {
+				status_flag |= 0x40;
+			}
+			// A little noise so graphs don't look flat.
+			let noise = (elapsed * 0.7).sin() * 0.8 + (elapsed * 0.13).sin() * 0.5;
+			let line_volts = if on_battery { 0. } else { scenario.line_volts + noise };
+			// Battery voltage drops faster as the battery empties, and sags under load while discharging.
+			let sag = if on_battery { 0.6 } else { 0. };
+			let battery_volts = NOMINAL_BATTERY_VOLTS * (0.88 + 0.22 * (charge / 100.).sqrt()) - sag + noise.abs() * 0.05;
+			let time_left_minutes = scenario.full_runtime_minutes * charge / 100.;
+			let last_transfer = if transfers > 0. {
+				"Low line voltage"
+			} else {
+				"No transfers since turnon"
+			};
+			let cumulative_on_battery = cycle * scenario.power_failure_seconds + seconds_on_battery;
+			status.extend([
+				("STATUS", status_text),
+				("LINEV", format!("{line_volts:.1} Volts")),
+				("LOADPCT", format!("{:.1} Percent", scenario.load_percent)),
+				("BCHARGE", format!("{charge:.1} Percent")),
+				("TIMELEFT", format!("{time_left_minutes:.1} Minutes")),
+				("MBATTCHG", "5 Percent".to_string()),
+				("MINTIMEL", "3 Minutes".to_string()),
+				("MAXTIME", "0 Seconds".to_string()),
+				("LOTRANS", format!("{:.1} Volts", scenario.line_volts * 0.75)),
+				("HITRANS", format!("{:.1} Volts", scenario.line_volts * 1.15)),
+				("BATTV", format!("{battery_volts:.1} Volts")),
+				("LASTXFER", last_transfer.to_string()),
+				("NUMXFERS", format!("{transfers}")),
+				("TONBATT", format!("{:.0} Seconds", if on_battery { seconds_on_battery } else { 0. })),
+				("CUMONBATT", format!("{cumulative_on_battery:.0} Seconds")),
+				("STATFLAG", format!("0x{:08X}", 0x05000000 | status_flag)),
+			]);
+			if t >= failure_start {
+				status.push(("XONBATT", timestamp(t - failure_start)));
+				if !on_battery {
+					status.push(("XOFFBATT", timestamp(t - failure_end)));
+				}
+			}
+		}
+		status.extend([
+			("SERIALNO", "SIMULATED0001".to_string()),
+			("NOMINV", format!("{:.0} Volts", scenario.line_volts)),
+			("NOMBATTV", format!("{NOMINAL_BATTERY_VOLTS:.1} Volts")),
+			("NOMPOWER", "900 Watts".to_string()),
+			("END APC", timestamp(0.)),
+		]);
+		status.into_iter().map(|(key, value)| format!("{key:<9}: {value}\n")).collect()
+	}
+}
@@ -0,0 +1,65 @@
+//! Rollups across every host in a scrape, so facility-level alerts (total load, shortest runtime, UPSes on battery) don't need recording
+//! rules on every Prometheus server.
+
+use std::collections::HashMap;
+
+use prometheus_exporter_base::{MetricType, PrometheusInstance, PrometheusMetric};
+
+use crate::{parse_metric, MetricParseConfig, MetricParseType, PercentScale};
+
+/// Render the rollups of the data of the hosts in a scrape that data could be fetched from. The load of hosts without `NOMPOWER` can't
+/// be known in watts, so it's left out of the total.
+pub fn render_rollups<'a>(hosts_data: impl Iterator<Item = &'a HashMap<String, String>>) -> String {
+	let mut load_watts = 0.;
+	let mut min_time_left = None::<f64>;
+	let mut on_battery = 0;
+	for apcupsd_data in hosts_data {
+		let value = |key: &str, parse_type: MetricParseType| {
+			let parse_config = MetricParseConfig {
+				percent_scale: PercentScale::Fraction,
+				..parse_type.into()
+			};
+			parse_metric(apcupsd_data.get(key)?.clone(), parse_config).ok().flatten()
+		};
+		if let (Some(load), Some(nominal_power)) = (value("LOADPCT", MetricParseType::Percentage), value("NOMPOWER", MetricParseType::Power)) {
+			load_watts += load * nominal_power;
+		}
+		if let Some(time_left) = value("TIMELEFT", MetricParseType::Duration) {
+			min_time_left = Some(min_time_left.map_or(time_left, |min_time_left| min_time_left.min(time_left)));
+		}
+		if apcupsd_data.get("STATUS").is_some_and(|status| status.split_whitespace().any(|flag| flag == "ONBATT")) {
+			on_battery += 1;
+		}
+	}
+
+	let mut rendered = String::new();
+	let gauges = [
+		(
+			"apcupsd_site_load_watts",
+			"Total load of the UPSes in this scrape that report their nominal power, in watts.",
+			Some(load_watts),
+		),
+		(
+			"apcupsd_site_battery_time_left_min_seconds",
+			"Shortest runtime left on battery of the UPSes in this scrape.",
+			min_time_left,
+		),
+		(
+			"apcupsd_site_on_battery_count",
+			"Number of UPSes in this scrape running on battery.",
+			Some(f64::from(on_battery)),
+		),
+	];
+	for (name, help, value) in gauges {
+		if let Some(value) = value {
+			rendered += &PrometheusMetric::build()
+				.with_name(name)
+				.with_help(help)
+				.with_metric_type(MetricType::Gauge)
+				.build()
+				.render_and_append_instance(&PrometheusInstance::new().with_value(value))
+				.render();
+		}
+	}
+	rendered
+}
@@ -0,0 +1,80 @@
+//! Reaching NIS servers that only listen on the loopback interface of a remote host by forwarding a local port over SSH, using the
+//! system's `ssh` client.
+
+use std::{
+	net::{SocketAddr, TcpListener},
+	path::PathBuf,
+	process::Stdio,
+	time::Duration,
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+/// How long to wait before restarting `ssh` when the tunnel goes down.
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(default)]
+pub struct SshTunnelOptions {
+	/// Host to connect to with SSH.
+	pub host: String,
+	pub port: u16,
+	/// User to log in as; default from the SSH configuration.
+	pub user: Option<String>,
+	/// Private key to authenticate with; default from the SSH configuration.
+	pub key: Option<PathBuf>,
+}
+
+impl Default for SshTunnelOptions {
+	fn default() -> Self {
+		Self {
+			host: String::new(),
+			port: 22,
+			user: None,
+			key: None,
+		}
+	}
+}
+
+/// Keep an SSH connection forwarding a local port to `target`, as seen from the SSH host, running in the background, returning the local
+/// address to connect to instead of `target`.
+pub fn spawn(options: &SshTunnelOptions, target: (String, u16)) -> std::io::Result<SocketAddr> {
+	// Let the OS pick a free port for ssh to listen on.
+	let local_address = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+	let destination = match &options.user {
+		Some(user) => format!("{user}@{}", options.host),
+		None => options.host.clone(),
+	};
+	let mut command = Command::new("ssh");
+	command
+		.args([
+			"-N",
+			"-o",
+			"BatchMode=yes",
+			"-o",
+			"ExitOnForwardFailure=yes",
+			"-o",
+			"ServerAliveInterval=15",
+		])
+		.args(["-p", &options.port.to_string()])
+		.args(["-L", &format!("{local_address}:{}", crate::host_port(&target.0, target.1))])
+		.stdin(Stdio::null())
+		.kill_on_drop(true);
+	if let Some(key) = &options.key {
+		command.arg("-i").arg(key);
+	}
+	command.arg(destination);
+	let target = crate::host_port(&target.0, target.1);
+	tokio::spawn(async move {
+		loop {
+			match command.status().await {
+				Ok(status) => crate::syslog::error(format!("SSH tunnel to {target} exited with {status}, restarting")),
+				Err(e) => crate::syslog::error(format!("Failed to start SSH tunnel to {target}: {e}")),
+			}
+			tokio::time::sleep(RESTART_DELAY).await;
+		}
+	});
+	Ok(local_address)
+}
@@ -0,0 +1,338 @@
+//! Statistics accumulated on every poll of apcupsd, so changes that happen between scrapes aren't lost.
+
+use std::{
+	collections::{BTreeMap, HashMap, VecDeque},
+	time::{Duration, Instant},
+};
+
+use prometheus_exporter_base::{MetricType, PrometheusMetric};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{parse_metric, prometheus_instance_with_labels, round_to_precision, MetricParseConfig, MetricParseType, PercentScale};
+
+/// Keys aggregated between scrapes, with how to parse them and the name of their regular metric.
+const AGGREGATED_KEYS: [(&str, MetricParseType, &str, &str); 4] = [
+	("LINEV", MetricParseType::Voltage, "apcupsd_line_volts", "input line voltage"),
+	(
+		"LOADPCT",
+		MetricParseType::Percentage,
+		"apcupsd_ups_load_percent",
+		"percentage of UPS load capacity used",
+	),
+	(
+		"BCHARGE",
+		MetricParseType::Percentage,
+		"apcupsd_battery_charge_percent",
+		"battery capacity charge percentage",
+	),
+	(
+		"ITEMP",
+		MetricParseType::Temperature,
+		"apcupsd_internal_temperature_celsius",
+		"UPS internal temperature in degrees Celsius",
+	),
+];
+
+#[derive(Clone, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(default)]
+pub struct PollStatisticsOptions {
+	/// Bucket upper bounds for `apcupsd_line_volts_histogram`, which isn't exported if this is empty.
+	pub line_volts_buckets: Vec<f64>,
+	/// Bucket upper bounds for `apcupsd_line_frequency_hertz_histogram`, which isn't exported if this is empty.
+	pub line_frequency_buckets: Vec<f64>,
+	/// Export `_min`, `_max`, and `_avg` of line voltage, load, battery charge, and internal temperature over the polls since the last scrape.
+	pub aggregates: bool,
+	/// Seconds of polls `apcupsd_battery_charge_change_per_minute` is computed over, from the first to the last charge in them.
+	pub charge_rate_window_seconds: f64,
+}
+
+impl Default for PollStatisticsOptions {
+	fn default() -> Self {
+		Self {
+			line_volts_buckets: Vec::new(),
+			line_frequency_buckets: Vec::new(),
+			aggregates: false,
+			charge_rate_window_seconds: 300.,
+		}
+	}
+}
+
+#[derive(Clone)]
+pub struct PollStatistics {
+	line_volts: Option<Histogram>,
+	line_frequency: Option<Histogram>,
+	/// Aggregates since the last scrape for each of [`AGGREGATED_KEYS`], if enabled.
+	aggregates: Option<[Option<Aggregate>; AGGREGATED_KEYS.len()]>,
+	percent_scale: PercentScale,
+	/// `NUMXFERS` at the last poll, to detect new transfers.
+	last_transfer_count: Option<u64>,
+	/// Transfers seen while polling, by the category of their `LASTXFER` reason.
+	transfers_by_reason: BTreeMap<&'static str, u64>,
+	/// `BCHARGE` at each poll within `charge_rate_window`, oldest first.
+	charges: VecDeque<(Instant, f64)>,
+	charge_rate_window: Duration,
+	/// Whether `STATUS` had `ONLINE` at the last poll.
+	online: bool,
+}
+
+impl PollStatistics {
+	pub fn new(options: &PollStatisticsOptions, percent_scale: PercentScale) -> Self {
+		Self {
+			line_volts: Histogram::new(&options.line_volts_buckets),
+			line_frequency: Histogram::new(&options.line_frequency_buckets),
+			aggregates: options.aggregates.then_some([None; AGGREGATED_KEYS.len()]),
+			percent_scale,
+			last_transfer_count: None,
+			transfers_by_reason: BTreeMap::new(),
+			charges: VecDeque::new(),
+			charge_rate_window: Duration::try_from_secs_f64(options.charge_rate_window_seconds).unwrap_or_default(),
+			online: false,
+		}
+	}
+
+	/// Start aggregating again for the next scrape.
+	pub fn reset_aggregates(&mut self) {
+		if let Some(aggregates) = &mut self.aggregates {
+			*aggregates = [None; AGGREGATED_KEYS.len()];
+		}
+	}
+
+	/// Record data from a successful poll.
+	pub fn observe(&mut self, apcupsd_data: &HashMap<String, String>) {
+		let percent_scale = self.percent_scale;
+		if let (Some(histogram), Some(value)) = (
+			&mut self.line_volts,
+			parsed_value(apcupsd_data, "LINEV", MetricParseType::Voltage, percent_scale),
+		) {
+			histogram.observe(value);
+		}
+		if let (Some(histogram), Some(value)) = (
+			&mut self.line_frequency,
+			parsed_value(apcupsd_data, "LINEFREQ", MetricParseType::Frequency, percent_scale),
+		) {
+			histogram.observe(value);
+		}
+		if let Some(aggregates) = &mut self.aggregates {
+			for (aggregate, (key, parse_type, _, _)) in aggregates.iter_mut().zip(AGGREGATED_KEYS) {
+				if let Some(value) = parsed_value(apcupsd_data, key, parse_type, percent_scale) {
+					aggregate.get_or_insert(Aggregate::new(value)).observe(value);
+				}
+			}
+		}
+		if let Some(transfer_count) = apcupsd_data.get("NUMXFERS").and_then(|count| count.parse::<u64>().ok()) {
+			// NUMXFERS starts over when apcupsd restarts, in which case the transfers since then can't be attributed to a reason.
+			if let Some(new_transfers) = self.last_transfer_count.and_then(|last| transfer_count.checked_sub(last)).filter(|new| *new > 0) {
+				let reason = apcupsd_data.get("LASTXFER").map_or("other", |reason| transfer_reason(reason));
+				*self.transfers_by_reason.entry(reason).or_default() += new_transfers;
+			}
+			self.last_transfer_count = Some(transfer_count);
+		}
+		if let Some(status) = apcupsd_data.get("STATUS") {
+			self.online = status.split_whitespace().any(|flag| flag == "ONLINE");
+		}
+		if let Some(charge) = parsed_value(apcupsd_data, "BCHARGE", MetricParseType::Percentage, percent_scale) {
+			let now = Instant::now();
+			self.charges.push_back((now, charge));
+			while self.charges.front().is_some_and(|(polled_at, _)| now.duration_since(*polled_at) > self.charge_rate_window) {
+				self.charges.pop_front();
+			}
+		}
+	}
+
+	/// How fast the battery charge changed over the polls in the window, once they span at least half of it, since a rate over a few
+	/// seconds would be mostly the rounding of `BCHARGE`.
+	fn charge_change_per_minute(&self) -> Option<f64> {
+		let ((first_polled_at, first_charge), (last_polled_at, last_charge)) = (self.charges.front()?, self.charges.back()?);
+		let span = last_polled_at.duration_since(*first_polled_at);
+		(!span.is_zero() && span >= self.charge_rate_window / 2).then(|| (last_charge - first_charge) / span.as_secs_f64() * 60.)
+	}
+
+	/// How long the battery will take to charge fully at the rate it's charging at while online, which overestimates a little since
+	/// charging slows down near full.
+	fn time_to_full(&self, change_per_minute: f64) -> Option<f64> {
+		let full = self.percent_scale.full();
+		let (_, charge) = self.charges.back()?;
+		(self.online && *charge < full && change_per_minute > 0.).then(|| (full - charge) / change_per_minute * 60.)
+	}
+
+	pub fn render(&self, labels: &[(String, String)], precision: Option<u32>) -> String {
+		let mut rendered = String::new();
+		if let Some(histogram) = &self.line_volts {
+			rendered += &histogram.render(
+				"apcupsd_line_volts_histogram",
+				"Input line voltage observed on each poll of apcupsd.",
+				labels,
+			);
+		}
+		if let Some(histogram) = &self.line_frequency {
+			rendered += &histogram.render(
+				"apcupsd_line_frequency_hertz_histogram",
+				"Line frequency in Hertz observed on each poll of apcupsd.",
+				labels,
+			);
+		}
+		if !self.transfers_by_reason.is_empty() {
+			let mut metric = PrometheusMetric::build()
+				.with_name("apcupsd_transfers_by_reason_total")
+				.with_help("Transfers to battery seen while polling apcupsd, by the category of the reason reported in LASTXFER.")
+				.with_metric_type(MetricType::Counter)
+				.build();
+			for (&reason, &count) in &self.transfers_by_reason {
+				metric.render_and_append_instance(&prometheus_instance_with_labels(labels).with_label("reason", reason).with_value(count));
+			}
+			rendered += &metric.render();
+		}
+		if let Some(change_per_minute) = self.charge_change_per_minute() {
+			rendered += &PrometheusMetric::build()
+				.with_name("apcupsd_battery_charge_change_per_minute")
+				.with_help("Change of the battery charge per minute over recent polls, in the unit of the battery charge metric.")
+				.with_metric_type(MetricType::Gauge)
+				.build()
+				.render_and_append_instance(&prometheus_instance_with_labels(labels).with_value(round_to_precision(change_per_minute, precision)))
+				.render();
+			if let Some(time_to_full) = self.time_to_full(change_per_minute) {
+				rendered += &PrometheusMetric::build()
+					.with_name("apcupsd_battery_time_to_full_seconds")
+					.with_help("Estimated time until the battery is fully charged at its recent rate of charge, while online.")
+					.with_metric_type(MetricType::Gauge)
+					.build()
+					.render_and_append_instance(&prometheus_instance_with_labels(labels).with_value(round_to_precision(time_to_full, precision)))
+					.render();
+			}
+		}
+		for (aggregate, (_, parse_type, name, description)) in self.aggregates.iter().flatten().zip(AGGREGATED_KEYS) {
+			if let Some(aggregate) = aggregate {
+				let is_percentage = matches!(parse_type, MetricParseType::Percentage);
+				let name = if is_percentage {
+					self.percent_scale.metric_name(name)
+				} else {
+					name.to_string()
+				};
+				for (suffix, statistic, value) in [
+					("min", "Minimum", aggregate.min),
+					("max", "Maximum", aggregate.max),
+					("avg", "Average", aggregate.sum / aggregate.count as f64),
+				] {
+					let mut help = format!("{statistic} {description} over the polls since the last scrape.");
+					if is_percentage {
+						help = self.percent_scale.help(&help);
+					}
+					rendered += &PrometheusMetric::build()
+						.with_name(&format!("{name}_{suffix}"))
+						.with_help(&help)
+						.with_metric_type(MetricType::Gauge)
+						.build()
+						.render_and_append_instance(&prometheus_instance_with_labels(labels).with_value(round_to_precision(value, precision)))
+						.render();
+				}
+			}
+		}
+		rendered
+	}
+}
+
+#[derive(Clone, Copy)]
+struct Aggregate {
+	min: f64,
+	max: f64,
+	sum: f64,
+	count: u64,
+}
+
+impl Aggregate {
+	pub fn new(value: f64) -> Self {
+		Self {
+			min: value,
+			max: value,
+			sum: 0.,
+			count: 0,
+		}
+	}
+
+	pub fn observe(&mut self, value: f64) {
+		self.min = self.min.min(value);
+		self.max = self.max.max(value);
+		self.sum += value;
+		self.count += 1;
+	}
+}
+
+/// Classify the transfer reason reported in `LASTXFER`, which differs between drivers and firmware versions.
+fn transfer_reason(reason: &str) -> &'static str {
+	let reason = reason.to_lowercase();
+	if reason.contains("self test") || reason.contains("selftest") {
+		"self_test"
+	} else if reason.contains("high line") {
+		"high_voltage"
+	} else if reason.contains("low line") {
+		"low_voltage"
+	} else if reason.contains("notch") || reason.contains("spike") {
+		"notch_or_spike"
+	} else if reason.contains("voltage change") {
+		"voltage_change"
+	} else if reason.contains("frequency") {
+		"frequency"
+	} else if reason.contains("ripple") {
+		"ripple"
+	} else if reason.contains("software") {
+		"software"
+	} else {
+		"other"
+	}
+}
+
+fn parsed_value(apcupsd_data: &HashMap<String, String>, key: &str, parse_type: MetricParseType, percent_scale: PercentScale) -> Option<f64> {
+	let parse_config = MetricParseConfig {
+		percent_scale,
+		..parse_type.into()
+	};
+	parse_metric(apcupsd_data.get(key)?.clone(), parse_config).ok().flatten()
+}
+
+#[derive(Clone)]
+struct Histogram {
+	/// Upper bounds and cumulative counts, not including the implicit `+Inf` bucket.
+	buckets: Vec<(f64, u64)>,
+	count: u64,
+	sum: f64,
+}
+
+impl Histogram {
+	/// Create a histogram with the given bucket upper bounds, or `None` if there aren't any.
+	pub fn new(upper_bounds: &[f64]) -> Option<Self> {
+		let mut upper_bounds = upper_bounds.iter().copied().filter(|bound| bound.is_finite()).collect::<Vec<_>>();
+		if upper_bounds.is_empty() {
+			return None;
+		}
+		upper_bounds.sort_by(f64::total_cmp);
+		upper_bounds.dedup();
+		Some(Self {
+			buckets: upper_bounds.into_iter().map(|bound| (bound, 0)).collect(),
+			count: 0,
+			sum: 0.,
+		})
+	}
+
+	pub fn observe(&mut self, value: f64) {
+		for (bound, count) in &mut self.buckets {
+			if value <= *bound {
+				*count += 1;
+			}
+		}
+		self.count += 1;
+		self.sum += value;
+	}
+
+	pub fn render(&self, name: &str, help: &str, labels: &[(String, String)]) -> String {
+		let labels = labels.iter().map(|(key, value)| format!("{key}=\"{value}\",")).collect::<String>();
+		let mut rendered = format!("# HELP {name} {help}\n# TYPE {name} histogram\n");
+		for (bound, count) in &self.buckets {
+			rendered += &format!("{name}_bucket{{{labels}le=\"{bound}\"}} {count}\n");
+		}
+		rendered += &format!("{name}_bucket{{{labels}le=\"+Inf\"}} {}\n", self.count);
+		let labels = labels.trim_end_matches(',');
+		rendered += &format!("{name}_sum{{{labels}}} {}\n{name}_count{{{labels}}} {}\n", self.sum, self.count);
+		rendered
+	}
+}
@@ -0,0 +1,38 @@
+//! Reading the status file apcupsd writes every `STATTIME` seconds when `STATFILE` is configured, for machines where the NIS port is
+//! disabled by policy but the file can be shared read-only (e.g. over NFS).
+
+use std::{collections::HashMap, io, path::PathBuf, time::SystemTime};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::capture;
+
+#[derive(Clone, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(default)]
+pub struct StatusFileOptions {
+	/// The `STATFILE` apcupsd writes.
+	pub path: PathBuf,
+	/// Treat the file like an unreachable NIS server once it hasn't been written for this long, e.g. because apcupsd stopped. Should be
+	/// longer than `STATTIME`.
+	pub max_age_seconds: f64,
+}
+
+impl Default for StatusFileOptions {
+	fn default() -> Self {
+		Self {
+			path: PathBuf::from("/var/log/apcupsd.status"),
+			max_age_seconds: 300.,
+		}
+	}
+}
+
+/// The data in the status file, with when it was written.
+pub async fn read(options: &StatusFileOptions) -> Result<(SystemTime, HashMap<String, String>), io::ErrorKind> {
+	let modified = tokio::fs::metadata(&options.path).await.and_then(|metadata| metadata.modified()).map_err(|e| e.kind())?;
+	if modified.elapsed().unwrap_or_default().as_secs_f64() > options.max_age_seconds {
+		return Err(io::ErrorKind::TimedOut);
+	}
+	let status = tokio::fs::read_to_string(&options.path).await.map_err(|e| e.kind())?;
+	Ok((modified, capture::parse_status(&status)))
+}
@@ -0,0 +1,84 @@
+//! A status page with a card for each UPS, for a quick look without setting up Grafana.
+
+use std::{
+	collections::HashMap,
+	fmt::Write,
+	sync::{Arc, Mutex, PoisonError},
+};
+
+use crate::history::History;
+
+const SPARKLINE_WIDTH: f64 = 240.;
+const SPARKLINE_HEIGHT: f64 = 40.;
+
+/// Render the status page for the hosts with history, which the cards are drawn from.
+pub fn render(histories: &HashMap<String, Arc<Mutex<History>>>) -> String {
+	let mut slugs: Vec<_> = histories.keys().collect();
+	slugs.sort();
+	let mut page = String::from(concat!(
+		"<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><meta http-equiv=\"refresh\" content=\"30\"><title>apcupsd</title><style>",
+		"body{font-family:sans-serif;background:#f4f4f4;margin:1em}main{display:flex;flex-wrap:wrap;gap:1em}",
+		".card{background:#fff;border-radius:6px;padding:1em;width:260px;box-shadow:0 1px 3px #0003}h2{margin:0 0 .3em;font-size:1.1em}",
+		".status{font-weight:bold}.ok{color:#2a7a2a}.alert{color:#b02020}td{padding:0 .5em 0 0}",
+		"svg{display:block;margin-top:.5em}</style></head><body><h1>apcupsd</h1><main>\n",
+	));
+	for slug in slugs {
+		let history = histories[slug].lock().unwrap_or_else(PoisonError::into_inner);
+		let status = history.latest_status().unwrap_or("no data");
+		let class = if status.split_whitespace().any(|flag| flag == "ONLINE") {
+			"ok"
+		} else {
+			"alert"
+		};
+		let latest = |key| history.series(key).into_iter().flatten().last();
+		let _ = write!(
+			page,
+			"<div class=\"card\"><h2>{}</h2><div class=\"status {class}\">{}</div><table>",
+			escape(slug),
+			escape(status)
+		);
+		for (label, key, format) in [
+			("Charge", "BCHARGE", format_percent as fn(f64) -> String),
+			("Load", "LOADPCT", format_percent),
+			("Runtime", "TIMELEFT", format_minutes),
+		] {
+			let value = latest(key).map_or("-".to_string(), format);
+			let _ = write!(page, "<tr><td>{label}</td><td>{value}</td></tr>");
+		}
+		page += "</table>";
+		page += &sparkline(&history.series("BCHARGE"), "#2a7a2a");
+		page += &sparkline(&history.series("LOADPCT"), "#2050b0");
+		page += "<small>charge (green) and load (blue), 0-100%</small></div>\n";
+	}
+	page += "</main></body></html>\n";
+	page
+}
+
+fn format_percent(value: f64) -> String {
+	format!("{value:.0}%")
+}
+
+fn format_minutes(seconds: f64) -> String {
+	format!("{:.0} min", seconds / 60.)
+}
+
+/// An SVG line of percentages over time, with gaps left out.
+fn sparkline(values: &[Option<f64>], color: &str) -> String {
+	let step = SPARKLINE_WIDTH / values.len().saturating_sub(1).max(1) as f64;
+	let points: Vec<_> = values
+		.iter()
+		.enumerate()
+		.filter_map(|(index, value)| {
+			let y = SPARKLINE_HEIGHT - (*value)?.clamp(0., 100.) / 100. * SPARKLINE_HEIGHT;
+			Some(format!("{:.1},{y:.1}", index as f64 * step))
+		})
+		.collect();
+	format!(
+		"<svg width=\"{SPARKLINE_WIDTH}\" height=\"{SPARKLINE_HEIGHT}\"><polyline fill=\"none\" stroke=\"{color}\" points=\"{}\"/></svg>",
+		points.join(" ")
+	)
+}
+
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,154 @@
+//! Logging power events and errors to the local syslog daemon or the systemd journal besides stderr, so they end up next to apcupsd's own
+//! logs when reconstructing what happened during an outage.
+
+use std::fmt::Display;
+#[cfg(unix)]
+use std::{io, os::unix::net::UnixDatagram, sync::OnceLock};
+
+#[cfg(unix)]
+use chrono::Local;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Deserialize, JsonSchema, Serialize)]
+#[serde(default)]
+pub struct SyslogOptions {
+	/// Where messages are sent.
+	pub target: SyslogTarget,
+	/// Identifier (tag) messages are logged with.
+	pub identifier: String,
+	/// Facility of messages, `daemon`, `user`, or `local0` to `local7`.
+	pub facility: String,
+}
+
+impl Default for SyslogOptions {
+	fn default() -> Self {
+		Self {
+			target: SyslogTarget::default(),
+			identifier: "apcupsd_exporter".to_string(),
+			facility: "daemon".to_string(),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyslogTarget {
+	/// RFC 3164 messages to `/dev/log`.
+	#[default]
+	Syslog,
+	/// The journal's native protocol, with the host and event as fields (`UPS`, `POWER_EVENT`) that can be filtered on.
+	Journald,
+}
+
+#[derive(Clone, Copy)]
+pub enum Priority {
+	Error = 3,
+	Warning = 4,
+}
+
+#[cfg(unix)]
+struct Logger {
+	socket: UnixDatagram,
+	target: SyslogTarget,
+	identifier: String,
+	facility: u8,
+}
+
+#[cfg(unix)]
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Send messages logged from now on to the configured target as well.
+#[cfg(unix)]
+pub fn init(options: &SyslogOptions) -> Result<(), Box<dyn std::error::Error>> {
+	let facility = match options.facility.as_str() {
+		"user" => 1,
+		"daemon" => 3,
+		local => match local.strip_prefix("local").and_then(|number| number.parse::<u8>().ok()) {
+			Some(number @ 0..=7) => 16 + number,
+			_ => return Err(format!("unknown facility {local:?}").into()),
+		},
+	};
+	let socket = UnixDatagram::unbound()?;
+	match options.target {
+		SyslogTarget::Syslog => socket.connect("/dev/log")?,
+		SyslogTarget::Journald => socket.connect("/run/systemd/journal/socket")?,
+	}
+	let logger = Logger {
+		socket,
+		target: options.target,
+		identifier: options.identifier.clone(),
+		facility,
+	};
+	LOGGER.set(logger).map_err(|_| "already initialized")?;
+	Ok(())
+}
+
+/// There's no local syslog socket to log to on other platforms.
+#[cfg(not(unix))]
+pub fn init(_options: &SyslogOptions) -> Result<(), Box<dyn std::error::Error>> {
+	Err("syslog is only available on Unix".into())
+}
+
+/// Log an error to stderr and, if configured, syslog.
+pub fn error(message: impl Display) {
+	log(Priority::Error, &message.to_string(), &[]);
+}
+
+/// Log a warning to stderr and, if configured, syslog.
+pub fn warning(message: impl Display) {
+	log(Priority::Warning, &message.to_string(), &[]);
+}
+
+/// Log to stderr and, if configured, syslog, with `fields` as structured fields in the journal.
+#[cfg_attr(not(unix), allow(unused_variables))]
+pub fn log(priority: Priority, message: &str, fields: &[(&str, &str)]) {
+	eprintln!("{message}");
+	#[cfg(unix)]
+	if let Some(logger) = LOGGER.get() {
+		// Failing to log can't be logged, and stderr already has the message.
+		let _ = logger.send(priority, message, fields);
+	}
+}
+
+#[cfg(unix)]
+impl Logger {
+	fn send(&self, priority: Priority, message: &str, fields: &[(&str, &str)]) -> io::Result<usize> {
+		match self.target {
+			SyslogTarget::Syslog => {
+				let timestamp = Local::now().format("%b %e %H:%M:%S");
+				let pri = self.facility * 8 + priority as u8;
+				self.socket.send(format!("<{pri}>{timestamp} {}[{}]: {message}", self.identifier, std::process::id()).as_bytes())
+			},
+			SyslogTarget::Journald => {
+				let facility = self.facility.to_string();
+				let priority = (priority as u8).to_string();
+				let mut entry = Vec::new();
+				let common = [
+					("MESSAGE", message),
+					("PRIORITY", priority.as_str()),
+					("SYSLOG_FACILITY", facility.as_str()),
+					("SYSLOG_IDENTIFIER", self.identifier.as_str()),
+				];
+				for (name, value) in common.iter().chain(fields) {
+					append_field(&mut entry, name, value);
+				}
+				self.socket.send(&entry)
+			},
+		}
+	}
+}
+
+/// Append a field in the journal's native format, which needs a length prefix for values spanning lines.
+#[cfg(unix)]
+fn append_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+	entry.extend_from_slice(name.as_bytes());
+	if value.contains('\n') {
+		entry.push(b'\n');
+		entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+	} else {
+		entry.push(b'=');
+	}
+	entry.extend_from_slice(value.as_bytes());
+	entry.push(b'\n');
+}
@@ -0,0 +1,54 @@
+//! Exporting tracing spans of fetching and rendering with OTLP, so slow scrapes can be attributed to a host's network latency or to
+//! parsing and rendering.
+
+#[cfg(feature = "telemetry")]
+use opentelemetry::{global, trace::TracerProvider as _, KeyValue};
+#[cfg(feature = "telemetry")]
+use opentelemetry_otlp::WithExportConfig;
+#[cfg(feature = "telemetry")]
+use opentelemetry_sdk::{runtime, trace::TracerProvider, Resource};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "telemetry")]
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+#[cfg(feature = "telemetry")]
+use crate::built_info;
+
+#[derive(Clone, Deserialize, JsonSchema, Serialize)]
+#[serde(default)]
+pub struct TracingOptions {
+	/// OTLP/gRPC endpoint of the collector spans are exported to.
+	pub otlp_endpoint: String,
+}
+
+impl Default for TracingOptions {
+	fn default() -> Self {
+		Self {
+			otlp_endpoint: "http://127.0.0.1:4317".to_string(),
+		}
+	}
+}
+
+/// Export spans to the configured collector in the background.
+#[cfg(feature = "telemetry")]
+pub fn init(options: &TracingOptions) -> Result<(), Box<dyn std::error::Error>> {
+	let exporter = opentelemetry_otlp::SpanExporter::builder().with_tonic().with_endpoint(options.otlp_endpoint.clone()).build()?;
+	let provider = TracerProvider::builder()
+		.with_batch_exporter(exporter, runtime::Tokio)
+		.with_resource(Resource::new([
+			KeyValue::new("service.name", built_info::PKG_NAME),
+			KeyValue::new("service.version", built_info::PKG_VERSION),
+		]))
+		.build();
+	let tracer = provider.tracer(built_info::PKG_NAME);
+	global::set_tracer_provider(provider);
+	tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer)).try_init()?;
+	Ok(())
+}
+
+/// Spans can't be exported in builds without the `telemetry` feature.
+#[cfg(not(feature = "telemetry"))]
+pub fn init(_options: &TracingOptions) -> Result<(), Box<dyn std::error::Error>> {
+	Err(crate::feature_disabled("telemetry").into())
+}
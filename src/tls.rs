@@ -0,0 +1,91 @@
+//! The TLS layer of the listeners and outgoing connections, built on rustls with the ring provider and the Mozilla root certificates
+//! compiled in, so it needs neither OpenSSL nor the system's certificate store, e.g. in a static musl binary on an appliance.
+
+use std::{
+	fs::File,
+	io::BufReader,
+	path::{Path, PathBuf},
+	sync::{Arc, OnceLock},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio_rustls::{
+	rustls::{
+		crypto::ring,
+		pki_types::{CertificateDer, PrivateKeyDer},
+		server::WebPkiClientVerifier,
+		ClientConfig, RootCertStore, ServerConfig,
+	},
+	TlsAcceptor, TlsConnector,
+};
+
+#[derive(Clone, Deserialize, JsonSchema, Serialize)]
+pub struct TlsOptions {
+	/// TLS certificate used to serve HTTPS, followed by its intermediates, in PEM format.
+	pub certificate_chain_file: PathBuf,
+	/// Private key used for TLS when serving HTTPS, in PEM format.
+	pub key_file: PathBuf,
+	/// CA certificate used to sign client certificates when doing mutual TLS. Clients without a certificate signed by it are refused.
+	#[serde(default)]
+	pub client_certificate_ca_file: Option<PathBuf>,
+}
+
+/// Make ring the process-wide provider for dependencies that create their own rustls configurations, like the listener and SMTP, since
+/// rustls can't choose one by itself when several are compiled in.
+pub fn install_default_provider() {
+	// This only fails if a provider was already installed, which is just as good.
+	let _ = ring::default_provider().install_default();
+}
+
+/// Connector for HTTPS and other TLS connections, verifying servers against the Mozilla root certificates.
+pub fn connector() -> &'static TlsConnector {
+	static CONNECTOR: OnceLock<TlsConnector> = OnceLock::new();
+	CONNECTOR.get_or_init(|| {
+		let mut roots = RootCertStore::empty();
+		roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+		let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+			.with_safe_default_protocol_versions()
+			.expect("the ring provider supports the default protocol versions")
+			.with_root_certificates(roots)
+			.with_no_client_auth();
+		TlsConnector::from(Arc::new(config))
+	})
+}
+
+/// Acceptor for the listeners, serving the configured certificate and requiring client certificates signed by the configured CA, if any.
+/// HTTP/2 is offered with ALPN if `http2` is set.
+pub fn acceptor(options: &TlsOptions, http2: bool) -> Result<TlsAcceptor, Box<dyn std::error::Error>> {
+	let provider = Arc::new(ring::default_provider());
+	let builder = ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions()?;
+	let builder = match &options.client_certificate_ca_file {
+		Some(ca_file) => {
+			let mut roots = RootCertStore::empty();
+			for certificate in certificates(ca_file)? {
+				roots.add(certificate)?;
+			}
+			builder.with_client_cert_verifier(WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?)
+		},
+		None => builder.with_no_client_auth(),
+	};
+	let mut config = builder.with_single_cert(certificates(&options.certificate_chain_file)?, private_key(&options.key_file)?)?;
+	config.alpn_protocols = if http2 {
+		vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+	} else {
+		vec![b"http/1.1".to_vec()]
+	};
+	Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// The certificates in the PEM file at `path`.
+fn certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+	File::open(path).and_then(|file| rustls_pemfile::certs(&mut BufReader::new(file)).collect()).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// The first private key in the PEM file at `path`.
+fn private_key(path: &Path) -> Result<PrivateKeyDer<'static>, String> {
+	File::open(path)
+		.and_then(|file| rustls_pemfile::private_key(&mut BufReader::new(file)))
+		.map_err(|e| format!("{}: {e}", path.display()))?
+		.ok_or_else(|| format!("{}: no private key", path.display()))
+}
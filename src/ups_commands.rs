@@ -0,0 +1,86 @@
+//! Triggering actions on a UPS, like a self-test, from automation. apcupsd's NIS server only reports status, so each action is a command
+//! run on the exporter's host (e.g. `ssh` to the UPS's host to run a script around `apctest`), and nothing runs unless it's configured.
+
+use std::{
+	collections::HashMap,
+	process::Stdio,
+	time::{Duration, TryFromFloatSecsError},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::{process::Command, time::timeout};
+
+#[derive(Clone, Deserialize, JsonSchema, Serialize)]
+#[serde(default)]
+pub struct UpsCommandOptions {
+	/// Password required with HTTP basic authentication, separate from `authorization` so whatever scrapes metrics can't trigger actions.
+	#[serde(serialize_with = "crate::serialize_redacted")]
+	pub password: String,
+	/// Slugs of the hosts actions may be triggered on.
+	pub hosts: Vec<String>,
+	/// Program and arguments to run for each action, e.g. `self_test`. `{slug}`, `{address}`, and `{port}` in the arguments are replaced
+	/// with those of the host.
+	pub actions: HashMap<String, Vec<String>>,
+	/// Seconds an action may run before it's killed.
+	pub timeout_seconds: f64,
+}
+
+impl Default for UpsCommandOptions {
+	fn default() -> Self {
+		Self {
+			password: String::new(),
+			hosts: Vec::new(),
+			actions: HashMap::new(),
+			timeout_seconds: 60.,
+		}
+	}
+}
+
+#[derive(Error, Debug)]
+pub enum UpsCommandError {
+	#[error("unknown action \"{0}\"")]
+	UnknownAction(String),
+	#[error("actions aren't allowed on host \"{0}\"")]
+	HostNotAllowed(String),
+	#[error("action \"{0}\" has no program configured")]
+	EmptyCommand(String),
+	#[error("failed to run action: {0}")]
+	Io(#[from] std::io::Error),
+	#[error("action timed out")]
+	Timeout,
+	#[error("action exited with {status}\n{output}")]
+	Failed { status: std::process::ExitStatus, output: String },
+}
+
+impl UpsCommandOptions {
+	/// How long an action may run, or why `timeout_seconds` isn't a valid duration (e.g. negative or NaN).
+	pub fn timeout(&self) -> Result<Duration, TryFromFloatSecsError> {
+		Duration::try_from_secs_f64(self.timeout_seconds)
+	}
+
+	/// Run `action` on the host with `slug` whose NIS server is at `address:port`, returning its output.
+	pub async fn run(&self, action: &str, slug: &str, address: &str, port: u16) -> Result<String, UpsCommandError> {
+		if !self.hosts.iter().any(|host| host == slug) {
+			return Err(UpsCommandError::HostNotAllowed(slug.to_string()));
+		}
+		let args = self.actions.get(action).ok_or_else(|| UpsCommandError::UnknownAction(action.to_string()))?;
+		let mut args = args.iter().map(|arg| arg.replace("{slug}", slug).replace("{address}", address).replace("{port}", &port.to_string()));
+		let program = args.next().ok_or_else(|| UpsCommandError::EmptyCommand(action.to_string()))?;
+		let mut command = Command::new(program);
+		command.args(args).stdin(Stdio::null()).kill_on_drop(true);
+		// Checked when the configuration is loaded.
+		let limit = self.timeout().unwrap_or_default();
+		let output = timeout(limit, command.output()).await.map_err(|_| UpsCommandError::Timeout)??;
+		let text = String::from_utf8_lossy(&output.stdout).into_owned() + &String::from_utf8_lossy(&output.stderr);
+		if output.status.success() {
+			Ok(text)
+		} else {
+			Err(UpsCommandError::Failed {
+				status: output.status,
+				output: text,
+			})
+		}
+	}
+}
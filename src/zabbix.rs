@@ -0,0 +1,120 @@
+//! Pushing to Zabbix with the sender (trapper) protocol, for environments that monitor with both Zabbix and Prometheus. Hosts are
+//! discovered with a low-level discovery rule, and their values are sent as items of its prototypes.
+
+use std::{
+	collections::HashMap,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt},
+	net::TcpStream,
+	time::timeout,
+};
+
+use crate::SharedExporter;
+
+/// Header of every message of the Zabbix protocol, followed by the length of the data.
+const HEADER: &[u8; 5] = b"ZBXD\x01";
+
+#[derive(Clone, Deserialize, JsonSchema, Serialize)]
+#[serde(default)]
+pub struct ZabbixOptions {
+	/// Address of the Zabbix server or proxy's trapper.
+	pub server: String,
+	/// Name of the host in Zabbix the discovery rule and items belong to.
+	pub host: String,
+	/// How often every host is fetched and sent.
+	pub interval_seconds: f64,
+	/// apcupsd keys sent as numbers, with units removed.
+	pub keys: Vec<String>,
+}
+
+impl Default for ZabbixOptions {
+	fn default() -> Self {
+		Self {
+			server: "127.0.0.1:10051".to_string(),
+			host: "apcupsd-exporter".to_string(),
+			interval_seconds: 60.,
+			keys: ["BCHARGE", "TIMELEFT", "LOADPCT", "LINEV", "OUTPUTV", "BATTV", "ITEMP"].map(str::to_string).to_vec(),
+		}
+	}
+}
+
+/// Send discovery data and the values of every host every interval in the background.
+pub fn spawn(options: ZabbixOptions, exporter: SharedExporter) -> Result<(), Box<dyn std::error::Error>> {
+	let interval = Duration::try_from_secs_f64(options.interval_seconds)?;
+	tokio::spawn(async move {
+		loop {
+			let items = items(&options, &exporter).await;
+			match send(&options.server, &items).await {
+				// Items without a matching item in Zabbix, e.g. before discovery created them, are counted as failed.
+				Ok(info) if !info.contains("failed: 0") => eprintln!("Zabbix server {} didn't process every item: {info}", options.server),
+				Ok(_) => {},
+				Err(e) => crate::syslog::error(format!("Failed to send to Zabbix server {}: {e}", options.server)),
+			}
+			tokio::time::sleep(interval).await;
+		}
+	});
+	Ok(())
+}
+
+/// The discovery item `apcupsd.discovery`, with `{#UPS}` (the slug) and `{#NIS}` (`address:port`) for every host, followed by
+/// `apcupsd.value[<slug>,<KEY>]`, `apcupsd.status[<slug>]`, and `apcupsd.up[<slug>]` for each host.
+async fn items(options: &ZabbixOptions, exporter: &SharedExporter) -> Vec<Value> {
+	let exporter = exporter.current();
+	let clock = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+	let item = |key: String, value: String| json!({ "host": options.host, "key": key, "value": value, "clock": clock });
+	let discovery: Vec<_> = exporter
+		.hosts
+		.iter()
+		.map(|host| json!({ "{#UPS}": host.slug, "{#NIS}": format!("{}:{}", host.options.address, host.options.port) }))
+		.collect();
+	let mut items = vec![item("apcupsd.discovery".to_string(), Value::from(discovery).to_string())];
+	for host in &exporter.hosts {
+		let apcupsd_data = host.access.refresh().await.map(|snapshot| snapshot.data).unwrap_or_default();
+		items.push(item(format!("apcupsd.up[{}]", host.slug), u8::from(!apcupsd_data.is_empty()).to_string()));
+		if let Some(status) = apcupsd_data.get("STATUS") {
+			items.push(item(format!("apcupsd.status[{}]", host.slug), status.trim().to_string()));
+		}
+		items.extend(numeric_values(&options.keys, &apcupsd_data).map(|(key, value)| item(format!("apcupsd.value[{},{key}]", host.slug), value)));
+	}
+	items
+}
+
+/// The number each of `keys` is reported with, skipping keys that weren't reported or aren't numbers.
+fn numeric_values<'a>(keys: &'a [String], apcupsd_data: &'a HashMap<String, String>) -> impl Iterator<Item = (&'a str, String)> {
+	keys.iter().filter_map(|key| {
+		let value = apcupsd_data.get(key)?.split_whitespace().next()?.parse::<f64>().ok()?;
+		Some((key.as_str(), value.to_string()))
+	})
+}
+
+/// Send items with the sender protocol, returning the `info` the server answers with.
+async fn send(server: &str, items: &[Value]) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+	let request = json!({ "request": "sender data", "data": items }).to_string();
+	let exchange = async {
+		let mut stream = TcpStream::connect(server).await?;
+		stream.write_all(HEADER).await?;
+		stream.write_u64_le(request.len() as u64).await?;
+		stream.write_all(request.as_bytes()).await?;
+		let mut header = [0; 5];
+		stream.read_exact(&mut header).await?;
+		if &header != HEADER {
+			return Err("invalid response header".into());
+		}
+		let length = stream.read_u64_le().await?;
+		let mut response = Vec::new();
+		stream.take(length).read_to_end(&mut response).await?;
+		Ok::<_, Box<dyn std::error::Error + Send + Sync>>(response)
+	};
+	let response: Value = serde_json::from_slice(&timeout(Duration::from_secs(10), exchange).await??)?;
+	let info = response["info"].as_str().unwrap_or_default().to_string();
+	if response["response"] != "success" {
+		return Err(format!("server didn't accept the data: {info}").into());
+	}
+	Ok(info)
+}